@@ -0,0 +1,167 @@
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+use crate::annotation::{self, Annotation};
+use crate::artistic;
+use crate::channels::{self, Channel, ChannelMixer, ChannelOperation};
+use crate::convolution::{convolve, EdgeMode, Kernel};
+use crate::denoise::{denoise, DenoiseMethod};
+use crate::operation::{ParamValue, Registry};
+use crate::paint::PaintTool;
+use crate::params::{apply_params, Params};
+use crate::redaction::{redact, RedactionStyle, Region};
+use crate::script::{self, FileAccess};
+use crate::text::{self, FontEntry, TextBox};
+use crate::transparency::{self, TransparencyOperation};
+
+// An edit that was applied to the image, with the values it was applied with
+#[derive(PartialEq, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum Adjustment {
+    Blur { sigma: f32 },
+    Brighten { value: i32 },
+    Contrast { value: f32 },
+    // Filters of the registry now, kept so older projects and presets still replay
+    Flip { horizontal: bool, vertical: bool },
+    Grayscale,
+    Invert,
+    Rotate { degrees: u32 },
+    Convolution { kernel: Kernel, edge_mode: EdgeMode },
+    Denoise { method: DenoiseMethod, radius: u32, strength: f32 },
+    Sepia { intensity: f32 },
+    Posterize { levels: u32 },
+    Threshold { level: u8 },
+    Pixelate { block_size: u32 },
+    Vignette { strength: f32, radius: f32, feather: f32 },
+    FilmGrain { amount: f32, grain_size: u32 },
+    // Several slider effects applied as one edit, older projects have them one by one
+    Effects { params: Params },
+    Channels { operation: ChannelOperation, channel: Channel, swap_channel: Channel, mixer: ChannelMixer },
+    Transparency { operation: TransparencyOperation, opacity: f32, background: [u8; 3], key_color: [u8; 3], tolerance: f32 },
+    Paint { tool: PaintTool },
+    Text { text_boxes: Vec<TextBox> },
+    Annotate { annotations: Vec<Annotation> },
+    Redact { style: RedactionStyle, block_size: u32, regions: Vec<Region> },
+    FlattenLayers,
+    // A filter of the operation registry, with its values in the order of its parameters
+    Operation { name: String, values: Vec<ParamValue> },
+    // A saved preset replayed as a single edit
+    Preset { name: String, adjustments: Vec<Adjustment> },
+    // A script run on the image, kept with its source so it replays the same way
+    Script { name: String, source: String },
+}
+
+impl Adjustment {
+    pub fn name(&self) -> &str {
+        match self {
+            Adjustment::Blur { .. } => "Blur",
+            Adjustment::Brighten { .. } => "Brighten",
+            Adjustment::Contrast { .. } => "Contrast",
+            Adjustment::Flip { .. } => "Flip",
+            Adjustment::Grayscale => "Grayscale",
+            Adjustment::Invert => "Invert",
+            Adjustment::Rotate { .. } => "Rotate",
+            Adjustment::Convolution { .. } => "Convolution",
+            Adjustment::Denoise { .. } => "Denoise",
+            Adjustment::Sepia { .. } => "Sepia",
+            Adjustment::Posterize { .. } => "Posterize",
+            Adjustment::Threshold { .. } => "Threshold",
+            Adjustment::Pixelate { .. } => "Pixelate",
+            Adjustment::Vignette { .. } => "Vignette",
+            Adjustment::FilmGrain { .. } => "Film Grain",
+            Adjustment::Effects { .. } => "Effects",
+            Adjustment::Channels { .. } => "Channels",
+            Adjustment::Transparency { .. } => "Transparency",
+            Adjustment::Paint { .. } => "Paint",
+            Adjustment::Text { .. } => "Text",
+            Adjustment::Annotate { .. } => "Shapes",
+            Adjustment::Redact { .. } => "Redact",
+            Adjustment::FlattenLayers => "Flatten Layers",
+            Adjustment::Operation { name, .. } => name,
+            Adjustment::Preset { .. } => "Preset",
+            Adjustment::Script { name, .. } => name,
+        }
+    }
+
+    // Replay the adjustment on any image. Fonts are only used by text, the registry by
+    // filters. The edits whose data is not kept in the adjustment (paint strokes, layers)
+    // and filters missing from the registry change nothing
+    pub fn apply(&self, img: &DynamicImage, fonts: &[FontEntry], operations: &Registry) -> DynamicImage {
+        match self {
+            Adjustment::Blur { sigma } => img.blur(*sigma),
+            Adjustment::Brighten { value } => img.brighten(*value),
+            Adjustment::Contrast { value } => img.adjust_contrast(*value),
+            Adjustment::Flip { horizontal, vertical } => {
+                let mut new_img = img.clone();
+                if *horizontal {
+                    new_img = new_img.fliph();
+                }
+                if *vertical {
+                    new_img = new_img.flipv();
+                }
+                new_img
+            },
+            Adjustment::Grayscale => img.grayscale(),
+            Adjustment::Invert => {
+                let mut new_img = img.clone();
+                new_img.invert();
+                new_img
+            },
+            Adjustment::Rotate { degrees } => match degrees {
+                90 => img.rotate90(),
+                180 => img.rotate180(),
+                270 => img.rotate270(),
+                _ => img.clone(),
+            },
+            Adjustment::Convolution { kernel, edge_mode } => convolve(img, kernel, *edge_mode),
+            Adjustment::Denoise { method, radius, strength } => denoise(img, *method, *radius, *strength),
+            Adjustment::Sepia { intensity } => artistic::sepia(img, *intensity),
+            Adjustment::Posterize { levels } if *levels >= 2 => artistic::posterize(img, *levels),
+            Adjustment::Threshold { level } => artistic::threshold(img, *level),
+            Adjustment::Pixelate { block_size } if *block_size >= 2 => artistic::pixelate(img, *block_size),
+            Adjustment::Posterize { .. } | Adjustment::Pixelate { .. } => img.clone(),
+            Adjustment::Vignette { strength, radius, feather } => artistic::vignette(img, *strength, *radius, *feather),
+            Adjustment::FilmGrain { amount, grain_size } => artistic::film_grain(img, *amount, *grain_size),
+            Adjustment::Effects { params } => apply_params(img, params),
+            Adjustment::Channels { operation, channel, swap_channel, mixer } => match operation {
+                ChannelOperation::Isolate => channels::isolate_channel(img, *channel),
+                ChannelOperation::Grayscale => channels::channel_to_grayscale(img, *channel),
+                ChannelOperation::Swap => channels::swap_channels(img, *channel, *swap_channel),
+                ChannelOperation::Mixer => channels::mix_channels(img, mixer),
+            },
+            Adjustment::Transparency { operation, opacity, background, key_color, tolerance } => match operation {
+                TransparencyOperation::Opacity => transparency::set_opacity(img, *opacity),
+                TransparencyOperation::RemoveAlpha => transparency::remove_alpha(img, *background),
+                TransparencyOperation::ChromaKey => transparency::chroma_key(img, *key_color, *tolerance),
+            },
+            Adjustment::Text { text_boxes } => {
+                let mut rgba = img.to_rgba8();
+                for text_box in text_boxes {
+                    if let Some(data) = text::font_data(fonts, &text_box.font) {
+                        text::render_text_box(&mut rgba, text_box, &data);
+                    }
+                }
+                DynamicImage::ImageRgba8(rgba)
+            },
+            Adjustment::Annotate { annotations } => {
+                let mut rgba = img.to_rgba8();
+                for annotation in annotations {
+                    annotation::render_annotation(&mut rgba, annotation);
+                }
+                DynamicImage::ImageRgba8(rgba)
+            },
+            Adjustment::Redact { style, block_size, regions } => redact(img, regions, *style, *block_size),
+            Adjustment::Operation { name, values } => operations.apply(img, name, values).unwrap_or_else(|_| img.clone()),
+            Adjustment::Preset { adjustments, .. } => apply_all(img, adjustments, fonts, operations),
+            // What the script prints is only shown in the console
+            Adjustment::Script { source, .. } => script::run(source, Some(img.clone()), fonts, operations, FileAccess::Denied, &mut Vec::new())
+                .ok()
+                .flatten()
+                .unwrap_or_else(|| img.clone()),
+            Adjustment::Paint { .. } | Adjustment::FlattenLayers => img.clone(),
+        }
+    }
+}
+
+pub fn apply_all(img: &DynamicImage, adjustments: &[Adjustment], fonts: &[FontEntry], operations: &Registry) -> DynamicImage {
+    adjustments.iter().fold(img.clone(), |img, adjustment| adjustment.apply(&img, fonts, operations))
+}
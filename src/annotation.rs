@@ -0,0 +1,365 @@
+use std::f32::consts::PI;
+use image::{Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+use crate::text::{self, TextBox};
+
+// Segments used to draw ellipses and markers
+const CIRCLE_SEGMENTS: usize = 72;
+// The highlighter is wider and translucent so the content below stays readable
+const HIGHLIGHTER_WIDTH_FACTOR: f32 = 4.0;
+const HIGHLIGHTER_OPACITY: f32 = 0.4;
+pub const MAX_STROKE_WIDTH: f32 = 40.0;
+
+#[derive(PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum ShapeKind {
+    Rectangle,
+    Ellipse,
+    Line,
+    Arrow,
+    Marker,
+    Highlighter,
+}
+
+impl ShapeKind {
+    pub const ALL: [ShapeKind; 6] = [ShapeKind::Rectangle, ShapeKind::Ellipse, ShapeKind::Line, ShapeKind::Arrow, ShapeKind::Marker, ShapeKind::Highlighter];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ShapeKind::Rectangle => "Rectangle",
+            ShapeKind::Ellipse => "Ellipse",
+            ShapeKind::Line => "Line",
+            ShapeKind::Arrow => "Arrow",
+            ShapeKind::Marker => "Numbered Marker",
+            ShapeKind::Highlighter => "Highlighter",
+        }
+    }
+
+    // Markers and highlighters start from a single point, the rest need both ends
+    pub fn min_points(&self) -> usize {
+        match self {
+            ShapeKind::Marker | ShapeKind::Highlighter => 1,
+            _ => 2,
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum DashStyle {
+    Solid,
+    Dashed,
+    Dotted,
+}
+
+impl DashStyle {
+    pub const ALL: [DashStyle; 3] = [DashStyle::Solid, DashStyle::Dashed, DashStyle::Dotted];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DashStyle::Solid => "Solid",
+            DashStyle::Dashed => "Dashed",
+            DashStyle::Dotted => "Dotted",
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub struct AnnotationStyle {
+    pub stroke_width: f32,
+    pub color: [u8; 3],
+    pub fill: bool,
+    pub fill_color: [u8; 4],
+    pub dash: DashStyle,
+}
+
+impl Default for AnnotationStyle {
+    fn default() -> Self {
+        Self {
+            stroke_width: 4.0,
+            color: [230, 30, 30],
+            fill: false,
+            fill_color: [255, 220, 0, 120],
+            dash: DashStyle::Solid,
+        }
+    }
+}
+
+// Shapes keep their points in image coordinates: two corners for rectangles and
+// ellipses, both ends for lines and arrows, the center for markers and every
+// pointer position for the highlighter
+#[derive(PartialEq, Clone, Serialize, Deserialize)]
+pub struct Annotation {
+    pub kind: ShapeKind,
+    pub points: Vec<(f32, f32)>,
+    pub number: u32,
+    pub style: AnnotationStyle,
+}
+
+impl Annotation {
+    pub fn new(kind: ShapeKind, point: (f32, f32), style: AnnotationStyle, number: u32) -> Self {
+        let points = match kind {
+            ShapeKind::Marker | ShapeKind::Highlighter => vec![point],
+            _ => vec![point, point],
+        };
+        Self { kind, points, number, style }
+    }
+
+    // Grow the shape while it is being drawn
+    pub fn drag_to(&mut self, point: (f32, f32)) {
+        match self.kind {
+            ShapeKind::Marker => {},
+            ShapeKind::Highlighter => {
+                let last = *self.points.last().unwrap();
+                let spacing = (self.line_width() / 4.0).max(1.0);
+                if distance(last, point) >= spacing {
+                    self.points.push(point);
+                }
+            },
+            _ => self.points[1] = point,
+        }
+    }
+
+    pub fn translate(&mut self, dx: f32, dy: f32) {
+        for point in self.points.iter_mut() {
+            *point = (point.0 + dx, point.1 + dy);
+        }
+    }
+
+    // Shapes too small to see are dropped when the drag ends
+    pub fn is_degenerate(&self) -> bool {
+        match self.kind {
+            ShapeKind::Marker => false,
+            ShapeKind::Highlighter => self.points.len() < 2,
+            _ => distance(self.points[0], self.points[1]) < 2.0,
+        }
+    }
+
+    fn line_width(&self) -> f32 {
+        match self.kind {
+            ShapeKind::Highlighter => self.style.stroke_width * HIGHLIGHTER_WIDTH_FACTOR,
+            _ => self.style.stroke_width,
+        }
+    }
+
+    pub fn marker_radius(&self) -> f32 {
+        self.style.stroke_width * 2.0 + 12.0
+    }
+
+    // Outline as a polyline, and whether it is closed
+    fn outline(&self) -> (Vec<(f32, f32)>, bool) {
+        match self.kind {
+            ShapeKind::Rectangle => {
+                let (a, b) = (self.points[0], self.points[1]);
+                (vec![a, (b.0, a.1), b, (a.0, b.1)], true)
+            },
+            ShapeKind::Ellipse => {
+                let (a, b) = (self.points[0], self.points[1]);
+                let center = ((a.0 + b.0) / 2.0, (a.1 + b.1) / 2.0);
+                (circle_points(center, ((a.0 - b.0) / 2.0).abs(), ((a.1 - b.1) / 2.0).abs()), true)
+            },
+            ShapeKind::Marker => {
+                let radius = self.marker_radius();
+                (circle_points(self.points[0], radius, radius), true)
+            },
+            ShapeKind::Arrow => {
+                // The shaft stops inside the head so it doesn't poke through the tip
+                let (a, b) = (self.points[0], self.points[1]);
+                let length = distance(a, b).max(f32::EPSILON);
+                let shorten = (self.arrow_head_length() * 0.5).min(length);
+                let end = (b.0 - (b.0 - a.0) / length * shorten, b.1 - (b.1 - a.1) / length * shorten);
+                (vec![a, end], false)
+            },
+            ShapeKind::Line | ShapeKind::Highlighter => (self.points.clone(), false),
+        }
+    }
+
+    fn arrow_head_length(&self) -> f32 {
+        (self.style.stroke_width * 4.0).max(12.0)
+    }
+
+    fn arrow_head(&self) -> Option<Vec<(f32, f32)>> {
+        if self.kind != ShapeKind::Arrow {
+            return None;
+        }
+        let (a, b) = (self.points[0], self.points[1]);
+        let length = distance(a, b);
+        if length <= 0.0 {
+            return None;
+        }
+        let (ux, uy) = ((b.0 - a.0) / length, (b.1 - a.1) / length);
+        let head = self.arrow_head_length();
+        let half_width = head * 0.45;
+        let base = (b.0 - ux * head, b.1 - uy * head);
+        Some(vec![
+            b,
+            (base.0 - uy * half_width, base.1 + ux * half_width),
+            (base.0 + uy * half_width, base.1 - ux * half_width),
+        ])
+    }
+
+    // Area touched by the shape, in image coordinates
+    pub fn bounds(&self) -> ((f32, f32), (f32, f32)) {
+        let (mut points, _) = self.outline();
+        if let Some(head) = self.arrow_head() {
+            points.extend(head);
+        }
+        let margin = self.line_width() / 2.0 + 1.0;
+        let min_x = points.iter().map(|p| p.0).fold(f32::MAX, f32::min) - margin;
+        let min_y = points.iter().map(|p| p.1).fold(f32::MAX, f32::min) - margin;
+        let max_x = points.iter().map(|p| p.0).fold(f32::MIN, f32::max) + margin;
+        let max_y = points.iter().map(|p| p.1).fold(f32::MIN, f32::max) + margin;
+        ((min_x, min_y), (max_x, max_y))
+    }
+
+    // Closed shapes can be grabbed anywhere inside, open ones only near the line
+    pub fn contains(&self, point: (f32, f32), tolerance: f32) -> bool {
+        let (outline, closed) = self.outline();
+        let reach = self.line_width() / 2.0 + tolerance;
+        if closed && point_in_polygon(&outline, point) {
+            return true;
+        }
+        if let Some(head) = self.arrow_head() {
+            if point_in_polygon(&head, point) {
+                return true;
+            }
+        }
+        nearest_on_polyline(&outline, closed, point, reach).0 <= reach
+    }
+}
+
+fn distance(a: (f32, f32), b: (f32, f32)) -> f32 {
+    ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt()
+}
+
+fn circle_points(center: (f32, f32), rx: f32, ry: f32) -> Vec<(f32, f32)> {
+    (0..CIRCLE_SEGMENTS)
+        .map(|i| {
+            let angle = i as f32 / CIRCLE_SEGMENTS as f32 * 2.0 * PI;
+            (center.0 + rx * angle.cos(), center.1 + ry * angle.sin())
+        })
+        .collect()
+}
+
+// Distance from the point to the polyline, and how far along the polyline the
+// closest point is. Segments further than reach are skipped
+fn nearest_on_polyline(points: &[(f32, f32)], closed: bool, p: (f32, f32), reach: f32) -> (f32, f32) {
+    if points.len() == 1 {
+        return (distance(points[0], p), 0.0);
+    }
+    let segments = if closed { points.len() } else { points.len() - 1 };
+    let mut best = (f32::MAX, 0.0);
+    let mut travelled = 0.0;
+    for i in 0..segments {
+        let (a, b) = (points[i], points[(i + 1) % points.len()]);
+        let length = distance(a, b);
+        let outside = p.0 < a.0.min(b.0) - reach
+            || p.0 > a.0.max(b.0) + reach
+            || p.1 < a.1.min(b.1) - reach
+            || p.1 > a.1.max(b.1) + reach;
+        if !outside {
+            let t = if length > 0.0 {
+                (((p.0 - a.0) * (b.0 - a.0) + (p.1 - a.1) * (b.1 - a.1)) / (length * length)).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            let d = distance(p, (a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t));
+            if d < best.0 {
+                best = (d, travelled + length * t);
+            }
+        }
+        travelled += length;
+    }
+    best
+}
+
+// Even-odd rule, same as the lasso selection
+fn point_in_polygon(points: &[(f32, f32)], p: (f32, f32)) -> bool {
+    let mut inside = false;
+    for i in 0..points.len() {
+        let (a, b) = (points[i], points[(i + 1) % points.len()]);
+        if (a.1 <= p.1) != (b.1 <= p.1) && p.0 < a.0 + (p.1 - a.1) / (b.1 - a.1) * (b.0 - a.0) {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+// Antialiased coverage of a line at the given distance, broken into dashes or dots
+fn stroke_coverage(dash: DashStyle, half_width: f32, distance: f32, along: f32) -> f32 {
+    let line = |d: f32| (half_width - d + 0.5).clamp(0.0, 1.0);
+    let width = (half_width * 2.0).max(1.0);
+    match dash {
+        DashStyle::Solid => line(distance),
+        DashStyle::Dashed => if along.rem_euclid(width * 5.0) < width * 3.0 { line(distance) } else { 0.0 },
+        DashStyle::Dotted => {
+            let period = width * 2.0;
+            let phase = along.rem_euclid(period);
+            let offset = phase.min(period - phase);
+            line((offset * offset + distance * distance).sqrt())
+        },
+    }
+}
+
+// Antialiased coverage of a filled polygon
+fn fill_coverage(points: &[(f32, f32)], p: (f32, f32)) -> f32 {
+    let d = nearest_on_polyline(points, true, p, 1.0).0;
+    if point_in_polygon(points, p) { (d + 0.5).clamp(0.0, 1.0) } else { (0.5 - d).clamp(0.0, 1.0) }
+}
+
+pub fn render_annotation(img: &mut RgbaImage, annotation: &Annotation) {
+    let (outline, closed) = annotation.outline();
+    let head = annotation.arrow_head();
+    let half_width = annotation.line_width() / 2.0;
+    let style = annotation.style;
+    let opacity = if annotation.kind == ShapeKind::Highlighter { HIGHLIGHTER_OPACITY } else { 1.0 };
+    let color = Rgba([style.color[0], style.color[1], style.color[2], (opacity * 255.0).round() as u8]);
+    let fill = match annotation.kind {
+        ShapeKind::Marker => Some(Rgba([style.color[0], style.color[1], style.color[2], 255])),
+        _ if closed && style.fill => Some(Rgba(style.fill_color)),
+        _ => None,
+    };
+
+    let ((min_x, min_y), (max_x, max_y)) = annotation.bounds();
+    let x0 = min_x.floor().max(0.0) as u32;
+    let y0 = min_y.floor().max(0.0) as u32;
+    let x1 = (max_x.ceil().max(0.0) as u32).min(img.width());
+    let y1 = (max_y.ceil().max(0.0) as u32).min(img.height());
+    for y in y0..y1 {
+        for x in x0..x1 {
+            let p = (x as f32 + 0.5, y as f32 + 0.5);
+            let mut px = *img.get_pixel(x, y);
+            if let Some(fill) = fill {
+                px = text::over_rgba(px, fill, fill_coverage(&outline, p));
+            }
+            if annotation.kind != ShapeKind::Marker {
+                let (d, along) = nearest_on_polyline(&outline, closed, p, half_width + 1.0);
+                let mut coverage = stroke_coverage(style.dash, half_width, d, along);
+                if let Some(head) = &head {
+                    coverage = coverage.max(fill_coverage(head, p));
+                }
+                px = text::over_rgba(px, color, coverage);
+            }
+            img.put_pixel(x, y, px);
+        }
+    }
+
+    if annotation.kind == ShapeKind::Marker {
+        render_marker_number(img, annotation);
+    }
+}
+
+// White number centered on the marker
+fn render_marker_number(img: &mut RgbaImage, annotation: &Annotation) {
+    let font_data = match text::default_font_data() {
+        Some(data) => data,
+        None => return,
+    };
+    let mut text_box = TextBox::new((0.0, 0.0));
+    text_box.text = annotation.number.to_string();
+    text_box.size = annotation.marker_radius() * 1.2;
+    text_box.color = [255, 255, 255];
+    text_box.outline_width = 0.0;
+    let (w, h) = text_box.measure(&font_data);
+    let center = annotation.points[0];
+    text_box.position = (center.0 - w / 2.0, center.1 - h / 2.0);
+    text::render_text_box(img, &text_box, &font_data);
+}
@@ -0,0 +1,183 @@
+use image::{DynamicImage, GrayImage, Luma, Rgba};
+use image::imageops::{self, FilterType};
+
+// Fixed seed so the grain doesn't change between previews
+const GRAIN_SEED: u64 = 0x9E37_79B9_7F4A_7C15;
+
+fn luma(px: &Rgba<u8>) -> f32 {
+    0.299 * px[0] as f32 + 0.587 * px[1] as f32 + 0.114 * px[2] as f32
+}
+
+fn to_u8(value: f32) -> u8 {
+    value.round().clamp(0.0, 255.0) as u8
+}
+
+// Amount goes from 0 (original colors) to 100 (full sepia)
+pub fn sepia(img: &DynamicImage, amount: f32) -> DynamicImage {
+    let amount = (amount / 100.0).clamp(0.0, 1.0);
+    let mut out = img.to_rgba8();
+    for px in out.pixels_mut() {
+        let (r, g, b) = (px[0] as f32, px[1] as f32, px[2] as f32);
+        let toned = [
+            0.393 * r + 0.769 * g + 0.189 * b,
+            0.349 * r + 0.686 * g + 0.168 * b,
+            0.272 * r + 0.534 * g + 0.131 * b,
+        ];
+        for c in 0..3 {
+            px[c] = to_u8(px[c] as f32 + (toned[c] - px[c] as f32) * amount);
+        }
+    }
+    DynamicImage::ImageRgba8(out)
+}
+
+pub fn posterize(img: &DynamicImage, levels: u32) -> DynamicImage {
+    let step = 255.0 / (levels.max(2) - 1) as f32;
+    let mut out = img.to_rgba8();
+    for px in out.pixels_mut() {
+        for c in 0..3 {
+            px[c] = to_u8((px[c] as f32 / step).round() * step);
+        }
+    }
+    DynamicImage::ImageRgba8(out)
+}
+
+// Pixels with a luma above the level become white, the others black
+pub fn threshold(img: &DynamicImage, level: u8) -> DynamicImage {
+    let mut out = img.to_rgba8();
+    for px in out.pixels_mut() {
+        let value = if luma(px) > level as f32 { 255 } else { 0 };
+        px[0] = value;
+        px[1] = value;
+        px[2] = value;
+    }
+    DynamicImage::ImageRgba8(out)
+}
+
+// Level that best separates the luma histogram in two classes (Otsu's method)
+pub fn otsu_threshold(img: &DynamicImage) -> u8 {
+    let mut histogram = [0u64; 256];
+    for px in img.to_rgba8().pixels() {
+        histogram[to_u8(luma(px)) as usize] += 1;
+    }
+    let total: u64 = histogram.iter().sum();
+    let sum_all: f64 = histogram.iter().enumerate().map(|(i, n)| i as f64 * *n as f64).sum();
+
+    let (mut best_level, mut best_variance) = (0u8, 0.0f64);
+    let (mut weight_bg, mut sum_bg) = (0u64, 0.0f64);
+    for (level, n) in histogram.iter().enumerate() {
+        weight_bg += n;
+        if weight_bg == 0 {
+            continue;
+        }
+        let weight_fg = total - weight_bg;
+        if weight_fg == 0 {
+            break;
+        }
+        sum_bg += level as f64 * *n as f64;
+        let mean_bg = sum_bg / weight_bg as f64;
+        let mean_fg = (sum_all - sum_bg) / weight_fg as f64;
+        let variance = weight_bg as f64 * weight_fg as f64 * (mean_bg - mean_fg).powi(2);
+        if variance > best_variance {
+            best_variance = variance;
+            best_level = level as u8;
+        }
+    }
+    best_level
+}
+
+// Replace each block of pixels by its average color
+pub fn pixelate(img: &DynamicImage, block_size: u32) -> DynamicImage {
+    let block_size = block_size.max(1);
+    let mut out = img.to_rgba8();
+    let (w, h) = out.dimensions();
+    for by in (0..h).step_by(block_size as usize) {
+        for bx in (0..w).step_by(block_size as usize) {
+            let (bw, bh) = (block_size.min(w - bx), block_size.min(h - by));
+            let mut sum = [0u64; 4];
+            for y in by..by + bh {
+                for x in bx..bx + bw {
+                    let px = out.get_pixel(x, y);
+                    for c in 0..4 {
+                        sum[c] += px[c] as u64;
+                    }
+                }
+            }
+            let count = (bw * bh) as u64;
+            let avg = Rgba([
+                (sum[0] / count) as u8,
+                (sum[1] / count) as u8,
+                (sum[2] / count) as u8,
+                (sum[3] / count) as u8,
+            ]);
+            for y in by..by + bh {
+                for x in bx..bx + bw {
+                    out.put_pixel(x, y, avg);
+                }
+            }
+        }
+    }
+    DynamicImage::ImageRgba8(out)
+}
+
+// Darken the borders. Radius and feather are fractions of the half diagonal,
+// strength goes from 0 (no vignette) to 100 (black borders)
+pub fn vignette(img: &DynamicImage, strength: f32, radius: f32, feather: f32) -> DynamicImage {
+    let strength = (strength / 100.0).clamp(0.0, 1.0);
+    let mut out = img.to_rgba8();
+    let (w, h) = out.dimensions();
+    let (cx, cy) = (w as f32 / 2.0, h as f32 / 2.0);
+    let half_diagonal = (cx * cx + cy * cy).sqrt().max(1.0);
+    let feather = feather.max(0.001);
+
+    for (x, y, px) in out.enumerate_pixels_mut() {
+        let (dx, dy) = (x as f32 + 0.5 - cx, y as f32 + 0.5 - cy);
+        let distance = (dx * dx + dy * dy).sqrt() / half_diagonal;
+        let t = ((distance - radius) / feather).clamp(0.0, 1.0);
+        let smooth = t * t * (3.0 - 2.0 * t);
+        let factor = 1.0 - strength * smooth;
+        for c in 0..3 {
+            px[c] = to_u8(px[c] as f32 * factor);
+        }
+    }
+    DynamicImage::ImageRgba8(out)
+}
+
+// Monochrome noise added to every pixel, bigger grain sizes give softer clumps
+pub fn film_grain(img: &DynamicImage, amount: f32, grain_size: u32) -> DynamicImage {
+    let mut out = img.to_rgba8();
+    let (w, h) = out.dimensions();
+    let grain_size = grain_size.max(1);
+    let (nw, nh) = ((w / grain_size).max(1), (h / grain_size).max(1));
+
+    let mut rng = XorShift(GRAIN_SEED);
+    let noise = GrayImage::from_fn(nw, nh, |_, _| {
+        // Sum of uniforms, close enough to a gaussian for grain
+        let sample: f32 = (0..4).map(|_| rng.next_f32()).sum::<f32>() / 4.0;
+        Luma([to_u8(sample * 255.0)])
+    });
+    let noise = if (nw, nh) == (w, h) {
+        noise
+    } else {
+        imageops::resize(&noise, w, h, FilterType::Triangle)
+    };
+
+    let scale = amount / 100.0 * 2.0;
+    for (x, y, px) in out.enumerate_pixels_mut() {
+        let grain = (noise.get_pixel(x, y)[0] as f32 - 128.0) * scale;
+        for c in 0..3 {
+            px[c] = to_u8(px[c] as f32 + grain);
+        }
+    }
+    DynamicImage::ImageRgba8(out)
+}
+
+struct XorShift(u64);
+
+impl XorShift {
+    fn next_f32(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
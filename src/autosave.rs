@@ -0,0 +1,46 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use directories_next::ProjectDirs;
+use my_image_editor::project::{self, Project, PROJECT_EXTENSION};
+
+pub const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+// Persistent folder for sessions that were not closed cleanly, None when the
+// platform has no app data directory
+pub fn sessions_dir() -> Option<PathBuf> {
+    ProjectDirs::from("", "", "Image Editor").map(|dirs| dirs.data_dir().join("sessions"))
+}
+
+// Every running editor writes its own file, so two windows don't overwrite each other
+pub fn session_path(dir: &Path) -> PathBuf {
+    dir.join(format!("session_{}.{}", std::process::id(), PROJECT_EXTENSION))
+}
+
+// The project is written next to the session and renamed over it, a crash while
+// saving leaves the previous autosave intact
+pub fn save(path: &Path, project: &Project) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let partial = path.with_extension("partial");
+    project::save(&partial, project)?;
+    fs::rename(&partial, path)
+}
+
+// Most recent session left behind by an editor that didn't exit cleanly
+pub fn find_unsaved_session(dir: &Path) -> Option<PathBuf> {
+    let current = session_path(dir);
+    fs::read_dir(dir)
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| *path != current && path.extension().is_some_and(|ext| ext == PROJECT_EXTENSION))
+        .max_by_key(|path| fs::metadata(path).and_then(|meta| meta.modified()).ok())
+}
+
+pub fn discard(path: &Path) {
+    // Nothing to do if it was never written
+    let _ = fs::remove_file(path);
+}
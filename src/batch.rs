@@ -0,0 +1,263 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+use my_image_editor::operation::Registry;
+use my_image_editor::ops;
+use my_image_editor::preset::Preset;
+use my_image_editor::text::FontEntry;
+use my_image_editor::transparency;
+use crate::preferences::ExportFormat;
+
+pub const IMAGE_EXTENSIONS: [&str; 5] = ["png", "jpg", "jpeg", "webp", "bmp"];
+pub const DEFAULT_PATTERN: &str = "{name}_edited.{ext}";
+
+// Where the edits of a batch come from
+#[derive(PartialEq, Clone)]
+pub enum BatchSource {
+    // The edits applied to the open image so far
+    Session,
+    // A preset of the sidebar, by the file it is kept in
+    Preset(PathBuf),
+}
+
+#[derive(PartialEq, Clone)]
+pub struct BatchSettings {
+    pub inputs: Vec<PathBuf>,
+    pub output_dir: Option<PathBuf>,
+    pub pattern: String,
+    pub format: ExportFormat,
+    pub source: BatchSource,
+}
+
+impl Default for BatchSettings {
+    fn default() -> Self {
+        Self {
+            inputs: Vec::new(),
+            output_dir: None,
+            pattern: DEFAULT_PATTERN.to_string(),
+            format: ExportFormat::Png,
+            source: BatchSource::Session,
+        }
+    }
+}
+
+impl BatchSettings {
+    // Files are only added once, whatever way they were picked
+    pub fn add_inputs(&mut self, paths: impl IntoIterator<Item = PathBuf>) {
+        for path in paths {
+            if !self.inputs.contains(&path) {
+                self.inputs.push(path);
+            }
+        }
+    }
+}
+
+// Images directly inside the folder, sorted by name
+pub fn images_in(dir: &Path) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file() && path.extension().is_some_and(|ext| {
+                IMAGE_EXTENSIONS.iter().any(|e| ext.eq_ignore_ascii_case(e))
+            }))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    paths.sort();
+    paths
+}
+
+// {name} is the input file name without extension, {ext} the one of the output format
+// and {index} the position of the file in the batch, starting at 1. The name always
+// ends with the format extension, so the encoder matches the file
+pub fn output_name(pattern: &str, input: &Path, index: usize, format: ExportFormat) -> String {
+    let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+    let mut name = pattern
+        .replace("{name}", &stem)
+        .replace("{ext}", format.extension())
+        .replace("{index}", &(index + 1).to_string());
+    let extension = format!(".{}", format.extension());
+    if !name.to_lowercase().ends_with(&extension) {
+        name.push_str(&extension);
+    }
+    name
+}
+
+#[derive(PartialEq, Clone)]
+pub enum FileStatus {
+    Waiting,
+    Processing,
+    Done(PathBuf),
+    Failed(String),
+    Cancelled,
+}
+
+impl FileStatus {
+    pub fn is_finished(&self) -> bool {
+        !matches!(self, FileStatus::Waiting | FileStatus::Processing)
+    }
+}
+
+// Everything a worker needs, shared between all of them
+struct Job {
+    inputs: Vec<PathBuf>,
+    output_dir: PathBuf,
+    pattern: String,
+    format: ExportFormat,
+    quality: u8,
+    preset: Preset,
+    fonts: Vec<FontEntry>,
+    operations: Registry,
+    next: AtomicUsize,
+    cancel: Arc<AtomicBool>,
+}
+
+// A batch running on worker threads. The statuses are updated by polling,
+// so the window can redraw while the files are processed
+pub struct Batch {
+    inputs: Vec<PathBuf>,
+    statuses: Vec<FileStatus>,
+    receiver: Receiver<(usize, FileStatus)>,
+    cancel: Arc<AtomicBool>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl Batch {
+    pub fn start(
+        settings: &BatchSettings,
+        output_dir: PathBuf,
+        preset: Preset,
+        fonts: Vec<FontEntry>,
+        operations: Registry,
+        quality: u8,
+    ) -> Self {
+        let cancel = Arc::new(AtomicBool::new(false));
+        let job = Arc::new(Job {
+            inputs: settings.inputs.clone(),
+            output_dir,
+            pattern: settings.pattern.clone(),
+            format: settings.format,
+            quality,
+            preset,
+            fonts,
+            operations,
+            next: AtomicUsize::new(0),
+            cancel: cancel.clone(),
+        });
+        let (sender, receiver) = mpsc::channel();
+        let threads = thread::available_parallelism().map_or(1, |n| n.get()).min(settings.inputs.len()).max(1);
+        let workers = (0..threads)
+            .map(|_| {
+                let job = job.clone();
+                let sender = sender.clone();
+                thread::spawn(move || work(&job, &sender))
+            })
+            .collect();
+        Self {
+            inputs: settings.inputs.clone(),
+            statuses: vec![FileStatus::Waiting; settings.inputs.len()],
+            receiver,
+            cancel,
+            workers,
+        }
+    }
+
+    // Take the updates sent by the workers since the last call
+    pub fn poll(&mut self) {
+        // Checked first, so the last updates of the workers are not missed
+        let finished = self.workers.iter().all(|worker| worker.is_finished());
+        for (index, status) in self.receiver.try_iter() {
+            self.statuses[index] = status;
+        }
+        if finished {
+            for status in &mut self.statuses {
+                if !status.is_finished() {
+                    *status = FileStatus::Cancelled;
+                }
+            }
+        }
+    }
+
+    // Files already being processed are finished, the others are skipped
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.statuses.iter().any(|status| !status.is_finished())
+    }
+
+    pub fn inputs(&self) -> &Vec<PathBuf> {
+        &self.inputs
+    }
+
+    pub fn statuses(&self) -> &Vec<FileStatus> {
+        &self.statuses
+    }
+
+    pub fn finished_count(&self) -> usize {
+        self.statuses.iter().filter(|status| status.is_finished()).count()
+    }
+
+    pub fn failures(&self) -> Vec<(&PathBuf, &String)> {
+        self.inputs
+            .iter()
+            .zip(&self.statuses)
+            .filter_map(|(path, status)| match status {
+                FileStatus::Failed(err) => Some((path, err)),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+// Closing the window or starting another batch stops the workers
+impl Drop for Batch {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+// Each worker takes the next file until there is none left or the batch is cancelled
+fn work(job: &Job, sender: &Sender<(usize, FileStatus)>) {
+    loop {
+        if job.cancel.load(Ordering::Relaxed) {
+            return;
+        }
+        let index = job.next.fetch_add(1, Ordering::Relaxed);
+        if index >= job.inputs.len() {
+            return;
+        }
+        if sender.send((index, FileStatus::Processing)).is_err() {
+            return;
+        }
+        let status = match process(job, index) {
+            Ok(path) => FileStatus::Done(path),
+            Err(err) => FileStatus::Failed(err),
+        };
+        if sender.send((index, status)).is_err() {
+            return;
+        }
+    }
+}
+
+fn process(job: &Job, index: usize) -> Result<PathBuf, String> {
+    let input = &job.inputs[index];
+    let output = job.output_dir.join(output_name(&job.pattern, input, index, job.format));
+    if output == *input {
+        return Err(String::from("The output would overwrite the input"));
+    }
+    let img = image::open(input).map_err(|err| format!("Could not open: {}", err))?;
+    let img = ops::apply_preset(img, &job.preset, &job.fonts, &job.operations).map_err(|err| err.to_string())?;
+    fs::create_dir_all(&job.output_dir).map_err(|err| format!("Could not create the output folder: {}", err))?;
+    transparency::save_with_alpha(&img, &output, job.quality).map_err(|err| format!("Could not write: {}", err))?;
+    Ok(output)
+}
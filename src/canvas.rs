@@ -0,0 +1,36 @@
+use eframe::egui::{pos2, Pos2, Rect, Vec2, vec2};
+
+// Placement of the image on the screen, used to convert pointer positions into pixels
+#[derive(Clone, Copy)]
+pub struct Canvas {
+    rect: Rect,
+    image_size: Vec2,
+}
+
+impl Canvas {
+    pub fn new(rect: Rect, image_size: [usize; 2]) -> Self {
+        Self {
+            rect,
+            image_size: vec2(image_size[0] as f32, image_size[1] as f32),
+        }
+    }
+
+    pub fn rect(self) -> Rect {
+        self.rect
+    }
+
+    // Screen points per image pixel
+    pub fn scale(self) -> f32 {
+        self.rect.width() / self.image_size.x
+    }
+
+    pub fn to_image(self, pos: Pos2) -> (f32, f32) {
+        let scale = self.scale();
+        ((pos.x - self.rect.min.x) / scale, (pos.y - self.rect.min.y) / scale)
+    }
+
+    pub fn to_screen(self, point: (f32, f32)) -> Pos2 {
+        let scale = self.scale();
+        pos2(self.rect.min.x + point.0 * scale, self.rect.min.y + point.1 * scale)
+    }
+}
@@ -0,0 +1,131 @@
+use image::{DynamicImage, Rgba};
+use serde::{Deserialize, Serialize};
+
+#[derive(PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum Channel {
+    Red,
+    Green,
+    Blue,
+    Alpha,
+}
+
+impl Channel {
+    pub const ALL: [Channel; 4] = [Channel::Red, Channel::Green, Channel::Blue, Channel::Alpha];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Channel::Red => "Red",
+            Channel::Green => "Green",
+            Channel::Blue => "Blue",
+            Channel::Alpha => "Alpha",
+        }
+    }
+
+    fn index(&self) -> usize {
+        match self {
+            Channel::Red => 0,
+            Channel::Green => 1,
+            Channel::Blue => 2,
+            Channel::Alpha => 3,
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum ChannelOperation {
+    Isolate,
+    Grayscale,
+    Swap,
+    Mixer,
+}
+
+impl ChannelOperation {
+    pub const ALL: [ChannelOperation; 4] = [ChannelOperation::Isolate, ChannelOperation::Grayscale, ChannelOperation::Swap, ChannelOperation::Mixer];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChannelOperation::Isolate => "View Channel",
+            ChannelOperation::Grayscale => "Channel as Grayscale",
+            ChannelOperation::Swap => "Swap Channels",
+            ChannelOperation::Mixer => "Channel Mixer",
+        }
+    }
+}
+
+// Each output channel is a weighted sum of the input channels plus an offset (0-255 scale)
+#[derive(PartialEq, Clone, Serialize, Deserialize)]
+pub struct ChannelMixer {
+    pub matrix: [[f32; 3]; 3],
+    pub offset: [f32; 3],
+    pub monochrome: bool,
+}
+
+impl Default for ChannelMixer {
+    fn default() -> Self {
+        Self {
+            matrix: [
+                [1.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0],
+                [0.0, 0.0, 1.0],
+            ],
+            offset: [0.0; 3],
+            monochrome: false,
+        }
+    }
+}
+
+fn to_u8(value: f32) -> u8 {
+    value.round().clamp(0.0, 255.0) as u8
+}
+
+// Keep only one channel. The alpha channel is shown as an opaque gray image
+pub fn isolate_channel(img: &DynamicImage, channel: Channel) -> DynamicImage {
+    if channel == Channel::Alpha {
+        return channel_to_grayscale(img, channel);
+    }
+    let mut out = img.to_rgba8();
+    for px in out.pixels_mut() {
+        for c in 0..3 {
+            if c != channel.index() {
+                px[c] = 0;
+            }
+        }
+    }
+    DynamicImage::ImageRgba8(out)
+}
+
+pub fn channel_to_grayscale(img: &DynamicImage, channel: Channel) -> DynamicImage {
+    let mut out = img.to_rgba8();
+    for px in out.pixels_mut() {
+        let value = px[channel.index()];
+        let alpha = if channel == Channel::Alpha { 255 } else { px[3] };
+        *px = Rgba([value, value, value, alpha]);
+    }
+    DynamicImage::ImageRgba8(out)
+}
+
+pub fn swap_channels(img: &DynamicImage, a: Channel, b: Channel) -> DynamicImage {
+    let mut out = img.to_rgba8();
+    for px in out.pixels_mut() {
+        px.0.swap(a.index(), b.index());
+    }
+    DynamicImage::ImageRgba8(out)
+}
+
+pub fn mix_channels(img: &DynamicImage, mixer: &ChannelMixer) -> DynamicImage {
+    let mut out = img.to_rgba8();
+    for px in out.pixels_mut() {
+        let input = [px[0] as f32, px[1] as f32, px[2] as f32];
+        let mix = |row: usize| -> f32 {
+            (0..3).map(|c| mixer.matrix[row][c] * input[c]).sum::<f32>() + mixer.offset[row]
+        };
+        if mixer.monochrome {
+            // Monochrome conversions only use the first row of the matrix
+            let value = to_u8(mix(0));
+            *px = Rgba([value, value, value, px[3]]);
+        } else {
+            *px = Rgba([to_u8(mix(0)), to_u8(mix(1)), to_u8(mix(2)), px[3]]);
+        }
+    }
+    DynamicImage::ImageRgba8(out)
+}
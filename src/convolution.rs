@@ -0,0 +1,287 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use image::{imageops, DynamicImage, Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+use crate::ops::{Error, Result};
+
+pub const KERNEL_SIZES: [usize; 4] = [3, 5, 7, 9];
+// Largest custom kernel side, each pixel already reads about 10k neighbours at this size
+pub const MAX_KERNEL_SIZE: usize = 99;
+
+#[derive(PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum EdgeMode {
+    Extend,
+    Wrap,
+    Mirror,
+    Transparent,
+}
+
+impl EdgeMode {
+    pub const ALL: [EdgeMode; 4] = [EdgeMode::Extend, EdgeMode::Wrap, EdgeMode::Mirror, EdgeMode::Transparent];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EdgeMode::Extend => "Extend",
+            EdgeMode::Wrap => "Wrap",
+            EdgeMode::Mirror => "Mirror",
+            EdgeMode::Transparent => "Transparent",
+        }
+    }
+
+    // Pixel used for a coordinate that may fall outside of the image
+    fn sample(&self, img: &RgbaImage, x: i64, y: i64) -> Rgba<u8> {
+        let (w, h) = (img.width() as i64, img.height() as i64);
+        if x >= 0 && x < w && y >= 0 && y < h {
+            return *img.get_pixel(x as u32, y as u32);
+        }
+        let (x, y) = match self {
+            EdgeMode::Extend => (x.clamp(0, w - 1), y.clamp(0, h - 1)),
+            EdgeMode::Wrap => (x.rem_euclid(w), y.rem_euclid(h)),
+            EdgeMode::Mirror => (mirror(x, w), mirror(y, h)),
+            EdgeMode::Transparent => return Rgba([0, 0, 0, 0]),
+        };
+        *img.get_pixel(x as u32, y as u32)
+    }
+}
+
+fn mirror(v: i64, len: i64) -> i64 {
+    if len == 1 {
+        return 0;
+    }
+    let period = 2 * (len - 1);
+    let v = v.rem_euclid(period);
+    if v < len { v } else { period - v }
+}
+
+#[derive(PartialEq, Clone, Serialize, Deserialize)]
+pub struct Kernel {
+    pub name: String,
+    pub size: usize,
+    pub values: Vec<f32>,
+    pub divisor: f32,
+    pub offset: f32,
+}
+
+impl Kernel {
+    // Identity kernel: leaves the image untouched
+    pub fn new(name: &str, size: usize) -> Self {
+        let mut values = vec![0.0; size * size];
+        values[size * size / 2] = 1.0;
+        Self {
+            name: String::from(name),
+            size,
+            values,
+            divisor: 1.0,
+            offset: 0.0,
+        }
+    }
+
+    // Custom kernels come from the UI, kernel files and presets, so their shape is checked
+    pub fn from_values(name: &str, size: usize, values: &[f32], divisor: f32, offset: f32) -> Result<Self> {
+        if !is_odd_square(size, values.len()) {
+            return Err(Error::InvalidParameter {
+                name: String::from("kernel"),
+                reason: format!("{} values don't make an odd sized {}x{} kernel of at most {}", values.len(), size, size, MAX_KERNEL_SIZE),
+            });
+        }
+        Ok(Self {
+            name: String::from(name),
+            size,
+            values: values.to_vec(),
+            divisor,
+            offset,
+        })
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> f32 {
+        self.values[row * self.size + col]
+    }
+    pub fn set(&mut self, row: usize, col: usize, value: f32) {
+        self.values[row * self.size + col] = value;
+    }
+
+    // Change the kernel size keeping the values centered
+    pub fn resize(&mut self, size: usize) {
+        if size == self.size {
+            return;
+        }
+        let mut values = vec![0.0; size * size];
+        let (old, new) = (self.size as i64, size as i64);
+        let shift = (new - old) / 2;
+        for row in 0..old {
+            for col in 0..old {
+                let (r, c) = (row + shift, col + shift);
+                if r >= 0 && r < new && c >= 0 && c < new {
+                    values[(r * new + c) as usize] = self.values[(row * old + col) as usize];
+                }
+            }
+        }
+        self.size = size;
+        self.values = values;
+    }
+
+    // Set the divisor so the kernel preserves the overall brightness
+    pub fn normalize(&mut self) {
+        let sum: f32 = self.values.iter().sum();
+        self.divisor = if sum == 0.0 { 1.0 } else { sum };
+    }
+
+    pub fn builtin() -> Vec<Kernel> {
+        let mut motion_blur = Kernel::new("Motion Blur", 9);
+        for i in 0..9 {
+            motion_blur.set(i, i, 1.0);
+        }
+        motion_blur.normalize();
+
+        let builtin = |name: &str, size: usize, values: &[f32], divisor: f32, offset: f32| {
+            Kernel::from_values(name, size, values, divisor, offset).expect("Built-in kernels are odd sized")
+        };
+        vec![
+            builtin("Emboss", 3, &[
+                -2.0, -1.0, 0.0,
+                -1.0, 1.0, 1.0,
+                0.0, 1.0, 2.0,
+            ], 1.0, 0.0),
+            builtin("Edge Detect", 3, &[
+                0.0, 1.0, 0.0,
+                1.0, -4.0, 1.0,
+                0.0, 1.0, 0.0,
+            ], 1.0, 0.0),
+            builtin("Sobel Horizontal", 3, &[
+                -1.0, -2.0, -1.0,
+                0.0, 0.0, 0.0,
+                1.0, 2.0, 1.0,
+            ], 1.0, 128.0),
+            builtin("Sobel Vertical", 3, &[
+                -1.0, 0.0, 1.0,
+                -2.0, 0.0, 2.0,
+                -1.0, 0.0, 1.0,
+            ], 1.0, 128.0),
+            builtin("Laplacian", 3, &[
+                -1.0, -1.0, -1.0,
+                -1.0, 8.0, -1.0,
+                -1.0, -1.0, -1.0,
+            ], 1.0, 0.0),
+            builtin("Box Blur", 5, &[1.0; 25], 25.0, 0.0),
+            motion_blur,
+            builtin("Outline", 3, &[
+                -1.0, -1.0, -1.0,
+                -1.0, 9.0, -1.0,
+                -1.0, -1.0, -1.0,
+            ], 1.0, 0.0),
+            builtin("Sharpen", 3, &[
+                0.0, -1.0, 0.0,
+                -1.0, 5.0, -1.0,
+                0.0, -1.0, 0.0,
+            ], 1.0, 0.0),
+        ]
+    }
+
+    // Plain text format: name line, "size divisor offset" line, then one line per row
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut text = format!("{}\n{} {} {}\n", self.name, self.size, self.divisor, self.offset);
+        for row in self.values.chunks(self.size) {
+            let row: Vec<String> = row.iter().map(|v| v.to_string()).collect();
+            text.push_str(&row.join(" "));
+            text.push('\n');
+        }
+        fs::write(path, text)
+    }
+
+    pub fn load(path: &Path) -> io::Result<Kernel> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        let text = fs::read_to_string(path)?;
+        let mut lines = text.lines().filter(|l| !l.trim().is_empty());
+
+        let name = lines.next().ok_or_else(|| invalid("Empty kernel file"))?.trim();
+        let header: Vec<&str> = lines.next().ok_or_else(|| invalid("Missing kernel header"))?.split_whitespace().collect();
+        if header.len() != 3 {
+            return Err(invalid("Kernel header must be \"size divisor offset\""));
+        }
+        let size: usize = header[0].parse().map_err(|_| invalid("Invalid kernel size"))?;
+        let divisor: f32 = header[1].parse().map_err(|_| invalid("Invalid kernel divisor"))?;
+        let offset: f32 = header[2].parse().map_err(|_| invalid("Invalid kernel offset"))?;
+
+        let values = lines
+            .flat_map(|l| l.split_whitespace())
+            .map(|v| v.parse::<f32>().map_err(|_| invalid("Invalid kernel value")))
+            .collect::<io::Result<Vec<f32>>>()?;
+
+        Kernel::from_values(name, size, &values, divisor, offset).map_err(|err| invalid(&err.to_string()))
+    }
+}
+
+// The size comes from files and presets, so the square is checked for overflow
+pub fn is_odd_square(size: usize, len: usize) -> bool {
+    !size.is_multiple_of(2) && size <= MAX_KERNEL_SIZE && size.checked_mul(size) == Some(len)
+}
+
+// Apply the kernel to the color channels, the alpha channel is kept as is
+pub fn convolve(img: &DynamicImage, kernel: &Kernel, edge_mode: EdgeMode) -> DynamicImage {
+    let src = img.to_rgba8();
+    let (w, h) = src.dimensions();
+    // A kernel that didn't go through `from_values` may not be square, it changes nothing
+    if w == 0 || h == 0 || !is_odd_square(kernel.size, kernel.values.len()) {
+        return img.clone();
+    }
+    if let Some(values) = filter3x3_values(kernel) {
+        return convolve_3x3(&src, &values, edge_mode);
+    }
+    let half = (kernel.size / 2) as i64;
+    let divisor = if kernel.divisor == 0.0 { 1.0 } else { kernel.divisor };
+
+    let out = RgbaImage::from_fn(w, h, |x, y| {
+        let mut sum = [0.0f32; 3];
+        for row in 0..kernel.size {
+            for col in 0..kernel.size {
+                let k = kernel.get(row, col);
+                if k == 0.0 {
+                    continue;
+                }
+                let px = edge_mode.sample(&src, x as i64 + col as i64 - half, y as i64 + row as i64 - half);
+                for c in 0..3 {
+                    sum[c] += k * px[c] as f32;
+                }
+            }
+        }
+        let value = |c: usize| (sum[c] / divisor + kernel.offset).round().clamp(0.0, 255.0) as u8;
+        Rgba([value(0), value(1), value(2), src.get_pixel(x, y)[3]])
+    });
+
+    DynamicImage::ImageRgba8(out)
+}
+
+// `filter3x3` always divides by the sum of the kernel (by 1 when it is 0) and has no offset,
+// so it only fits the 3x3 kernels that work that way, like most of the built-in ones
+fn filter3x3_values(kernel: &Kernel) -> Option<[f32; 9]> {
+    if kernel.size != 3 || kernel.offset != 0.0 {
+        return None;
+    }
+    let divisor = if kernel.divisor == 0.0 { 1.0 } else { kernel.divisor };
+    let sum: f32 = kernel.values.iter().sum();
+    let mut values = [0.0; 9];
+    if sum == 0.0 {
+        for (value, k) in values.iter_mut().zip(&kernel.values) {
+            *value = k / divisor;
+        }
+    } else if sum == divisor {
+        values.copy_from_slice(&kernel.values);
+    } else {
+        return None;
+    }
+    Some(values)
+}
+
+// `filter3x3` leaves the outer pixels blank, so the image gets a border
+// from the edge mode first and loses it afterwards
+fn convolve_3x3(src: &RgbaImage, values: &[f32; 9], edge_mode: EdgeMode) -> DynamicImage {
+    let (w, h) = src.dimensions();
+    let padded = RgbaImage::from_fn(w + 2, h + 2, |x, y| edge_mode.sample(src, x as i64 - 1, y as i64 - 1));
+    let filtered = imageops::filter3x3(&padded, values);
+    let out = RgbaImage::from_fn(w, h, |x, y| {
+        let px = filtered.get_pixel(x + 1, y + 1);
+        Rgba([px[0], px[1], px[2], src.get_pixel(x, y)[3]])
+    });
+    DynamicImage::ImageRgba8(out)
+}
@@ -0,0 +1,170 @@
+use std::thread;
+use image::{DynamicImage, Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+// Patch radius compared by the non-local means filter (3x3 patches)
+const NLM_PATCH_RADIUS: i64 = 1;
+
+#[derive(PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum DenoiseMethod {
+    Median,
+    Bilateral,
+    NonLocalMeans,
+}
+
+impl DenoiseMethod {
+    pub const ALL: [DenoiseMethod; 3] = [DenoiseMethod::Median, DenoiseMethod::Bilateral, DenoiseMethod::NonLocalMeans];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DenoiseMethod::Median => "Median",
+            DenoiseMethod::Bilateral => "Bilateral",
+            DenoiseMethod::NonLocalMeans => "Non-Local Means",
+        }
+    }
+
+    pub fn max_radius(&self) -> u32 {
+        match self {
+            DenoiseMethod::Median => 10,
+            DenoiseMethod::Bilateral => 10,
+            DenoiseMethod::NonLocalMeans => 7,
+        }
+    }
+}
+
+// Strength goes from 0 (no effect) to 100 (strongest)
+pub fn denoise(img: &DynamicImage, method: DenoiseMethod, radius: u32, strength: f32) -> DynamicImage {
+    let src = img.to_rgba8();
+    let out = match method {
+        DenoiseMethod::Median => median(&src, radius as i64, strength),
+        DenoiseMethod::Bilateral => bilateral(&src, radius as i64, strength),
+        DenoiseMethod::NonLocalMeans => non_local_means(&src, radius as i64, strength),
+    };
+    DynamicImage::ImageRgba8(out)
+}
+
+fn clamped(img: &RgbaImage, x: i64, y: i64) -> &Rgba<u8> {
+    img.get_pixel(
+        x.clamp(0, img.width() as i64 - 1) as u32,
+        y.clamp(0, img.height() as i64 - 1) as u32,
+    )
+}
+
+// Compute every row of the output in parallel, one band of rows per thread
+fn par_rows<F>(src: &RgbaImage, f: F) -> RgbaImage
+    where F: Fn(u32, u32) -> Rgba<u8> + Sync {
+    let (w, h) = src.dimensions();
+    let mut out = RgbaImage::new(w, h);
+    if w == 0 || h == 0 {
+        return out;
+    }
+    let threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let rows_per_band = (h as usize).div_ceil(threads);
+    let row_len = w as usize * 4;
+
+    thread::scope(|scope| {
+        for (band, chunk) in out.chunks_mut(rows_per_band * row_len).enumerate() {
+            let f = &f;
+            scope.spawn(move || {
+                for (i, px) in chunk.chunks_mut(4).enumerate() {
+                    let x = (i % w as usize) as u32;
+                    let y = (band * rows_per_band + i / w as usize) as u32;
+                    px.copy_from_slice(&f(x, y).0);
+                }
+            });
+        }
+    });
+    out
+}
+
+// Switching median: only pixels far from the local median are replaced,
+// the threshold goes down as the strength goes up
+fn median(src: &RgbaImage, radius: i64, strength: f32) -> RgbaImage {
+    let threshold = 255.0 * (1.0 - strength / 100.0);
+    par_rows(src, |x, y| {
+        let mut window: [Vec<u8>; 3] = Default::default();
+        for dy in -radius..=radius {
+            for dx in -radius..=radius {
+                let px = clamped(src, x as i64 + dx, y as i64 + dy);
+                for (c, values) in window.iter_mut().enumerate() {
+                    values.push(px[c]);
+                }
+            }
+        }
+        let mut px = *src.get_pixel(x, y);
+        for (c, values) in window.iter_mut().enumerate() {
+            let mid = values.len() / 2;
+            let median = *values.select_nth_unstable(mid).1;
+            if (px[c] as f32 - median as f32).abs() >= threshold {
+                px[c] = median;
+            }
+        }
+        px
+    })
+}
+
+// Edge preserving blur: neighbours are weighted by distance and by color similarity
+fn bilateral(src: &RgbaImage, radius: i64, strength: f32) -> RgbaImage {
+    let sigma_space = (radius as f32 / 2.0).max(0.5);
+    let sigma_range = strength.max(1.0);
+    let side = (2 * radius + 1) as usize;
+    let mut space_weights = vec![0.0f32; side * side];
+    for dy in -radius..=radius {
+        for dx in -radius..=radius {
+            let d2 = (dx * dx + dy * dy) as f32;
+            space_weights[(dy + radius) as usize * side + (dx + radius) as usize] = (-d2 / (2.0 * sigma_space * sigma_space)).exp();
+        }
+    }
+
+    par_rows(src, |x, y| {
+        let center = src.get_pixel(x, y);
+        let mut sum = [0.0f32; 3];
+        let mut total = 0.0f32;
+        for dy in -radius..=radius {
+            for dx in -radius..=radius {
+                let px = clamped(src, x as i64 + dx, y as i64 + dy);
+                let diff2: f32 = (0..3).map(|c| (px[c] as f32 - center[c] as f32).powi(2)).sum();
+                let weight = space_weights[(dy + radius) as usize * side + (dx + radius) as usize]
+                    * (-diff2 / (2.0 * sigma_range * sigma_range)).exp();
+                for (c, s) in sum.iter_mut().enumerate() {
+                    *s += weight * px[c] as f32;
+                }
+                total += weight;
+            }
+        }
+        let value = |c: usize| (sum[c] / total).round().clamp(0.0, 255.0) as u8;
+        Rgba([value(0), value(1), value(2), center[3]])
+    })
+}
+
+// Average pixels of the search window whose surrounding patch looks like ours
+fn non_local_means(src: &RgbaImage, radius: i64, strength: f32) -> RgbaImage {
+    let h2 = strength.max(1.0).powi(2);
+    let patch_len = ((2 * NLM_PATCH_RADIUS + 1).pow(2) * 3) as f32;
+
+    par_rows(src, |x, y| {
+        let (x, y) = (x as i64, y as i64);
+        let mut sum = [0.0f32; 3];
+        let mut total = 0.0f32;
+        for sy in -radius..=radius {
+            for sx in -radius..=radius {
+                let mut dist2 = 0.0f32;
+                for py in -NLM_PATCH_RADIUS..=NLM_PATCH_RADIUS {
+                    for px in -NLM_PATCH_RADIUS..=NLM_PATCH_RADIUS {
+                        let a = clamped(src, x + px, y + py);
+                        let b = clamped(src, x + sx + px, y + sy + py);
+                        dist2 += (0..3).map(|c| (a[c] as f32 - b[c] as f32).powi(2)).sum::<f32>();
+                    }
+                }
+                let weight = (-(dist2 / patch_len) / h2).exp();
+                let px = clamped(src, x + sx, y + sy);
+                for (c, s) in sum.iter_mut().enumerate() {
+                    *s += weight * px[c] as f32;
+                }
+                total += weight;
+            }
+        }
+        let value = |c: usize| (sum[c] / total).round().clamp(0.0, 255.0) as u8;
+        Rgba([value(0), value(1), value(2), src.get_pixel(x as u32, y as u32)[3]])
+    })
+}
@@ -0,0 +1,147 @@
+use std::sync::Arc;
+use image::DynamicImage;
+use crate::operation::{Operation, ParamSpec, ParamValue, Registry};
+use crate::ops::Result;
+
+// In-house filters. A new one only needs an `Operation` impl and a line here.
+// Only the one-click filters live here so far, the slider effects (sepia, posterize,
+// vignette...) stay in `Params` because they are previewed and applied as one edit
+pub fn register_builtins(registry: &mut Registry) {
+    registry.register(Arc::new(Flip));
+    registry.register(Arc::new(Grayscale));
+    registry.register(Arc::new(Invert));
+    registry.register(Arc::new(Rotate));
+}
+
+pub struct Flip;
+
+impl Operation for Flip {
+    fn name(&self) -> &str {
+        "Flip"
+    }
+
+    fn params(&self) -> Vec<ParamSpec> {
+        vec![ParamSpec::bool("Horizontally", true), ParamSpec::bool("Vertically", false)]
+    }
+
+    fn apply(&self, img: &DynamicImage, values: &[ParamValue]) -> Result<DynamicImage> {
+        let mut new_img = img.clone();
+        if values[0].as_bool() {
+            new_img = new_img.fliph();
+        }
+        if values[1].as_bool() {
+            new_img = new_img.flipv();
+        }
+        Ok(new_img)
+    }
+}
+
+pub struct Grayscale;
+
+impl Operation for Grayscale {
+    fn name(&self) -> &str {
+        "Grayscale"
+    }
+
+    fn params(&self) -> Vec<ParamSpec> {
+        Vec::new()
+    }
+
+    fn apply(&self, img: &DynamicImage, _values: &[ParamValue]) -> Result<DynamicImage> {
+        Ok(img.grayscale())
+    }
+}
+
+pub struct Invert;
+
+impl Operation for Invert {
+    fn name(&self) -> &str {
+        "Invert"
+    }
+
+    fn params(&self) -> Vec<ParamSpec> {
+        Vec::new()
+    }
+
+    // Alpha is kept as is
+    fn apply(&self, img: &DynamicImage, _values: &[ParamValue]) -> Result<DynamicImage> {
+        let mut new_img = img.clone();
+        new_img.invert();
+        Ok(new_img)
+    }
+}
+
+pub struct Rotate;
+
+impl Operation for Rotate {
+    fn name(&self) -> &str {
+        "Rotate"
+    }
+
+    fn params(&self) -> Vec<ParamSpec> {
+        vec![ParamSpec::choice("Angle", &["90°", "180°", "270°"], 0)]
+    }
+
+    // Clockwise
+    fn apply(&self, img: &DynamicImage, values: &[ParamValue]) -> Result<DynamicImage> {
+        Ok(match values[0].as_choice() {
+            0 => img.rotate90(),
+            1 => img.rotate180(),
+            _ => img.rotate270(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GenericImageView, Rgba, RgbaImage};
+    use crate::adjustment::Adjustment;
+    use crate::operation::default_values;
+    use super::*;
+
+    fn image() -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(3, 2, |x, y| Rgba([x as u8 * 100, y as u8 * 200, 50, 255])))
+    }
+
+    // The filter as the editor replays it, with its default values
+    fn apply_default(name: &str) -> DynamicImage {
+        let registry = Registry::with_builtins();
+        let values = default_values(registry.find(name).unwrap().as_ref());
+        Adjustment::Operation { name: String::from(name), values }.apply(&image(), &[], &registry)
+    }
+
+    #[test]
+    fn flips_horizontally_by_default() {
+        let out = apply_default("Flip");
+        assert_eq!(out.get_pixel(0, 0), image().get_pixel(2, 0));
+        assert_eq!(out.get_pixel(0, 1), image().get_pixel(2, 1));
+    }
+
+    #[test]
+    fn grayscale_leaves_equal_channels() {
+        for (_, _, px) in apply_default("Grayscale").pixels() {
+            assert!(px[0] == px[1] && px[1] == px[2]);
+        }
+    }
+
+    #[test]
+    fn invert_keeps_alpha() {
+        let out = apply_default("Invert");
+        assert_eq!(out.get_pixel(1, 1), Rgba([155, 55, 205, 255]));
+    }
+
+    #[test]
+    fn rotates_clockwise() {
+        let out = apply_default("Rotate");
+        assert_eq!(out.dimensions(), (2, 3));
+        // The bottom left corner ends up top left
+        assert_eq!(out.get_pixel(0, 0), image().get_pixel(0, 1));
+    }
+
+    #[test]
+    fn every_builtin_is_covered() {
+        let registry = Registry::with_builtins();
+        let names: Vec<&str> = registry.operations().iter().map(|operation| operation.name()).collect();
+        assert_eq!(names, ["Flip", "Grayscale", "Invert", "Rotate"]);
+    }
+}
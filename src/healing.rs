@@ -0,0 +1,165 @@
+use image::{GrayImage, Rgba, RgbaImage};
+
+// Candidate sources are searched at these multiples of the spot size
+const SOURCE_DISTANCES: [f32; 3] = [1.2, 1.6, 2.2];
+const SOURCE_DIRECTIONS: usize = 8;
+// Over-relaxation factor and iteration cap of the blending solver
+const RELAXATION: f32 = 1.8;
+const MAX_ITERATIONS: usize = 400;
+
+// Replace the painted spot with texture from a nearby area. The texture is copied
+// from the best matching neighbour and its colors are shifted smoothly so the
+// edges meet the surrounding pixels (poisson blending)
+pub fn heal(base: &RgbaImage, coverage: &GrayImage, opacity: f32) -> RgbaImage {
+    let (w, h) = base.dimensions();
+    let bounds = match coverage_bounds(coverage) {
+        Some(bounds) => bounds,
+        None => return base.clone(),
+    };
+    // One pixel of border around the spot gives the colors to match
+    let x0 = bounds.0.saturating_sub(1);
+    let y0 = bounds.1.saturating_sub(1);
+    let x1 = (bounds.2 + 2).min(w);
+    let y1 = (bounds.3 + 2).min(h);
+    let (bw, bh) = (x1 - x0, y1 - y0);
+    let offset = find_source(base, coverage, (x0, y0, bw, bh));
+
+    let inside = |x: u32, y: u32| coverage.get_pixel(x, y)[0] > 0;
+    let source = |x: u32, y: u32| -> [f32; 4] {
+        match offset {
+            Some((dx, dy)) => {
+                let px = base.get_pixel((x as i64 + dx) as u32, (y as i64 + dy) as u32);
+                [px[0] as f32, px[1] as f32, px[2] as f32, px[3] as f32]
+            },
+            None => [0.0; 4],
+        }
+    };
+
+    // Difference between the spot surroundings and the source, spread over the spot
+    let index = |x: u32, y: u32| ((y - y0) * bw + (x - x0)) as usize;
+    let mut diff = vec![[0.0f32; 4]; (bw * bh) as usize];
+    for y in y0..y1 {
+        for x in x0..x1 {
+            if !inside(x, y) {
+                let px = base.get_pixel(x, y);
+                let src = source(x, y);
+                diff[index(x, y)] = [0, 1, 2, 3].map(|c| px[c] as f32 - src[c]);
+            }
+        }
+    }
+    let iterations = ((bw.max(bh) * 2) as usize).min(MAX_ITERATIONS);
+    for _ in 0..iterations {
+        for y in y0..y1 {
+            for x in x0..x1 {
+                if !inside(x, y) {
+                    continue;
+                }
+                let mut sum = [0.0f32; 4];
+                let mut count = 0.0;
+                let neighbours = [(x.wrapping_sub(1), y), (x + 1, y), (x, y.wrapping_sub(1)), (x, y + 1)];
+                for (nx, ny) in neighbours {
+                    if nx < x0 || ny < y0 || nx >= x1 || ny >= y1 {
+                        continue;
+                    }
+                    let value = diff[index(nx, ny)];
+                    for c in 0..4 {
+                        sum[c] += value[c];
+                    }
+                    count += 1.0;
+                }
+                if count > 0.0 {
+                    let current = &mut diff[index(x, y)];
+                    for c in 0..4 {
+                        current[c] += RELAXATION * (sum[c] / count - current[c]);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut out = base.clone();
+    for y in y0..y1 {
+        for x in x0..x1 {
+            if !inside(x, y) {
+                continue;
+            }
+            let weight = coverage.get_pixel(x, y)[0] as f32 / 255.0 * opacity / 100.0;
+            let (src, d, px) = (source(x, y), diff[index(x, y)], base.get_pixel(x, y));
+            let mix = |c: usize| {
+                let healed = (src[c] + d[c]).clamp(0.0, 255.0);
+                (px[c] as f32 + (healed - px[c] as f32) * weight).round() as u8
+            };
+            out.put_pixel(x, y, Rgba([mix(0), mix(1), mix(2), mix(3)]));
+        }
+    }
+    out
+}
+
+// Smallest rectangle (min x, min y, max x, max y) with painted pixels
+fn coverage_bounds(coverage: &GrayImage) -> Option<(u32, u32, u32, u32)> {
+    let mut bounds: Option<(u32, u32, u32, u32)> = None;
+    for (x, y, px) in coverage.enumerate_pixels() {
+        if px[0] == 0 {
+            continue;
+        }
+        bounds = Some(match bounds {
+            None => (x, y, x, y),
+            Some((x0, y0, x1, y1)) => (x0.min(x), y0.min(y), x1.max(x), y1.max(y)),
+        });
+    }
+    bounds
+}
+
+// Offset to the neighbouring area whose untouched pixels look the most like the
+// ones around the spot. None when no candidate fits inside the image
+fn find_source(base: &RgbaImage, coverage: &GrayImage, area: (u32, u32, u32, u32)) -> Option<(i64, i64)> {
+    let (w, h) = base.dimensions();
+    let (x0, y0, bw, bh) = area;
+    let size = bw.max(bh) as f32;
+    let mut best: Option<((i64, i64), f32)> = None;
+    for distance in SOURCE_DISTANCES {
+        for i in 0..SOURCE_DIRECTIONS {
+            let angle = i as f32 / SOURCE_DIRECTIONS as f32 * std::f32::consts::TAU;
+            let dx = (angle.cos() * distance * size).round() as i64;
+            let dy = (angle.sin() * distance * size).round() as i64;
+            let (sx, sy) = (x0 as i64 + dx, y0 as i64 + dy);
+            if sx < 0 || sy < 0 || sx + bw as i64 > w as i64 || sy + bh as i64 > h as i64 {
+                continue;
+            }
+            let mut score = 0.0;
+            let mut samples = 0.0;
+            for y in y0..y0 + bh {
+                for x in x0..x0 + bw {
+                    let source_x = (x as i64 + dx) as u32;
+                    let source_y = (y as i64 + dy) as u32;
+                    // The source itself has to be clean, and only the surroundings can be compared
+                    if coverage.get_pixel(source_x, source_y)[0] > 0 {
+                        score = f32::MAX;
+                        break;
+                    }
+                    if coverage.get_pixel(x, y)[0] > 0 {
+                        continue;
+                    }
+                    let (a, b) = (base.get_pixel(x, y), base.get_pixel(source_x, source_y));
+                    score += (0..4).map(|c| (a[c] as f32 - b[c] as f32).powi(2)).sum::<f32>();
+                    samples += 1.0;
+                }
+                if score == f32::MAX {
+                    break;
+                }
+            }
+            if score == f32::MAX {
+                continue;
+            }
+            let score = if samples > 0.0 { score / samples } else { 0.0 };
+            let better = match best {
+                Some((_, best_score)) => score < best_score,
+                None => true,
+            };
+            if better {
+                best = Some(((dx, dy), score));
+            }
+        }
+    }
+    best.map(|(offset, _)| offset)
+}
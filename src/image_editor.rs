@@ -1,10 +1,43 @@
-use std::path::{PathBuf};
+use std::path::{Path, PathBuf};
 use std::collections::VecDeque;
+use std::borrow::Cow;
 use std::fs;
+use std::io;
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+use eframe::egui::{ColorImage, TextureFilter, Vec2};
+use image::{DynamicImage, ImageResult, RgbaImage};
 use tempfile::{tempdir, TempDir};
 use egui_extras::RetainedImage;
+use my_image_editor::convolution::{convolve, EdgeMode, Kernel};
+use my_image_editor::params::{self, Params};
+use my_image_editor::artistic;
+use my_image_editor::channels::{self, Channel, ChannelMixer, ChannelOperation};
+use my_image_editor::transparency::{self, TransparencyOperation};
+use my_image_editor::selection::{self, Selection, SelectionMode, SelectionTool};
+use my_image_editor::paint::{Brush, PaintTool, Stroke};
+use my_image_editor::text::{self, FontEntry, TextBox};
+use my_image_editor::annotation::{self, Annotation, AnnotationStyle, ShapeKind};
+use my_image_editor::redaction::{self, RedactionStyle, Region};
+use my_image_editor::layers::{self, Layer, LayerSettings};
+use my_image_editor::adjustment::Adjustment;
+use my_image_editor::project::{self, Project};
+use crate::autosave::{self, AUTOSAVE_INTERVAL};
+use crate::recent::RecentFiles;
+use crate::preferences::Preferences;
+use crate::keymap::Action;
+use my_image_editor::preset::{self, Preset};
+use my_image_editor::script::{self, FileAccess};
+use my_image_editor::ops;
+use my_image_editor::operation::{self as registry, ParamValue, Registry};
+use my_image_editor::plugin;
+use crate::batch::{Batch, BatchSettings, BatchSource};
+use crate::recent::THUMBNAIL_SIZE;
 
-const MAX_VERSIONS: usize = 5;
+const MIN_ZOOM: f32 = 0.1;
+const MAX_ZOOM: f32 = 32.0;
+const RECENT_FILES_KEY: &str = "recent_files";
+const PREFERENCES_KEY: &str = "preferences";
 
 #[derive(PartialEq)]
 pub enum State {
@@ -12,10 +45,55 @@ pub enum State {
     Blur,
     Brighten,
     Contrast,
-    Flip,
-    Grayscale,
-    Invert,
-    Rotate,
+    Convolution,
+    Denoise,
+    Sepia,
+    Posterize,
+    Threshold,
+    Pixelate,
+    Vignette,
+    FilmGrain,
+    Channels,
+    Transparency,
+    Select,
+    Paint,
+    Text,
+    Shapes,
+    Redact,
+    // A filter of the operation registry, by its position
+    Operation(usize),
+}
+
+impl State {
+    // Modes whose values are kept together in the pending edit
+    pub fn uses_params(&self) -> bool {
+        matches!(
+            self,
+            State::Blur | State::Brighten | State::Contrast | State::Denoise | State::Sepia | State::Posterize
+                | State::Threshold | State::Pixelate | State::Vignette | State::FilmGrain
+        )
+    }
+}
+
+// A filter preview, run on its own thread since plugins can take seconds on a large image.
+// It keeps what it was started with, so a result that is out of date can be told apart
+struct OperationJob {
+    index: usize,
+    source: PathBuf,
+    values: Vec<ParamValue>,
+    handle: JoinHandle<ops::Result<(DynamicImage, DynamicImage)>>,
+}
+
+// The image the script started from, the one it left and what it printed
+type ScriptOutput = (Option<DynamicImage>, ops::Result<Option<DynamicImage>>, Vec<String>);
+
+// A script of the console, run on its own thread so a long one doesn't hold the window.
+// The result is only kept if the image is still the one it started from
+struct ScriptJob {
+    source: Option<PathBuf>,
+    name: String,
+    script: String,
+    handle: JoinHandle<ScriptOutput>,
 }
 
 pub struct ImageEditor {
@@ -30,11 +108,97 @@ pub struct ImageEditor {
     versions_discart: VecDeque<Option<PathBuf>>,
     logo: RetainedImage,
     state: State,
-    intensity: f32,
+    params: Params,
+    kernel: Kernel,
+    edge_mode: EdgeMode,
+    custom_kernels: Vec<Kernel>,
+    kernel_editor_open: bool,
+    kernel_message: Option<String>,
+    channel_operation: ChannelOperation,
+    channel: Channel,
+    swap_channel: Channel,
+    channel_mixer: ChannelMixer,
+    transparency_operation: TransparencyOperation,
+    opacity: f32,
+    key_tolerance: f32,
+    background_color: [u8; 3],
+    key_color: [u8; 3],
+    selection: Option<Selection>,
+    selection_tool: SelectionTool,
+    selection_mode: SelectionMode,
+    selection_feather: f32,
+    wand_tolerance: f32,
+    selection_points: Vec<(f32, f32)>,
+    selection_overlay: Option<RetainedImage>,
+    brush: Brush,
+    stroke: Option<Stroke>,
+    clone_source: Option<(f32, f32)>,
+    clone_offset: Option<(f32, f32)>,
+    zoom: f32,
+    pan: Vec2,
+    fonts: Vec<FontEntry>,
+    text_boxes: Vec<TextBox>,
+    selected_text_box: Option<usize>,
+    text_drag_offset: Option<(f32, f32)>,
+    overlay_base: Option<RgbaImage>,
+    annotation_tool: ShapeKind,
+    annotation_style: AnnotationStyle,
+    annotations: Vec<Annotation>,
+    selected_annotation: Option<usize>,
+    annotation_drag: Option<(f32, f32)>,
+    drawing_annotation: bool,
+    redaction_style: RedactionStyle,
+    redaction_block_size: u32,
+    redaction_regions: Vec<Region>,
+    redaction_drag: Option<Region>,
+    layers: Vec<Layer>,
+    selected_layer: Option<usize>,
+    layers_composite: Option<RetainedImage>,
+    layer_drag: Option<(f32, f32)>,
+    layer_message: Option<String>,
+    adjustments: Vec<Adjustment>,
+    undone_adjustments: Vec<Adjustment>,
+    project_message: Option<String>,
+    autosave_path: Option<PathBuf>,
+    last_autosave: Instant,
+    autosave_job: Option<JoinHandle<io::Result<()>>>,
+    unsaved_session: Option<PathBuf>,
+    recent_files: RecentFiles,
+    preferences: Preferences,
+    preferences_changed: bool,
+    settings_open: bool,
+    export_path: Option<PathBuf>,
+    rebinding: Option<Action>,
+    shortcuts_open: bool,
+    presets: Vec<(PathBuf, Preset)>,
+    preset_name: String,
+    preset_message: Option<String>,
+    // Previews of every preset on the image they were made from, redone when it changes
+    preset_thumbnails: Vec<RetainedImage>,
+    preset_thumbnails_source: Option<PathBuf>,
+    preset_thumbnails_job: Option<JoinHandle<Vec<RgbaImage>>>,
+    batch_open: bool,
+    batch_settings: BatchSettings,
+    batch: Option<Batch>,
+    operations: Registry,
+    operation_values: Vec<ParamValue>,
+    operation_message: Option<String>,
+    operation_job: Option<OperationJob>,
+    plugin_errors: Vec<String>,
+    script_open: bool,
+    script_name: String,
+    script_source: String,
+    script_job: Option<ScriptJob>,
+    script_log: Vec<String>,
+    scripts: Vec<PathBuf>,
 }
 
 impl ImageEditor {
     pub fn new() -> Self {
+        let (operations, plugin_errors) = load_operations();
+        let (presets, preset_errors) = preset::presets_dir()
+            .map(|dir| preset::load_all(&dir, &operations))
+            .unwrap_or_default();
         Self {
             temp_dir: tempdir().expect("Could not create temporary directory"),
             initial_image_path: None,
@@ -50,7 +214,88 @@ impl ImageEditor {
                 include_bytes!("../imgs/logo.png"),
             ).unwrap(),
             state: State::Waiting,
-            intensity: 0.0,
+            params: Params::default(),
+            kernel: Kernel::new("Identity", 3),
+            edge_mode: EdgeMode::Extend,
+            custom_kernels: Vec::new(),
+            kernel_editor_open: false,
+            kernel_message: None,
+            channel_operation: ChannelOperation::Isolate,
+            channel: Channel::Red,
+            swap_channel: Channel::Blue,
+            channel_mixer: ChannelMixer::default(),
+            transparency_operation: TransparencyOperation::Opacity,
+            opacity: 100.0,
+            key_tolerance: 0.0,
+            background_color: [255, 255, 255],
+            key_color: [0, 255, 0],
+            selection: None,
+            selection_tool: SelectionTool::Rectangle,
+            selection_mode: SelectionMode::Replace,
+            selection_feather: 0.0,
+            wand_tolerance: 15.0,
+            selection_points: Vec::new(),
+            selection_overlay: None,
+            brush: Brush::default(),
+            stroke: None,
+            clone_source: None,
+            clone_offset: None,
+            zoom: 1.0,
+            pan: Vec2::ZERO,
+            fonts: Vec::new(),
+            text_boxes: Vec::new(),
+            selected_text_box: None,
+            text_drag_offset: None,
+            overlay_base: None,
+            annotation_tool: ShapeKind::Rectangle,
+            annotation_style: AnnotationStyle::default(),
+            annotations: Vec::new(),
+            selected_annotation: None,
+            annotation_drag: None,
+            drawing_annotation: false,
+            redaction_style: RedactionStyle::Blur,
+            redaction_block_size: 16,
+            redaction_regions: Vec::new(),
+            redaction_drag: None,
+            layers: Vec::new(),
+            selected_layer: None,
+            layers_composite: None,
+            layer_drag: None,
+            layer_message: None,
+            adjustments: Vec::new(),
+            undone_adjustments: Vec::new(),
+            project_message: None,
+            autosave_path: autosave::sessions_dir().map(|dir| autosave::session_path(&dir)),
+            last_autosave: Instant::now(),
+            autosave_job: None,
+            unsaved_session: autosave::sessions_dir().and_then(|dir| autosave::find_unsaved_session(&dir)),
+            recent_files: RecentFiles::default(),
+            preferences: Preferences::default(),
+            preferences_changed: true,
+            settings_open: false,
+            export_path: None,
+            rebinding: None,
+            shortcuts_open: false,
+            presets,
+            preset_name: String::new(),
+            preset_message: (!preset_errors.is_empty()).then(|| preset_errors.join("\n")),
+            preset_thumbnails: Vec::new(),
+            preset_thumbnails_source: None,
+            preset_thumbnails_job: None,
+            batch_open: false,
+            batch_settings: BatchSettings::default(),
+            batch: None,
+            operations,
+            operation_values: Vec::new(),
+            operation_message: None,
+            operation_job: None,
+            plugin_errors,
+            script_open: false,
+            script_name: String::new(),
+            script_source: String::new(),
+            script_job: None,
+            script_log: Vec::new(),
+            scripts: preset::presets_dir().map(|dir| script::load_all(&dir)).unwrap_or_default(),
         }
     }
 
@@ -77,7 +322,16 @@ impl ImageEditor {
         self.set_current_img(Some(RetainedImage::from_image_bytes(
             self.initial_image_name().unwrap(),
             &img,
-        ).expect("ERROR UPDATING IMAGE FROM PICKED PATH!")));
+        ).expect("ERROR UPDATING IMAGE FROM PICKED PATH!").with_texture_filter(self.texture_filter())));
+        self.overlay_base = None;
+        self.refresh_layers_composite();
+
+        // A selection only makes sense for an image of the same size
+        if let (Some(selection), Some(size)) = (&self.selection, self.current_img_size()) {
+            if selection.dimensions() != (size[0] as u32, size[1] as u32) {
+                self.deselect();
+            }
+        }
     }
 
     pub fn inc_version_number(&mut self) {
@@ -111,17 +365,55 @@ impl ImageEditor {
             .unwrap())
             .expect("Failed to open INFILE.")
     }
+    pub fn current_img_size(&self) -> Option<[usize; 2]> {
+        self.current_img.as_ref().map(|img| img.size())
+    }
+    // Mix the effect result with the untouched image outside of the selection
+    fn restrict_to_selection(&self, img: &DynamicImage, new_img: DynamicImage) -> DynamicImage {
+        match &self.selection {
+            Some(selection) if selection.dimensions() == (new_img.width(), new_img.height()) => {
+                selection::blend_masked(img, &new_img, &selection.feathered(self.selection_feather))
+            },
+            _ => new_img,
+        }
+    }
     fn get_outfile_pathname(&self) -> PathBuf {
-        self.temp_dir.path().join(format!("{}_{}", self.version_number, self.initial_image_name().as_ref().unwrap()))
-
+        // Versions are always kept as PNG so the alpha channel survives every edit
+        let file_name = PathBuf::from(self.initial_image_name().unwrap()).with_extension("png");
+        self.temp_dir.path().join(format!("{}_{}", self.version_number, file_name.display()))
+    }
+    // The file is encoded again from the pixels, so metadata of the original
+    // (EXIF, GPS, camera data) never reaches the exported file
+    pub fn export_current_image(&self, path: &Path) {
+        let mut img = self.open_current_image();
+        // Layers, text and shapes that are still editable are flattened into the exported file
+        if !self.layers.is_empty() || !self.text_boxes.is_empty() || !self.annotations.is_empty() {
+            let mut rgba = layers::composite(&img.to_rgba8(), &self.layers);
+            self.render_overlays(&mut rgba);
+            img = DynamicImage::ImageRgba8(rgba);
+        }
+        transparency::save_with_alpha(&img, path, self.preferences.export_quality).expect("Failed writing OUTFILE.");
+    }
+    // Edits that are only kept in memory while editing are written to a file before being applied
+    pub fn finish_pending_edit(&mut self) {
+        if let State::Operation(_) = self.state {
+            self.wait_for_operation();
+        }
+        if self.state == State::Text || self.state == State::Shapes {
+            self.set_current_img_edited_path(self.apply_overlays());
+            self.clear_overlays();
+        }
     }
 
     pub fn initial_image_name(&self) -> Option<String> {
         Some(String::from(self.initial_image_path.as_ref().unwrap().file_name().unwrap().to_str().unwrap()))
     }
-    pub fn prepare_new_edition(&mut self) {
-        // Check if have arrived in the maximum of versions
-        if self.versions().len() > MAX_VERSIONS {
+    // Every version is pushed with the adjustment that leads from it, so the last
+    // `versions.len()` adjustments are the ones undo walks back through. Older ones
+    // are kept past the history size, the replay from the original needs them all
+    pub fn prepare_new_edition(&mut self, adjustment: Adjustment) {
+        // Check if have arrived in the maximum of versions, which may have been lowered since
+        while self.versions().len() > self.preferences.history_size {
             self.pop_front_versions();
         }
         // If have any versions in the discard stack, remove
@@ -132,134 +424,174 @@ impl ImageEditor {
         self.push_back_versions(self.current_img_path().clone());
         // Increment the version number
         self.inc_version_number();
+        self.adjustments.push(adjustment);
+        self.undone_adjustments.clear();
     }
 
-    pub fn apply_blur(&self) -> Option<PathBuf> {
-        if self.intensity == 0.0 {
+    // All the slider effects of the pending edit, previewed together
+    pub fn apply_effects(&self) -> Option<PathBuf> {
+        if self.params.is_identity() {
             return self.current_img_path.clone();
         }
-
         // Open current image
         let img = self.open_current_image();
-
-        // Apply blur (f32) on current image
-        let new_img = img.blur(self.intensity);
-
+        // Apply every active effect on current image
+        let new_img = params::apply_params(&img, &self.params);
+        // Keep the effects inside the selection
+        let new_img = self.restrict_to_selection(&img, new_img);
         // Save new image on a temp dir
         let file_path = self.get_outfile_pathname();
         new_img.save(&file_path).expect("Failed writing OUTFILE.");
-
         // Return new image path
         Some(file_path)
     }
-    pub fn apply_brighten(&self) -> Option<PathBuf> {
+    pub fn apply_convolution(&self) -> Option<PathBuf> {
         // Open current image
         let img = self.open_current_image();
-        // Apply brighten (i32) on current image
-        let new_img = img.brighten(self.intensity as i32);
+        // Apply the selected kernel on current image
+        let new_img = convolve(&img, &self.kernel, self.edge_mode);
+        // Keep the effect inside the selection
+        let new_img = self.restrict_to_selection(&img, new_img);
         // Save new image on a temp dir
         let file_path = self.get_outfile_pathname();
         new_img.save(&file_path).expect("Failed writing OUTFILE.");
         // Return new image path
         Some(file_path)
     }
-    pub fn apply_invert(&self) -> Option<PathBuf> {
-        // Open current image
-        let mut img = self.open_current_image();
-        // Apply brighten (i32) on current image
-        img.invert();
-        // Save new image on a temp dir
-        let file_path = self.get_outfile_pathname();
-        img.save(&file_path).expect("Failed writing OUTFILE.");
-        // Return new image path
-        Some(file_path)
+    // Preview the registry filter of the current mode with the values of the bottom panel.
+    // The preview shows up once `poll_operation` finds it done, while one runs the next waits for it
+    pub fn preview_operation(&mut self) {
+        let State::Operation(index) = self.state else {
+            return;
+        };
+        if self.operation_job.is_some() {
+            return;
+        }
+        // The registry may have been reloaded without it
+        let Some(operation) = self.operations.get(index) else {
+            self.operation_message = Some(String::from("This filter is not available anymore"));
+            return;
+        };
+        let Some(source) = self.current_img_path.clone() else {
+            return;
+        };
+        let (name, operations, values) = (operation.name().to_string(), self.operations.clone(), self.operation_values.clone());
+        let path = source.clone();
+        let job_values = values.clone();
+        let handle = thread::spawn(move || {
+            let img = image::open(&path).map_err(|err| ops::Error::Failed { operation: name.clone(), reason: err.to_string() })?;
+            let new_img = operations.apply(&img, &name, &job_values)?;
+            Ok((img, new_img))
+        });
+        self.operation_job = Some(OperationJob { index, source, values, handle });
     }
-    pub fn apply_grayscale(&self) -> Option<PathBuf> {
-        // Open current image
-        let img = self.open_current_image();
-        // Apply brighten (i32) on current image
-        let new_img = img.grayscale();
-        // Save new image on a temp dir
-        let file_path = self.get_outfile_pathname();
-        new_img.save(&file_path).expect("Failed writing OUTFILE.");
-        // Return new image path
-        Some(file_path)
+    // Called every frame. A filter that fails leaves the image as it is and tells why
+    pub fn poll_operation(&mut self) {
+        if self.operation_job.as_ref().is_some_and(|job| job.handle.is_finished()) {
+            let job = self.operation_job.take().unwrap();
+            self.finish_operation(job);
+        }
     }
-    pub fn apply_contrast(&self) -> Option<PathBuf> {
-        // Open current image
-        let img = self.open_current_image();
-        // Apply brighten (f32) on current image
-        let new_img = img.adjust_contrast(self.intensity);
-        // Save new image on a temp dir
-        let file_path = self.get_outfile_pathname();
-        new_img.save(&file_path).expect("Failed writing OUTFILE.");
-        // Return new image path
-        Some(file_path)
+    pub fn operation_running(&self) -> bool {
+        self.operation_job.is_some()
     }
-    pub fn apply_flip_horizontal_vertical(&self) -> Option<PathBuf> {
-        // Open current image
-        let img = self.open_current_image();
-        // Apply brighten (i32) on current image
-        let mut new_img = img.fliph();
-        new_img = new_img.flipv();
-        // Save new image on a temp dir
-        let file_path = self.get_outfile_pathname();
-        new_img.save(&file_path).expect("Failed writing OUTFILE.");
-        // Return new image path
-        Some(file_path)
+    // Apply waits for the preview of the values it keeps
+    pub fn wait_for_operation(&mut self) {
+        while let Some(job) = self.operation_job.take() {
+            self.finish_operation(job);
+        }
     }
-    pub fn apply_flip_horizontal(&self) -> Option<PathBuf> {
-        // Open current image
-        let img = self.open_current_image();
-        // Apply brighten (i32) on current image
-        let new_img = img.fliph();
+    fn finish_operation(&mut self, job: OperationJob) {
+        let result = job.handle.join().expect("Filter thread panicked");
+        // Left the filter meanwhile, the result is not needed
+        let State::Operation(index) = self.state else {
+            return;
+        };
+        // Another filter, an undo or a slider moved while it ran
+        if index != job.index || self.current_img_path.as_ref() != Some(&job.source) || self.operation_values != job.values {
+            self.preview_operation();
+            return;
+        }
+        let (img, new_img) = match result {
+            Ok(images) => images,
+            Err(err) => {
+                self.operation_message = Some(err.to_string());
+                return;
+            },
+        };
+        self.operation_message = None;
+        // Keep the effect inside the selection
+        let new_img = self.restrict_to_selection(&img, new_img);
         // Save new image on a temp dir
         let file_path = self.get_outfile_pathname();
         new_img.save(&file_path).expect("Failed writing OUTFILE.");
-        // Return new image path
-        Some(file_path)
+        self.set_current_img_edited_path(Some(file_path));
+    }
+    pub fn otsu_threshold(&self) -> u8 {
+        artistic::otsu_threshold(&self.open_current_image())
     }
-    pub fn apply_flip_vertical(&self) -> Option<PathBuf> {
+    pub fn apply_channels(&self) -> Option<PathBuf> {
         // Open current image
         let img = self.open_current_image();
-        // Apply brighten (i32) on current image
-        let new_img = img.flipv();
+        // Apply the selected channel operation on current image
+        let new_img = match self.channel_operation {
+            ChannelOperation::Isolate => channels::isolate_channel(&img, self.channel),
+            ChannelOperation::Grayscale => channels::channel_to_grayscale(&img, self.channel),
+            ChannelOperation::Swap => channels::swap_channels(&img, self.channel, self.swap_channel),
+            ChannelOperation::Mixer => channels::mix_channels(&img, &self.channel_mixer),
+        };
+        // Keep the effect inside the selection
+        let new_img = self.restrict_to_selection(&img, new_img);
         // Save new image on a temp dir
         let file_path = self.get_outfile_pathname();
         new_img.save(&file_path).expect("Failed writing OUTFILE.");
         // Return new image path
         Some(file_path)
     }
-    pub fn apply_rotate90(&self) -> Option<PathBuf> {
+
+    pub fn apply_transparency(&self) -> Option<PathBuf> {
         // Open current image
         let img = self.open_current_image();
-        // Apply brighten (i32) on current image
-        let new_img = img.rotate90();
+        // Apply the selected transparency operation on current image
+        let new_img = match self.transparency_operation {
+            TransparencyOperation::Opacity => transparency::set_opacity(&img, self.opacity),
+            TransparencyOperation::RemoveAlpha => transparency::remove_alpha(&img, self.background_color),
+            TransparencyOperation::ChromaKey => transparency::chroma_key(&img, self.key_color, self.key_tolerance),
+        };
+        // Keep the effect inside the selection
+        let new_img = self.restrict_to_selection(&img, new_img);
         // Save new image on a temp dir
         let file_path = self.get_outfile_pathname();
         new_img.save(&file_path).expect("Failed writing OUTFILE.");
         // Return new image path
         Some(file_path)
     }
-    pub fn apply_rotate180(&self) -> Option<PathBuf> {
+
+    pub fn apply_redaction(&self) -> Option<PathBuf> {
+        if self.redaction_regions.is_empty() {
+            return self.current_img_path.clone();
+        }
         // Open current image
         let img = self.open_current_image();
-        // Apply brighten (i32) on current image
-        let new_img = img.rotate180();
+        // Cover every region on current image
+        let new_img = redaction::redact(&img, &self.redaction_regions, self.redaction_style, self.redaction_block_size);
         // Save new image on a temp dir
         let file_path = self.get_outfile_pathname();
         new_img.save(&file_path).expect("Failed writing OUTFILE.");
         // Return new image path
         Some(file_path)
     }
-    pub fn apply_rotate270(&self) -> Option<PathBuf> {
+    pub fn apply_overlays(&self) -> Option<PathBuf> {
+        if self.text_boxes.is_empty() && self.annotations.is_empty() {
+            return self.current_img_path.clone();
+        }
         // Open current image
-        let img = self.open_current_image();
-        // Apply brighten (i32) on current image
-        let new_img = img.rotate270();
+        let mut img = self.open_current_image().to_rgba8();
+        // Draw every shape and text box on current image
+        self.render_overlays(&mut img);
         // Save new image on a temp dir
         let file_path = self.get_outfile_pathname();
-        new_img.save(&file_path).expect("Failed writing OUTFILE.");
+        DynamicImage::ImageRgba8(img).save(&file_path).expect("Failed writing OUTFILE.");
         // Return new image path
         Some(file_path)
     }
@@ -290,23 +622,1193 @@ impl ImageEditor {
         self.set_current_img_edited(Some(RetainedImage::from_image_bytes(
             self.initial_image_name().unwrap(),
             &img,
-        ).expect("ERROR UPDATING IMAGE FROM PICKED PATH!")));
+        ).expect("ERROR UPDATING IMAGE FROM PICKED PATH!").with_texture_filter(self.texture_filter())));
     }
     pub fn set_initial_image_path(&mut self, initial_image_path: Option<PathBuf>) {
         self.initial_image_path = initial_image_path;
     }
     pub fn clear_effects_values(&mut self) {
-        self.intensity = 0.0;
+        // The denoise method is a choice rather than a value, so it stays
+        self.params = Params { denoise_method: self.params.denoise_method, ..Params::default() };
+        self.channel_mixer = ChannelMixer::default();
+        self.opacity = 100.0;
+        self.key_tolerance = 0.0;
+        self.clear_overlays();
+        self.redaction_regions.clear();
+        self.redaction_drag = None;
     }
-    pub fn set_intensity(&mut self, intensity: f32) {
-        self.intensity = intensity;
+    pub fn params(&self) -> &Params {
+        &self.params
     }
-    pub fn intensity(&self) -> f32 {
-        self.intensity
+    pub fn set_params(&mut self, params: Params) {
+        self.params = params;
+        self.params.denoise_radius = params.denoise_radius.min(params.denoise_method.max_radius());
     }
     pub fn initial_image_path(&self) -> &Option<PathBuf> {
         &self.initial_image_path
     }
+    pub fn kernel(&self) -> &Kernel {
+        &self.kernel
+    }
+    pub fn set_kernel(&mut self, kernel: Kernel) {
+        self.kernel = kernel;
+    }
+    pub fn edge_mode(&self) -> &EdgeMode {
+        &self.edge_mode
+    }
+    pub fn set_edge_mode(&mut self, edge_mode: EdgeMode) {
+        self.edge_mode = edge_mode;
+    }
+    pub fn custom_kernels(&self) -> &Vec<Kernel> {
+        &self.custom_kernels
+    }
+    pub fn add_custom_kernel(&mut self, kernel: Kernel) {
+        // A kernel saved with an existing name replaces the old one
+        self.custom_kernels.retain(|k| k.name != kernel.name);
+        self.custom_kernels.push(kernel);
+    }
+    pub fn kernel_editor_open(&self) -> bool {
+        self.kernel_editor_open
+    }
+    pub fn set_kernel_editor_open(&mut self, kernel_editor_open: bool) {
+        self.kernel_editor_open = kernel_editor_open;
+    }
+    pub fn kernel_message(&self) -> &Option<String> {
+        &self.kernel_message
+    }
+    pub fn set_kernel_message(&mut self, kernel_message: Option<String>) {
+        self.kernel_message = kernel_message;
+    }
+    pub fn channel_operation(&self) -> &ChannelOperation {
+        &self.channel_operation
+    }
+    pub fn set_channel_operation(&mut self, channel_operation: ChannelOperation) {
+        self.channel_operation = channel_operation;
+    }
+    pub fn channel(&self) -> &Channel {
+        &self.channel
+    }
+    pub fn set_channel(&mut self, channel: Channel) {
+        self.channel = channel;
+    }
+    pub fn swap_channel(&self) -> &Channel {
+        &self.swap_channel
+    }
+    pub fn set_swap_channel(&mut self, swap_channel: Channel) {
+        self.swap_channel = swap_channel;
+    }
+    pub fn channel_mixer(&self) -> &ChannelMixer {
+        &self.channel_mixer
+    }
+    pub fn set_channel_mixer(&mut self, channel_mixer: ChannelMixer) {
+        self.channel_mixer = channel_mixer;
+    }
+    pub fn transparency_operation(&self) -> &TransparencyOperation {
+        &self.transparency_operation
+    }
+    pub fn set_transparency_operation(&mut self, transparency_operation: TransparencyOperation) {
+        self.transparency_operation = transparency_operation;
+    }
+    pub fn key_tolerance(&self) -> f32 {
+        self.key_tolerance
+    }
+    pub fn set_key_tolerance(&mut self, key_tolerance: f32) {
+        self.key_tolerance = key_tolerance;
+    }
+    pub fn opacity(&self) -> f32 {
+        self.opacity
+    }
+    pub fn set_opacity(&mut self, opacity: f32) {
+        self.opacity = opacity;
+    }
+    pub fn background_color(&self) -> [u8; 3] {
+        self.background_color
+    }
+    pub fn set_background_color(&mut self, background_color: [u8; 3]) {
+        self.background_color = background_color;
+    }
+    pub fn key_color(&self) -> [u8; 3] {
+        self.key_color
+    }
+    pub fn set_key_color(&mut self, key_color: [u8; 3]) {
+        self.key_color = key_color;
+    }
+    pub fn selection_tool(&self) -> &SelectionTool {
+        &self.selection_tool
+    }
+    pub fn set_selection_tool(&mut self, selection_tool: SelectionTool) {
+        self.selection_tool = selection_tool;
+    }
+    pub fn selection_mode(&self) -> &SelectionMode {
+        &self.selection_mode
+    }
+    pub fn set_selection_mode(&mut self, selection_mode: SelectionMode) {
+        self.selection_mode = selection_mode;
+    }
+    pub fn selection_feather(&self) -> f32 {
+        self.selection_feather
+    }
+    pub fn set_selection_feather(&mut self, selection_feather: f32) {
+        self.selection_feather = selection_feather;
+        self.refresh_selection_overlay();
+    }
+    pub fn wand_tolerance(&self) -> f32 {
+        self.wand_tolerance
+    }
+    pub fn set_wand_tolerance(&mut self, wand_tolerance: f32) {
+        self.wand_tolerance = wand_tolerance;
+    }
+    pub fn selection_points(&self) -> &Vec<(f32, f32)> {
+        &self.selection_points
+    }
+    pub fn selection_overlay(&self) -> &Option<RetainedImage> {
+        &self.selection_overlay
+    }
+    pub fn start_selection(&mut self, point: (f32, f32)) {
+        self.selection_points = vec![point];
+    }
+    pub fn extend_selection(&mut self, point: (f32, f32)) {
+        // Rectangles and ellipses only need the first and the last point
+        if self.selection_tool != SelectionTool::Lasso && self.selection_points.len() > 1 {
+            self.selection_points.pop();
+        }
+        self.selection_points.push(point);
+    }
+    pub fn finish_selection(&mut self) {
+        let points = std::mem::take(&mut self.selection_points);
+        let size = match self.current_img_size() {
+            Some(size) => (size[0] as u32, size[1] as u32),
+            None => return,
+        };
+        let (first, last) = match (points.first(), points.last()) {
+            (Some(first), Some(last)) => (*first, *last),
+            _ => return,
+        };
+        let shape = match self.selection_tool {
+            SelectionTool::Rectangle => selection::rectangle_mask(size.0, size.1, first, last),
+            SelectionTool::Ellipse => selection::ellipse_mask(size.0, size.1, first, last),
+            SelectionTool::Lasso => selection::lasso_mask(size.0, size.1, &points),
+            SelectionTool::MagicWand => return,
+        };
+        self.combine_selection(shape);
+    }
+    pub fn magic_wand_select(&mut self, point: (f32, f32)) {
+        if point.0 < 0.0 || point.1 < 0.0 {
+            return;
+        }
+        let img = self.open_current_image();
+        let shape = selection::magic_wand_mask(&img, (point.0 as u32, point.1 as u32), self.wand_tolerance);
+        self.combine_selection(shape);
+    }
+    fn combine_selection(&mut self, shape: image::GrayImage) {
+        let selection = Selection::combine(self.selection.take(), shape, self.selection_mode);
+        self.selection = if selection.is_empty() { None } else { Some(selection) };
+        self.refresh_selection_overlay();
+    }
+    pub fn deselect(&mut self) {
+        self.selection = None;
+        self.selection_points.clear();
+        self.refresh_selection_overlay();
+    }
+    pub fn invert_selection(&mut self) {
+        if let Some(selection) = &self.selection {
+            let inverted = selection.inverted();
+            self.selection = if inverted.is_empty() { None } else { Some(inverted) };
+            self.refresh_selection_overlay();
+        }
+    }
+    // Dim everything outside of the selection so it is visible over the image
+    fn refresh_selection_overlay(&mut self) {
+        self.selection_overlay = self.selection.as_ref().map(|selection| {
+            let mask = selection.feathered(self.selection_feather);
+            let (w, h) = mask.dimensions();
+            let pixels: Vec<u8> = mask
+                .pixels()
+                .flat_map(|px| [0, 0, 0, ((255 - px[0]) as f32 * 0.6) as u8])
+                .collect();
+            RetainedImage::from_color_image(
+                "selection",
+                ColorImage::from_rgba_unmultiplied([w as usize, h as usize], &pixels),
+            ).with_texture_filter(self.texture_filter())
+        });
+    }
+    pub fn brush(&self) -> &Brush {
+        &self.brush
+    }
+    pub fn set_brush(&mut self, brush: Brush) {
+        self.brush = brush;
+    }
+    pub fn is_painting(&self) -> bool {
+        self.stroke.is_some()
+    }
+    pub fn begin_stroke(&mut self, point: (f32, f32)) {
+        // The clone stamp needs a source first. The offset to it is kept for the next strokes
+        let source_offset = match (self.brush.tool, self.clone_source, self.clone_offset) {
+            (PaintTool::CloneStamp, None, _) => return,
+            (PaintTool::CloneStamp, Some(_), Some(offset)) => offset,
+            (PaintTool::CloneStamp, Some(source), None) => (source.0 - point.0, source.1 - point.1),
+            _ => (0.0, 0.0),
+        };
+        if self.brush.tool == PaintTool::CloneStamp {
+            self.clone_offset = Some(source_offset);
+        }
+        let base = self.open_current_image().to_rgba8();
+        let mask = match &self.selection {
+            Some(selection) if selection.dimensions() == base.dimensions() => Some(selection.feathered(self.selection_feather)),
+            _ => None,
+        };
+        let mut stroke = Stroke::new(base, self.brush, mask);
+        stroke.set_source_offset(source_offset);
+        stroke.add_point(point);
+        self.stroke = Some(stroke);
+        self.refresh_stroke_preview();
+    }
+    pub fn continue_stroke(&mut self, point: (f32, f32)) {
+        if let Some(stroke) = self.stroke.as_mut() {
+            stroke.add_point(point);
+            self.refresh_stroke_preview();
+        }
+    }
+    // Every stroke becomes a new version, so it can be undone on its own
+    pub fn end_stroke(&mut self) {
+        if let Some(stroke) = self.stroke.take() {
+            let new_img = DynamicImage::ImageRgba8(stroke.into_result());
+            let file_path = self.get_outfile_pathname();
+            new_img.save(&file_path).expect("Failed writing OUTFILE.");
+            self.prepare_new_edition(Adjustment::Paint { tool: self.brush.tool });
+            self.set_current_img_path(Some(file_path.clone()));
+            self.set_current_img_edited_path(Some(file_path));
+        }
+    }
+    fn refresh_stroke_preview(&mut self) {
+        if let Some(stroke) = &self.stroke {
+            let result = stroke.result();
+            self.current_img_edited = Some(RetainedImage::from_color_image(
+                "stroke",
+                ColorImage::from_rgba_unmultiplied([result.width() as usize, result.height() as usize], result.as_raw()),
+            ).with_texture_filter(self.texture_filter()));
+        }
+    }
+    pub fn set_clone_source(&mut self, point: (f32, f32)) {
+        self.clone_source = Some(point);
+        self.clone_offset = None;
+    }
+    // Where the clone stamp would copy from when painting at the point
+    pub fn clone_source_for(&self, point: (f32, f32)) -> Option<(f32, f32)> {
+        match (self.clone_source, self.clone_offset) {
+            (Some(_), Some(offset)) => Some((point.0 + offset.0, point.1 + offset.1)),
+            (source, None) => source,
+            (None, Some(_)) => None,
+        }
+    }
+    pub fn pick_color(&mut self, point: (f32, f32)) {
+        let img = self.open_current_image().to_rgba8();
+        if point.0 >= 0.0 && point.1 >= 0.0 && (point.0 as u32) < img.width() && (point.1 as u32) < img.height() {
+            let px = img.get_pixel(point.0 as u32, point.1 as u32);
+            self.brush.color = [px[0], px[1], px[2]];
+            self.brush.tool = PaintTool::Brush;
+        }
+    }
+    pub fn zoom(&self) -> f32 {
+        self.zoom
+    }
+    pub fn set_zoom(&mut self, zoom: f32) {
+        self.zoom = zoom.clamp(MIN_ZOOM, MAX_ZOOM);
+    }
+    pub fn pan(&self) -> Vec2 {
+        self.pan
+    }
+    pub fn set_pan(&mut self, pan: Vec2) {
+        self.pan = pan;
+    }
+    pub fn reset_view(&mut self) {
+        self.zoom = 1.0;
+        self.pan = Vec2::ZERO;
+    }
+    pub fn fonts(&self) -> &Vec<FontEntry> {
+        &self.fonts
+    }
+    pub fn load_fonts(&mut self) {
+        if self.fonts.is_empty() {
+            self.fonts = text::available_fonts();
+        }
+    }
+    // Font data by name, falling back to the first bundled font
+    fn font_data(&self, name: &str) -> Option<Cow<'static, [u8]>> {
+        text::font_data(&self.fonts, name)
+    }
+    // Shapes go below the text
+    fn render_overlays(&self, img: &mut RgbaImage) {
+        for annotation in &self.annotations {
+            annotation::render_annotation(img, annotation);
+        }
+        self.render_text_boxes(img);
+    }
+    fn render_text_boxes(&self, img: &mut RgbaImage) {
+        for text_box in &self.text_boxes {
+            if let Some(data) = self.font_data(&text_box.font) {
+                text::render_text_box(img, text_box, &data);
+            }
+        }
+    }
+    pub fn text_boxes(&self) -> &Vec<TextBox> {
+        &self.text_boxes
+    }
+    pub fn selected_text_box(&self) -> Option<usize> {
+        self.selected_text_box
+    }
+    pub fn add_text_box(&mut self, point: (f32, f32)) {
+        let mut text_box = TextBox::new(point);
+        // New boxes keep the style of the selected one
+        if let Some(selected) = self.selected_text_box.and_then(|i| self.text_boxes.get(i)) {
+            text_box = TextBox { text: text_box.text, position: point, ..selected.clone() };
+        }
+        self.text_boxes.push(text_box);
+        self.selected_text_box = Some(self.text_boxes.len() - 1);
+        self.refresh_overlay_preview();
+    }
+    pub fn update_text_box(&mut self, index: usize, text_box: TextBox) {
+        if self.text_boxes.get(index) != Some(&text_box) {
+            self.text_boxes[index] = text_box;
+            self.refresh_overlay_preview();
+        }
+    }
+    pub fn remove_selected_text_box(&mut self) {
+        if let Some(index) = self.selected_text_box.take() {
+            self.text_boxes.remove(index);
+            self.refresh_overlay_preview();
+        }
+    }
+    pub fn clear_overlays(&mut self) {
+        self.text_boxes.clear();
+        self.selected_text_box = None;
+        self.text_drag_offset = None;
+        self.annotations.clear();
+        self.selected_annotation = None;
+        self.annotation_drag = None;
+        self.drawing_annotation = false;
+    }
+    pub fn text_box_corners(&self, index: usize) -> Option<[(f32, f32); 4]> {
+        let text_box = self.text_boxes.get(index)?;
+        let data = self.font_data(&text_box.font)?;
+        Some(text_box.corners(&data))
+    }
+    // Select the top most box under the point and start dragging it
+    pub fn grab_text_box(&mut self, point: (f32, f32)) -> bool {
+        let hit = (0..self.text_boxes.len()).rev().find(|i| {
+            let text_box = &self.text_boxes[*i];
+            self.font_data(&text_box.font).is_some_and(|data| text_box.contains(&data, point))
+        });
+        if let Some(index) = hit {
+            let position = self.text_boxes[index].position;
+            self.selected_text_box = Some(index);
+            self.text_drag_offset = Some((point.0 - position.0, point.1 - position.1));
+        }
+        hit.is_some()
+    }
+    pub fn drag_text_box(&mut self, point: (f32, f32)) {
+        if let (Some(index), Some(offset)) = (self.selected_text_box, self.text_drag_offset) {
+            let mut text_box = self.text_boxes[index].clone();
+            text_box.position = (point.0 - offset.0, point.1 - offset.1);
+            self.update_text_box(index, text_box);
+        }
+    }
+    pub fn release_text_box(&mut self) {
+        self.text_drag_offset = None;
+    }
+    pub fn refresh_overlay_preview(&mut self) {
+        if self.overlay_base.is_none() {
+            self.overlay_base = Some(self.open_current_image().to_rgba8());
+        }
+        let mut img = self.overlay_base.clone().unwrap();
+        self.render_overlays(&mut img);
+        self.current_img_edited = Some(RetainedImage::from_color_image(
+            "overlays",
+            ColorImage::from_rgba_unmultiplied([img.width() as usize, img.height() as usize], img.as_raw()),
+        ).with_texture_filter(self.texture_filter()));
+    }
+    pub fn annotation_tool(&self) -> ShapeKind {
+        self.annotation_tool
+    }
+    pub fn set_annotation_tool(&mut self, annotation_tool: ShapeKind) {
+        self.annotation_tool = annotation_tool;
+    }
+    pub fn annotation_style(&self) -> &AnnotationStyle {
+        &self.annotation_style
+    }
+    // The style is used for new shapes and also changes the selected one
+    pub fn set_annotation_style(&mut self, annotation_style: AnnotationStyle) {
+        if self.annotation_style == annotation_style {
+            return;
+        }
+        self.annotation_style = annotation_style;
+        if let Some(index) = self.selected_annotation {
+            self.annotations[index].style = annotation_style;
+            self.refresh_overlay_preview();
+        }
+    }
+    pub fn annotations(&self) -> &Vec<Annotation> {
+        &self.annotations
+    }
+    pub fn selected_annotation(&self) -> Option<usize> {
+        self.selected_annotation
+    }
+    // Grab the shape under the pointer to move it, or start drawing a new one
+    pub fn begin_annotation(&mut self, point: (f32, f32), tolerance: f32) {
+        let hit = (0..self.annotations.len()).rev().find(|i| self.annotations[*i].contains(point, tolerance));
+        if let Some(index) = hit {
+            self.selected_annotation = Some(index);
+            self.annotation_style = self.annotations[index].style;
+            self.annotation_drag = Some(point);
+            return;
+        }
+        let number = self.annotations.iter().filter(|a| a.kind == ShapeKind::Marker).count() as u32 + 1;
+        self.annotations.push(Annotation::new(self.annotation_tool, point, self.annotation_style, number));
+        self.selected_annotation = Some(self.annotations.len() - 1);
+        self.drawing_annotation = true;
+        self.refresh_overlay_preview();
+    }
+    pub fn drag_annotation(&mut self, point: (f32, f32)) {
+        let index = match self.selected_annotation {
+            Some(index) => index,
+            None => return,
+        };
+        if self.drawing_annotation {
+            self.annotations[index].drag_to(point);
+        } else if let Some(last) = self.annotation_drag {
+            self.annotations[index].translate(point.0 - last.0, point.1 - last.1);
+            self.annotation_drag = Some(point);
+        } else {
+            return;
+        }
+        self.refresh_overlay_preview();
+    }
+    pub fn end_annotation(&mut self) {
+        if self.drawing_annotation {
+            if let Some(index) = self.selected_annotation {
+                if self.annotations[index].is_degenerate() {
+                    self.annotations.remove(index);
+                    self.selected_annotation = None;
+                    self.refresh_overlay_preview();
+                }
+            }
+        }
+        self.drawing_annotation = false;
+        self.annotation_drag = None;
+    }
+    pub fn remove_selected_annotation(&mut self) {
+        if let Some(index) = self.selected_annotation.take() {
+            self.annotations.remove(index);
+            self.refresh_overlay_preview();
+        }
+    }
+    pub fn redaction_style(&self) -> RedactionStyle {
+        self.redaction_style
+    }
+    pub fn set_redaction_style(&mut self, redaction_style: RedactionStyle) {
+        self.redaction_style = redaction_style;
+    }
+    pub fn redaction_block_size(&self) -> u32 {
+        self.redaction_block_size
+    }
+    pub fn set_redaction_block_size(&mut self, redaction_block_size: u32) {
+        self.redaction_block_size = redaction_block_size;
+    }
+    pub fn redaction_regions(&self) -> &Vec<Region> {
+        &self.redaction_regions
+    }
+    pub fn redaction_drag(&self) -> Option<Region> {
+        self.redaction_drag
+    }
+    pub fn start_redaction_region(&mut self, point: (f32, f32)) {
+        self.redaction_drag = Some((point, point));
+    }
+    pub fn extend_redaction_region(&mut self, point: (f32, f32)) {
+        if let Some((start, _)) = self.redaction_drag {
+            self.redaction_drag = Some((start, point));
+        }
+    }
+    // Keep the region if it covers at least one pixel and update the preview
+    pub fn finish_redaction_region(&mut self) {
+        if let Some((a, b)) = self.redaction_drag.take() {
+            let [width, height] = self.current_img_size().unwrap_or([0, 0]);
+            if redaction::region_bounds(width as u32, height as u32, a, b).is_some() {
+                self.redaction_regions.push((a, b));
+                self.set_current_img_edited_path(self.apply_redaction());
+            }
+        }
+    }
+    pub fn clear_redaction_regions(&mut self) {
+        self.redaction_regions.clear();
+        self.set_current_img_edited_path(self.current_img_path.clone());
+    }
+    pub fn layers(&self) -> &Vec<Layer> {
+        &self.layers
+    }
+    pub fn selected_layer(&self) -> Option<usize> {
+        self.selected_layer
+    }
+    pub fn select_layer(&mut self, selected_layer: Option<usize>) {
+        self.selected_layer = selected_layer;
+    }
+    pub fn layers_composite(&self) -> &Option<RetainedImage> {
+        &self.layers_composite
+    }
+    // New layers go on top, centered on the background
+    pub fn add_layer(&mut self, path: &Path) -> ImageResult<()> {
+        let mut layer = Layer::open(path)?;
+        if let Some([width, height]) = self.current_img_size() {
+            let (w, h) = layer.size();
+            layer.settings.position = (((width as f32 - w) / 2.0).round(), ((height as f32 - h) / 2.0).round());
+        }
+        self.layers.push(layer);
+        self.selected_layer = Some(self.layers.len() - 1);
+        self.refresh_layers_composite();
+        Ok(())
+    }
+    pub fn layer_message(&self) -> &Option<String> {
+        &self.layer_message
+    }
+    pub fn set_layer_message(&mut self, layer_message: Option<String>) {
+        self.layer_message = layer_message;
+    }
+    pub fn remove_layer(&mut self, index: usize) {
+        self.layers.remove(index);
+        self.selected_layer = None;
+        self.refresh_layers_composite();
+    }
+    // Swap the layer with the one above (up) or below it
+    pub fn move_layer(&mut self, index: usize, up: bool) {
+        let other = if up { index + 1 } else { index.wrapping_sub(1) };
+        if other >= self.layers.len() {
+            return;
+        }
+        self.layers.swap(index, other);
+        if self.selected_layer == Some(index) {
+            self.selected_layer = Some(other);
+        }
+        self.refresh_layers_composite();
+    }
+    pub fn set_layer_settings(&mut self, index: usize, settings: LayerSettings) {
+        if self.layers[index].settings != settings {
+            self.layers[index].settings = settings;
+            self.refresh_layers_composite();
+        }
+    }
+    pub fn clear_layers(&mut self) {
+        self.layers.clear();
+        self.selected_layer = None;
+        self.layer_drag = None;
+        self.layers_composite = None;
+    }
+    // Select the top most visible layer under the point and start moving it
+    pub fn grab_layer(&mut self, point: (f32, f32)) {
+        let hit = (0..self.layers.len()).rev().find(|i| {
+            self.layers[*i].settings.visible && self.layers[*i].contains(point)
+        });
+        self.selected_layer = hit;
+        self.layer_drag = hit.map(|_| point);
+    }
+    pub fn drag_layer(&mut self, point: (f32, f32)) {
+        if let (Some(index), Some(last)) = (self.selected_layer, self.layer_drag) {
+            let mut settings = self.layers[index].settings;
+            settings.position = (settings.position.0 + point.0 - last.0, settings.position.1 + point.1 - last.1);
+            self.layer_drag = Some(point);
+            self.set_layer_settings(index, settings);
+        }
+    }
+    pub fn release_layer(&mut self) {
+        self.layer_drag = None;
+    }
+    fn refresh_layers_composite(&mut self) {
+        if self.layers.is_empty() || self.current_img_path.is_none() {
+            self.layers_composite = None;
+            return;
+        }
+        let img = layers::composite(&self.open_current_image().to_rgba8(), &self.layers);
+        self.layers_composite = Some(RetainedImage::from_color_image(
+            "layers",
+            ColorImage::from_rgba_unmultiplied([img.width() as usize, img.height() as usize], img.as_raw()),
+        ).with_texture_filter(self.texture_filter()));
+    }
+    // Merge every layer into the background as a new version
+    pub fn flatten_layers(&mut self) {
+        if self.layers.is_empty() {
+            return;
+        }
+        let img = layers::composite(&self.open_current_image().to_rgba8(), &self.layers);
+        let file_path = self.get_outfile_pathname();
+        DynamicImage::ImageRgba8(img).save(&file_path).expect("Failed writing OUTFILE.");
+        self.clear_layers();
+        self.prepare_new_edition(Adjustment::FlattenLayers);
+        self.set_current_img_path(Some(file_path.clone()));
+        self.set_current_img_edited_path(Some(file_path));
+    }
+    pub fn adjustments(&self) -> &Vec<Adjustment> {
+        &self.adjustments
+    }
+    // The edit that "Apply Changes" would commit in the current state
+    pub fn current_adjustment(&self) -> Option<Adjustment> {
+        let adjustment = match self.state {
+            State::Waiting | State::Select | State::Paint => return None,
+            State::Convolution => Adjustment::Convolution { kernel: self.kernel.clone(), edge_mode: self.edge_mode },
+            State::Blur | State::Brighten | State::Contrast | State::Denoise | State::Sepia | State::Posterize
+                | State::Threshold | State::Pixelate | State::Vignette | State::FilmGrain => Adjustment::Effects { params: self.params },
+            State::Channels => Adjustment::Channels {
+                operation: self.channel_operation,
+                channel: self.channel,
+                swap_channel: self.swap_channel,
+                mixer: self.channel_mixer.clone(),
+            },
+            State::Transparency => Adjustment::Transparency {
+                operation: self.transparency_operation,
+                opacity: self.opacity,
+                background: self.background_color,
+                key_color: self.key_color,
+                tolerance: self.key_tolerance,
+            },
+            State::Text => Adjustment::Text { text_boxes: self.text_boxes.clone() },
+            State::Shapes => Adjustment::Annotate { annotations: self.annotations.clone() },
+            State::Redact => Adjustment::Redact {
+                style: self.redaction_style,
+                block_size: self.redaction_block_size,
+                regions: self.redaction_regions.clone(),
+            },
+            State::Operation(index) => Adjustment::Operation {
+                name: self.operations.get(index)?.name().to_string(),
+                values: self.operation_values.clone(),
+            },
+        };
+        Some(adjustment)
+    }
+    pub fn undo_adjustment(&mut self) {
+        if let Some(adjustment) = self.adjustments.pop() {
+            self.undone_adjustments.push(adjustment);
+        }
+    }
+    pub fn redo_adjustment(&mut self) {
+        if let Some(adjustment) = self.undone_adjustments.pop() {
+            self.adjustments.push(adjustment);
+        }
+    }
+    pub fn clear_adjustments(&mut self) {
+        self.adjustments.clear();
+        self.undone_adjustments.clear();
+    }
+    pub fn project_message(&self) -> &Option<String> {
+        &self.project_message
+    }
+    pub fn set_project_message(&mut self, project_message: Option<String>) {
+        self.project_message = project_message;
+    }
+    fn session_project(&self) -> Project {
+        Project {
+            image_name: self.initial_image_name().unwrap(),
+            original: self.initial_image_path.clone().unwrap(),
+            current: self.current_img_path.clone().unwrap(),
+            history: self.versions.iter().flatten().cloned().collect(),
+            redo: self.versions_discart.iter().flatten().cloned().collect(),
+            adjustments: self.adjustments.clone(),
+            undone_adjustments: self.undone_adjustments.clone(),
+            layers: self.layers.clone(),
+            selection: self.selection.clone(),
+            annotations: self.annotations.clone(),
+            text_boxes: self.text_boxes.clone(),
+        }
+    }
+    pub fn save_project(&self, path: &Path) -> io::Result<()> {
+        project::save(path, &self.session_project())
+    }
+    // Projects keep the whole history, so a redaction can be undone by whoever opens the file
+    pub fn has_redactions(&self) -> bool {
+        project::has_redactions(&self.adjustments)
+    }
+    // Replace the session with the one stored in the project
+    pub fn open_project(&mut self, path: &Path) -> io::Result<()> {
+        let project = project::load(path, self.temp_dir.path(), &self.operations)?;
+        self.export_path = None;
+        self.set_initial_image_path(Some(project.original));
+        self.set_current_img_path(Some(project.current.clone()));
+        self.set_current_img_edited_path(Some(project.current));
+        self.versions = project.history.into_iter().map(Some).collect();
+        self.versions_discart = project.redo.into_iter().map(Some).collect();
+        self.state = State::Waiting;
+        self.clear_effects_values();
+        self.reset_view();
+        self.clear_layers();
+        self.layers = project.layers;
+        self.adjustments = project.adjustments;
+        self.undone_adjustments = project.undone_adjustments;
+        self.selection = project.selection;
+        self.refresh_selection_overlay();
+        self.refresh_layers_composite();
+        // Pending shapes and text come back editable in their own mode
+        if !project.text_boxes.is_empty() {
+            self.state = State::Text;
+            self.load_fonts();
+        } else if !project.annotations.is_empty() {
+            self.state = State::Shapes;
+        }
+        self.text_boxes = project.text_boxes;
+        self.annotations = project.annotations;
+        if self.state != State::Waiting {
+            self.refresh_overlay_preview();
+        }
+        Ok(())
+    }
+    // Called every frame, writes the session in the background once the interval has passed
+    pub fn autosave(&mut self) {
+        if self.autosave_job.as_ref().is_some_and(|job| job.is_finished()) {
+            let result = self.autosave_job.take().unwrap().join().expect("Autosave thread panicked");
+            if let Err(err) = result {
+                self.project_message = Some(format!("Could not autosave: {}", err));
+            }
+        }
+        if self.autosave_job.is_some() || self.current_img_path.is_none() || self.last_autosave.elapsed() < AUTOSAVE_INTERVAL {
+            return;
+        }
+        if let Some(path) = self.autosave_path.clone() {
+            // Autosaves go to a folder the user never picked, so redacted pixels must not end up there
+            let mut project = self.session_project();
+            if project.has_redactions() {
+                project.drop_unredacted();
+            }
+            self.autosave_job = Some(thread::spawn(move || autosave::save(&path, &project)));
+        }
+        self.last_autosave = Instant::now();
+    }
+    // On a clean exit the session is not needed anymore
+    pub fn discard_autosave(&mut self) {
+        if let Some(job) = self.autosave_job.take() {
+            let _ = job.join();
+        }
+        if let Some(path) = &self.autosave_path {
+            autosave::discard(path);
+        }
+    }
+    pub fn unsaved_session(&self) -> &Option<PathBuf> {
+        &self.unsaved_session
+    }
+    pub fn restore_unsaved_session(&mut self) {
+        if let Some(path) = self.unsaved_session.take() {
+            match self.open_project(&path) {
+                Ok(()) => autosave::discard(&path),
+                Err(err) => self.project_message = Some(format!("Could not restore session: {}", err)),
+            }
+        }
+    }
+    pub fn discard_unsaved_session(&mut self) {
+        if let Some(path) = self.unsaved_session.take() {
+            autosave::discard(&path);
+        }
+    }
+    // Start a new session from an image file
+    pub fn open_image(&mut self, path: PathBuf) {
+        self.recent_files.add(&path);
+        self.export_path = None;
+        self.set_initial_image_path(Some(path.clone()));
+        self.set_current_img_path(Some(path.clone()));
+        self.set_current_img_edited_path(Some(path));
+        self.reset_version_number();
+        self.set_state(State::Waiting);
+        self.clear_effects_values();
+        self.deselect();
+        self.reset_view();
+        self.clear_layers();
+        self.clear_adjustments();
+    }
+    pub fn recent_files(&self) -> &RecentFiles {
+        &self.recent_files
+    }
+    pub fn recent_files_mut(&mut self) -> &mut RecentFiles {
+        &mut self.recent_files
+    }
+    // Read what was stored by the previous run, and reopen the last image if asked to
+    pub fn load_storage(&mut self, storage: &dyn eframe::Storage) {
+        if let Some(recent_files) = eframe::get_value(storage, RECENT_FILES_KEY) {
+            self.recent_files = recent_files;
+        }
+        if let Some(preferences) = eframe::get_value(storage, PREFERENCES_KEY) {
+            self.preferences = preferences;
+        }
+        self.recent_files.prune();
+        if self.recent_files.reopen_last && self.unsaved_session.is_none() {
+            if let Some(path) = self.recent_files.last().cloned() {
+                self.open_image(path);
+            }
+        }
+    }
+    pub fn save_storage(&self, storage: &mut dyn eframe::Storage) {
+        eframe::set_value(storage, RECENT_FILES_KEY, &self.recent_files);
+        eframe::set_value(storage, PREFERENCES_KEY, &self.preferences);
+    }
+    pub fn preferences(&self) -> &Preferences {
+        &self.preferences
+    }
+    pub fn set_preferences(&mut self, preferences: Preferences) {
+        if self.preferences == preferences {
+            return;
+        }
+        let filter_changed = self.preferences.preview_quality != preferences.preview_quality;
+        self.preferences = preferences;
+        self.preferences_changed = true;
+        // Images already on screen were uploaded with the previous filter
+        if filter_changed && self.current_img_path.is_some() {
+            self.set_current_img_path(self.current_img_path.clone());
+            match self.state {
+                State::Text | State::Shapes => self.refresh_overlay_preview(),
+                _ => self.set_current_img_edited_path(self.current_img_edited_path.clone()),
+            }
+            self.refresh_selection_overlay();
+        }
+    }
+    // True once after the preferences changed, so they are only pushed to egui when needed
+    pub fn take_preferences_changed(&mut self) -> bool {
+        std::mem::take(&mut self.preferences_changed)
+    }
+    fn texture_filter(&self) -> TextureFilter {
+        self.preferences.preview_quality.texture_filter()
+    }
+    pub fn settings_open(&self) -> bool {
+        self.settings_open
+    }
+    pub fn set_settings_open(&mut self, settings_open: bool) {
+        self.settings_open = settings_open;
+    }
+    // Where Save writes without asking, set by the last Save As
+    pub fn export_path(&self) -> &Option<PathBuf> {
+        &self.export_path
+    }
+    pub fn set_export_path(&mut self, export_path: Option<PathBuf>) {
+        self.export_path = export_path;
+    }
+    // Action waiting for a key press in the keymap editor
+    pub fn rebinding(&self) -> Option<Action> {
+        self.rebinding
+    }
+    pub fn set_rebinding(&mut self, rebinding: Option<Action>) {
+        self.rebinding = rebinding;
+    }
+    pub fn shortcuts_open(&self) -> bool {
+        self.shortcuts_open
+    }
+    pub fn set_shortcuts_open(&mut self, shortcuts_open: bool) {
+        self.shortcuts_open = shortcuts_open;
+    }
+    pub fn presets(&self) -> &Vec<(PathBuf, Preset)> {
+        &self.presets
+    }
+    pub fn preset_name(&self) -> &String {
+        &self.preset_name
+    }
+    pub fn set_preset_name(&mut self, preset_name: String) {
+        self.preset_name = preset_name;
+    }
+    pub fn preset_message(&self) -> &Option<String> {
+        &self.preset_message
+    }
+    pub fn set_preset_message(&mut self, preset_message: Option<String>) {
+        self.preset_message = preset_message;
+    }
+    // Keep a preset in the presets folder, replacing the one stored under the same file name
+    fn add_preset(&mut self, preset: Preset, extension: &str) -> io::Result<()> {
+        let dir = preset::presets_dir()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No folder to keep presets in"))?;
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(preset::file_name(&preset.name, extension));
+        preset::save(&path, &preset)?;
+        self.presets.retain(|(p, _)| *p != path);
+        self.presets.push((path, preset));
+        self.presets.sort_by_key(|(_, preset)| preset.name.to_lowercase());
+        self.clear_preset_thumbnails();
+        Ok(())
+    }
+    // Record the edits committed in this session under the typed name
+    pub fn save_preset(&mut self) -> io::Result<()> {
+        let preset = Preset::record(self.preset_name.trim(), &self.adjustments);
+        self.add_preset(preset, "json")?;
+        self.preset_name.clear();
+        Ok(())
+    }
+    pub fn import_preset(&mut self, path: &Path) -> io::Result<()> {
+        let preset = preset::load(path)?;
+        preset.validate(&self.operations).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+        let extension = if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("toml")) { "toml" } else { "json" };
+        self.add_preset(preset, extension)
+    }
+    pub fn export_preset(&self, index: usize, path: &Path) -> io::Result<()> {
+        preset::save(path, &self.presets[index].1)
+    }
+    pub fn delete_preset(&mut self, index: usize) -> io::Result<()> {
+        fs::remove_file(&self.presets[index].0)?;
+        self.presets.remove(index);
+        self.clear_preset_thumbnails();
+        Ok(())
+    }
+    // Replay the preset on the current image as a new version. A preset with an edit
+    // that can't be applied leaves the image as it is and tells why
+    pub fn apply_preset(&mut self, index: usize) {
+        let preset = self.presets[index].1.clone();
+        if preset.uses_fonts() {
+            self.load_fonts();
+        }
+        let img = self.open_current_image();
+        let new_img = match ops::apply_preset(img.clone(), &preset, &self.fonts, &self.operations) {
+            Ok(new_img) => new_img,
+            Err(err) => {
+                self.preset_message = Some(format!("Could not apply {}: {}", preset.name, err));
+                return;
+            },
+        };
+        self.preset_message = None;
+        // Keep the preset inside the selection
+        let new_img = self.restrict_to_selection(&img, new_img);
+        let file_path = self.get_outfile_pathname();
+        new_img.save(&file_path).expect("Failed writing OUTFILE.");
+        self.prepare_new_edition(preset.to_adjustment());
+        self.set_current_img_path(Some(file_path.clone()));
+        self.set_current_img_edited_path(Some(file_path));
+    }
+    // The presets are replayed on a small copy of the current image, in the background
+    // since a preset may hold a long script. None until they are ready
+    pub fn preset_thumbnail(&mut self, index: usize) -> Option<&RetainedImage> {
+        let source = self.current_img_path.clone()?;
+        if self.preset_thumbnails_source != self.current_img_path {
+            self.clear_preset_thumbnails();
+            self.preset_thumbnails_source = Some(source.clone());
+        }
+        if self.preset_thumbnails_job.as_ref().is_some_and(|job| job.is_finished()) {
+            let images = self.preset_thumbnails_job.take().unwrap().join().expect("Preset thumbnails thread panicked");
+            let filter = self.texture_filter();
+            self.preset_thumbnails = self.presets
+                .iter()
+                .zip(images)
+                .map(|((path, _), img)| {
+                    RetainedImage::from_color_image(
+                        path.display().to_string(),
+                        ColorImage::from_rgba_unmultiplied([img.width() as usize, img.height() as usize], img.as_raw()),
+                    ).with_texture_filter(filter)
+                })
+                .collect();
+        }
+        if self.preset_thumbnails.len() != self.presets.len() && self.preset_thumbnails_job.is_none() {
+            let presets: Vec<Preset> = self.presets.iter().map(|(_, preset)| preset.clone()).collect();
+            let (fonts, operations) = (self.fonts.clone(), self.operations.clone());
+            self.preset_thumbnails_job = Some(thread::spawn(move || {
+                let thumbnail = image::open(&source).expect("Failed to open INFILE.").thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE);
+                presets
+                    .iter()
+                    .map(|preset| {
+                        ops::apply_preset(thumbnail.clone(), preset, &fonts, &operations)
+                            .unwrap_or_else(|_| thumbnail.clone())
+                            .to_rgba8()
+                    })
+                    .collect()
+            }));
+        }
+        self.preset_thumbnails.get(index)
+    }
+    pub fn preset_thumbnails_running(&self) -> bool {
+        self.preset_thumbnails_job.is_some()
+    }
+    // A running job is left to finish on its own, its thumbnails are not wanted anymore
+    fn clear_preset_thumbnails(&mut self) {
+        self.preset_thumbnails.clear();
+        self.preset_thumbnails_job = None;
+    }
+    pub fn batch_open(&self) -> bool {
+        self.batch_open
+    }
+    pub fn set_batch_open(&mut self, batch_open: bool) {
+        self.batch_open = batch_open;
+    }
+    pub fn batch_settings(&self) -> &BatchSettings {
+        &self.batch_settings
+    }
+    pub fn set_batch_settings(&mut self, batch_settings: BatchSettings) {
+        self.batch_settings = batch_settings;
+    }
+    pub fn batch(&self) -> &Option<Batch> {
+        &self.batch
+    }
+    // The edits the batch would apply, None if the picked preset is gone
+    pub fn batch_preset(&self) -> Option<Preset> {
+        match &self.batch_settings.source {
+            BatchSource::Session => Some(Preset::record("Session", &self.adjustments)),
+            BatchSource::Preset(path) => self.presets.iter().find(|(p, _)| p == path).map(|(_, preset)| preset.clone()),
+        }
+    }
+    pub fn start_batch(&mut self) {
+        let (Some(preset), Some(output_dir)) = (self.batch_preset(), self.batch_settings.output_dir.clone()) else {
+            return;
+        };
+        if preset.uses_fonts() {
+            self.load_fonts();
+        }
+        self.batch = Some(Batch::start(
+            &self.batch_settings,
+            output_dir,
+            preset,
+            self.fonts.clone(),
+            self.operations.clone(),
+            self.preferences.export_quality,
+        ));
+    }
+    pub fn poll_batch(&mut self) {
+        if let Some(batch) = &mut self.batch {
+            batch.poll();
+        }
+    }
+    pub fn cancel_batch(&mut self) {
+        if let Some(batch) = &self.batch {
+            batch.cancel();
+        }
+    }
+    pub fn operations(&self) -> &Registry {
+        &self.operations
+    }
+    pub fn operation_values(&self) -> &Vec<ParamValue> {
+        &self.operation_values
+    }
+    pub fn set_operation_values(&mut self, operation_values: Vec<ParamValue>) {
+        self.operation_values = operation_values;
+    }
+    // Every parameter back to its default, for the filter of the current mode
+    pub fn reset_operation_values(&mut self) {
+        self.operation_values = match self.state {
+            State::Operation(index) => self.operations.get(index).map(|operation| registry::default_values(operation.as_ref())).unwrap_or_default(),
+            _ => Vec::new(),
+        };
+    }
+    pub fn operation_message(&self) -> &Option<String> {
+        &self.operation_message
+    }
+    pub fn plugin_errors(&self) -> &Vec<String> {
+        &self.plugin_errors
+    }
+    // Pick up the plugins added to the folder since launch
+    pub fn reload_plugins(&mut self) {
+        (self.operations, self.plugin_errors) = load_operations();
+        self.clear_preset_thumbnails();
+    }
+    pub fn script_open(&self) -> bool {
+        self.script_open
+    }
+    pub fn set_script_open(&mut self, script_open: bool) {
+        self.script_open = script_open;
+    }
+    pub fn script_name(&self) -> &String {
+        &self.script_name
+    }
+    pub fn set_script_name(&mut self, script_name: String) {
+        self.script_name = script_name;
+    }
+    pub fn script_source(&self) -> &String {
+        &self.script_source
+    }
+    pub fn set_script_source(&mut self, script_source: String) {
+        self.script_source = script_source;
+    }
+    pub fn script_log(&self) -> &Vec<String> {
+        &self.script_log
+    }
+    pub fn log_script(&mut self, line: String) {
+        self.script_log.push(line);
+    }
+    pub fn clear_script_log(&mut self) {
+        self.script_log.clear();
+    }
+    pub fn scripts(&self) -> &Vec<PathBuf> {
+        &self.scripts
+    }
+    // Keep the script of the console in the presets folder, under its name
+    pub fn save_script(&mut self) -> io::Result<()> {
+        let dir = preset::presets_dir()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No folder to keep scripts in"))?;
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(preset::file_name(self.script_name.trim(), script::SCRIPT_EXTENSION));
+        fs::write(&path, &self.script_source)?;
+        self.script_log.push(format!("Saved {}", path.display()));
+        if !self.scripts.contains(&path) {
+            self.scripts.push(path);
+            self.scripts.sort();
+        }
+        Ok(())
+    }
+    // Load a script into the console, from the presets folder or anywhere else
+    pub fn open_script(&mut self, path: &Path) -> io::Result<()> {
+        self.script_source = fs::read_to_string(path)?;
+        self.script_name = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+        self.script_open = true;
+        Ok(())
+    }
+    pub fn delete_script(&mut self, index: usize) -> io::Result<()> {
+        fs::remove_file(&self.scripts[index])?;
+        self.scripts.remove(index);
+        Ok(())
+    }
+    // Run the script of the console on the current image, what it leaves in `image` becomes
+    // a new version. Without an open image the script can still open and save files
+    pub fn run_script(&mut self) {
+        if self.script_job.is_some() {
+            return;
+        }
+        let source = self.current_img_path.clone();
+        let name = match self.script_name.trim() {
+            "" => String::from("Script"),
+            name => name.to_string(),
+        };
+        let script = self.script_source.clone();
+        let (path, code) = (source.clone(), script.clone());
+        let (fonts, operations) = (self.fonts.clone(), self.operations.clone());
+        let handle = thread::spawn(move || {
+            let img = path.map(|path| image::open(path).expect("Failed to open INFILE."));
+            let mut log = Vec::new();
+            let result = script::run(&code, img.clone(), &fonts, &operations, FileAccess::Allowed, &mut log);
+            (img, result, log)
+        });
+        self.script_job = Some(ScriptJob { source, name, script, handle });
+    }
+    pub fn script_running(&self) -> bool {
+        self.script_job.is_some()
+    }
+    // Called every frame, picks up the result of the script once it is done
+    pub fn poll_script(&mut self) {
+        if !self.script_job.as_ref().is_some_and(|job| job.handle.is_finished()) {
+            return;
+        }
+        let job = self.script_job.take().unwrap();
+        let (img, result, mut log) = job.handle.join().expect("Script thread panicked");
+        self.script_log.append(&mut log);
+        let new_img = match result {
+            Ok(new_img) => new_img,
+            Err(err) => {
+                self.script_log.push(err.to_string());
+                return;
+            },
+        };
+        let (Some(img), Some(new_img)) = (img, new_img) else {
+            return;
+        };
+        if self.current_img_path != job.source {
+            self.script_log.push(String::from("The image changed while the script ran, its result was not kept"));
+            return;
+        }
+        // Keep the script inside the selection, unless it changed the size of the image
+        let new_img = self.restrict_to_selection(&img, new_img);
+        let file_path = self.get_outfile_pathname();
+        new_img.save(&file_path).expect("Failed writing OUTFILE.");
+        self.prepare_new_edition(Adjustment::Script { name: job.name, source: job.script });
+        self.set_current_img_path(Some(file_path.clone()));
+        self.set_current_img_edited_path(Some(file_path));
+    }
 }
 
-
+// The in-house filters and the plugins, with the errors of the plugins that didn't load
+pub fn load_operations() -> (Registry, Vec<String>) {
+    let mut operations = Registry::with_builtins();
+    let errors = plugin::plugins_dir()
+        .map(|dir| plugin::register_plugins(&mut operations, &dir))
+        .unwrap_or_default();
+    (operations, errors)
+}
@@ -0,0 +1,248 @@
+use eframe::egui::{Key, Modifiers};
+use serde::{Deserialize, Serialize};
+use crate::image_editor::State;
+
+#[derive(PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum Action {
+    Open,
+    Save,
+    SaveAs,
+    Undo,
+    Redo,
+    Apply,
+    Cancel,
+    Shortcuts,
+    Blur,
+    Brighten,
+    Contrast,
+    Flip,
+    Grayscale,
+    Invert,
+    Rotate,
+    Convolution,
+    Denoise,
+    Sepia,
+    Posterize,
+    Threshold,
+    Pixelate,
+    Vignette,
+    FilmGrain,
+    Channels,
+    Transparency,
+    Select,
+    Paint,
+    Text,
+    Shapes,
+    Redact,
+}
+
+impl Action {
+    pub const ALL: [Action; 30] = [
+        Action::Open,
+        Action::Save,
+        Action::SaveAs,
+        Action::Undo,
+        Action::Redo,
+        Action::Apply,
+        Action::Cancel,
+        Action::Shortcuts,
+        Action::Blur,
+        Action::Brighten,
+        Action::Contrast,
+        Action::Flip,
+        Action::Grayscale,
+        Action::Invert,
+        Action::Rotate,
+        Action::Convolution,
+        Action::Denoise,
+        Action::Sepia,
+        Action::Posterize,
+        Action::Threshold,
+        Action::Pixelate,
+        Action::Vignette,
+        Action::FilmGrain,
+        Action::Channels,
+        Action::Transparency,
+        Action::Select,
+        Action::Paint,
+        Action::Text,
+        Action::Shapes,
+        Action::Redact,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::Open => "Open file",
+            Action::Save => "Save",
+            Action::SaveAs => "Save as",
+            Action::Undo => "Undo",
+            Action::Redo => "Redo",
+            Action::Apply => "Apply changes",
+            Action::Cancel => "Cancel",
+            Action::Shortcuts => "Show shortcuts",
+            Action::Blur => "Blur",
+            Action::Brighten => "Brighten",
+            Action::Contrast => "Contrast",
+            Action::Flip => "Flip",
+            Action::Grayscale => "Grayscale",
+            Action::Invert => "Invert",
+            Action::Rotate => "Rotate",
+            Action::Convolution => "Convolution",
+            Action::Denoise => "Denoise",
+            Action::Sepia => "Sepia",
+            Action::Posterize => "Posterize",
+            Action::Threshold => "Threshold",
+            Action::Pixelate => "Pixelate",
+            Action::Vignette => "Vignette",
+            Action::FilmGrain => "Film Grain",
+            Action::Channels => "Channels",
+            Action::Transparency => "Transparency",
+            Action::Select => "Select",
+            Action::Paint => "Paint",
+            Action::Text => "Text",
+            Action::Shapes => "Shapes",
+            Action::Redact => "Redact",
+        }
+    }
+
+    // The editing mode the action switches to, None for the other actions
+    pub fn state(&self) -> Option<State> {
+        match self {
+            Action::Blur => Some(State::Blur),
+            Action::Brighten => Some(State::Brighten),
+            Action::Contrast => Some(State::Contrast),
+            Action::Convolution => Some(State::Convolution),
+            Action::Denoise => Some(State::Denoise),
+            Action::Sepia => Some(State::Sepia),
+            Action::Posterize => Some(State::Posterize),
+            Action::Threshold => Some(State::Threshold),
+            Action::Pixelate => Some(State::Pixelate),
+            Action::Vignette => Some(State::Vignette),
+            Action::FilmGrain => Some(State::FilmGrain),
+            Action::Channels => Some(State::Channels),
+            Action::Transparency => Some(State::Transparency),
+            Action::Select => Some(State::Select),
+            Action::Paint => Some(State::Paint),
+            Action::Text => Some(State::Text),
+            Action::Shapes => Some(State::Shapes),
+            Action::Redact => Some(State::Redact),
+            _ => None,
+        }
+    }
+
+    // Filters of the operation registry, by name, for the actions that open one
+    pub fn operation(&self) -> Option<&'static str> {
+        match self {
+            Action::Flip => Some("Flip"),
+            Action::Grayscale => Some("Grayscale"),
+            Action::Invert => Some("Invert"),
+            Action::Rotate => Some("Rotate"),
+            _ => None,
+        }
+    }
+}
+
+// Ctrl stands for Cmd on macOS
+#[derive(PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub struct Shortcut {
+    pub key: Key,
+    pub ctrl: bool,
+    pub shift: bool,
+}
+
+impl Shortcut {
+    pub const fn new(key: Key) -> Self {
+        Self { key, ctrl: false, shift: false }
+    }
+
+    pub const fn ctrl(key: Key) -> Self {
+        Self { key, ctrl: true, shift: false }
+    }
+
+    pub const fn ctrl_shift(key: Key) -> Self {
+        Self { key, ctrl: true, shift: true }
+    }
+
+    pub fn from_event(key: Key, modifiers: Modifiers) -> Self {
+        Self { key, ctrl: modifiers.command, shift: modifiers.shift }
+    }
+
+    pub fn modifiers(&self) -> Modifiers {
+        Modifiers { command: self.ctrl, shift: self.shift, ..Default::default() }
+    }
+
+    pub fn label(&self) -> String {
+        let mut label = String::new();
+        if self.ctrl {
+            label.push_str("Ctrl+");
+        }
+        if self.shift {
+            label.push_str("Shift+");
+        }
+        label.push_str(&format!("{:?}", self.key));
+        label
+    }
+}
+
+// Every action has at most one shortcut and a shortcut triggers at most one action
+#[derive(PartialEq, Clone, Serialize, Deserialize)]
+pub struct Keymap {
+    bindings: Vec<(Action, Shortcut)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self {
+            bindings: vec![
+                (Action::Open, Shortcut::ctrl(Key::O)),
+                (Action::Save, Shortcut::ctrl(Key::S)),
+                (Action::SaveAs, Shortcut::ctrl_shift(Key::S)),
+                (Action::Undo, Shortcut::ctrl(Key::Z)),
+                (Action::Redo, Shortcut::ctrl(Key::Y)),
+                (Action::Apply, Shortcut::new(Key::Enter)),
+                (Action::Cancel, Shortcut::new(Key::Escape)),
+                (Action::Shortcuts, Shortcut::new(Key::F1)),
+                (Action::Blur, Shortcut::new(Key::U)),
+                (Action::Brighten, Shortcut::new(Key::L)),
+                (Action::Contrast, Shortcut::new(Key::C)),
+                (Action::Flip, Shortcut::new(Key::F)),
+                (Action::Grayscale, Shortcut::new(Key::G)),
+                (Action::Invert, Shortcut::new(Key::I)),
+                (Action::Rotate, Shortcut::new(Key::R)),
+                (Action::Convolution, Shortcut::new(Key::K)),
+                (Action::Denoise, Shortcut::new(Key::D)),
+                (Action::Sepia, Shortcut::new(Key::E)),
+                (Action::Posterize, Shortcut::new(Key::P)),
+                (Action::Threshold, Shortcut::new(Key::H)),
+                (Action::Pixelate, Shortcut::new(Key::X)),
+                (Action::Vignette, Shortcut::new(Key::V)),
+                (Action::FilmGrain, Shortcut::new(Key::N)),
+                (Action::Channels, Shortcut::new(Key::M)),
+                (Action::Transparency, Shortcut::new(Key::O)),
+                (Action::Select, Shortcut::new(Key::S)),
+                (Action::Paint, Shortcut::new(Key::B)),
+                (Action::Text, Shortcut::new(Key::T)),
+                (Action::Shapes, Shortcut::new(Key::A)),
+                (Action::Redact, Shortcut::new(Key::Q)),
+            ],
+        }
+    }
+}
+
+impl Keymap {
+    pub fn bindings(&self) -> &Vec<(Action, Shortcut)> {
+        &self.bindings
+    }
+
+    pub fn shortcut(&self, action: Action) -> Option<Shortcut> {
+        self.bindings.iter().find(|(a, _)| *a == action).map(|(_, shortcut)| *shortcut)
+    }
+
+    // Binding a shortcut takes it away from the action that had it before
+    pub fn bind(&mut self, action: Action, shortcut: Option<Shortcut>) {
+        self.bindings.retain(|(a, s)| *a != action && Some(*s) != shortcut);
+        if let Some(shortcut) = shortcut {
+            self.bindings.push((action, shortcut));
+        }
+    }
+}
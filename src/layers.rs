@@ -0,0 +1,162 @@
+use std::ops::RangeInclusive;
+use std::path::Path;
+use image::{imageops, ImageResult, Rgba, RgbaImage};
+use image::imageops::FilterType;
+use serde::{Deserialize, Serialize};
+
+// A layer is resized to its scale on every composite
+pub const SCALE_RANGE: RangeInclusive<f32> = 0.05..=4.0;
+
+#[derive(PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum BlendMode {
+    Normal,
+    Multiply,
+    Screen,
+    Overlay,
+    Darken,
+    Lighten,
+    Difference,
+}
+
+impl BlendMode {
+    pub const ALL: [BlendMode; 7] = [
+        BlendMode::Normal,
+        BlendMode::Multiply,
+        BlendMode::Screen,
+        BlendMode::Overlay,
+        BlendMode::Darken,
+        BlendMode::Lighten,
+        BlendMode::Difference,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BlendMode::Normal => "Normal",
+            BlendMode::Multiply => "Multiply",
+            BlendMode::Screen => "Screen",
+            BlendMode::Overlay => "Overlay",
+            BlendMode::Darken => "Darken",
+            BlendMode::Lighten => "Lighten",
+            BlendMode::Difference => "Difference",
+        }
+    }
+
+    // Both channels go from 0 to 1
+    fn blend(&self, base: f32, top: f32) -> f32 {
+        match self {
+            BlendMode::Normal => top,
+            BlendMode::Multiply => base * top,
+            BlendMode::Screen => base + top - base * top,
+            BlendMode::Overlay => {
+                if base <= 0.5 { 2.0 * base * top } else { 1.0 - 2.0 * (1.0 - base) * (1.0 - top) }
+            },
+            BlendMode::Darken => base.min(top),
+            BlendMode::Lighten => base.max(top),
+            BlendMode::Difference => (base - top).abs(),
+        }
+    }
+}
+
+// Position is the top left corner in background pixels, opacity goes from 0 to 100
+#[derive(PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub struct LayerSettings {
+    pub position: (f32, f32),
+    pub scale: f32,
+    pub opacity: f32,
+    pub blend_mode: BlendMode,
+    pub visible: bool,
+}
+
+impl Default for LayerSettings {
+    fn default() -> Self {
+        Self {
+            position: (0.0, 0.0),
+            scale: 1.0,
+            opacity: 100.0,
+            blend_mode: BlendMode::Normal,
+            visible: true,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Layer {
+    pub name: String,
+    image: RgbaImage,
+    pub settings: LayerSettings,
+}
+
+impl Layer {
+    pub fn new(name: String, image: RgbaImage) -> Self {
+        Self { name, image, settings: LayerSettings::default() }
+    }
+
+    pub fn open(path: &Path) -> ImageResult<Layer> {
+        let image = image::open(path)?.to_rgba8();
+        let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        Ok(Layer::new(name, image))
+    }
+
+    pub fn image(&self) -> &RgbaImage {
+        &self.image
+    }
+
+    // Size on the background once scaled
+    pub fn size(&self) -> (f32, f32) {
+        (self.image.width() as f32 * self.settings.scale, self.image.height() as f32 * self.settings.scale)
+    }
+
+    pub fn contains(&self, point: (f32, f32)) -> bool {
+        let (w, h) = self.size();
+        let (x, y) = self.settings.position;
+        point.0 >= x && point.1 >= y && point.0 < x + w && point.1 < y + h
+    }
+}
+
+// Draw the visible layers over the background, first layer at the bottom
+pub fn composite(background: &RgbaImage, layers: &[Layer]) -> RgbaImage {
+    let mut out = background.clone();
+    for layer in layers.iter().filter(|layer| layer.settings.visible) {
+        let (w, h) = layer.size();
+        let (w, h) = (w.round() as u32, h.round() as u32);
+        if w == 0 || h == 0 {
+            continue;
+        }
+        let scaled;
+        let image = if (w, h) == layer.image.dimensions() {
+            &layer.image
+        } else {
+            scaled = imageops::resize(&layer.image, w, h, FilterType::Triangle);
+            &scaled
+        };
+        let (x0, y0) = (layer.settings.position.0.round() as i64, layer.settings.position.1.round() as i64);
+        let opacity = (layer.settings.opacity / 100.0).clamp(0.0, 1.0);
+        for (lx, ly, top) in image.enumerate_pixels() {
+            let (x, y) = (x0 + lx as i64, y0 + ly as i64);
+            if x < 0 || y < 0 || x >= out.width() as i64 || y >= out.height() as i64 {
+                continue;
+            }
+            let base = out.get_pixel_mut(x as u32, y as u32);
+            *base = blend_pixel(*base, *top, opacity, layer.settings.blend_mode);
+        }
+    }
+    out
+}
+
+// Separable blend followed by source over, where the blended color only shows
+// as much as the base pixel is opaque
+fn blend_pixel(base: Rgba<u8>, top: Rgba<u8>, opacity: f32, mode: BlendMode) -> Rgba<u8> {
+    let top_alpha = top[3] as f32 / 255.0 * opacity;
+    if top_alpha <= 0.0 {
+        return base;
+    }
+    let base_alpha = base[3] as f32 / 255.0;
+    let alpha = top_alpha + base_alpha * (1.0 - top_alpha);
+    let mix = |c: usize| {
+        let (b, t) = (base[c] as f32 / 255.0, top[c] as f32 / 255.0);
+        let blended = (1.0 - base_alpha) * t + base_alpha * mode.blend(b, t);
+        let color = (top_alpha * blended + base_alpha * b * (1.0 - top_alpha)) / alpha;
+        (color * 255.0).round().clamp(0.0, 255.0) as u8
+    };
+    Rgba([mix(0), mix(1), mix(2), (alpha * 255.0).round() as u8])
+}
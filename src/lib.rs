@@ -0,0 +1,51 @@
+// The editing engine, without any window. Images go in as `DynamicImage` and come
+// back edited or with an `Error`, the checked entry points below are the API
+pub use adjustment::Adjustment;
+pub use operation::{Operation, ParamKind, ParamSpec, ParamValue, Registry};
+pub use ops::{apply, apply_adjustment, apply_preset, validate_adjustment, validate_params, Error, Result};
+pub use params::Params;
+pub use preset::Preset;
+pub use text::FontEntry;
+
+mod filters;
+mod healing;
+
+// What the editor window is built on. Not part of the API, it changes with the window
+#[doc(hidden)]
+pub mod adjustment;
+#[doc(hidden)]
+pub mod annotation;
+#[doc(hidden)]
+pub mod artistic;
+#[doc(hidden)]
+pub mod channels;
+#[doc(hidden)]
+pub mod convolution;
+#[doc(hidden)]
+pub mod denoise;
+#[doc(hidden)]
+pub mod layers;
+#[doc(hidden)]
+pub mod operation;
+#[doc(hidden)]
+pub mod ops;
+#[doc(hidden)]
+pub mod paint;
+#[doc(hidden)]
+pub mod params;
+#[doc(hidden)]
+pub mod plugin;
+#[doc(hidden)]
+pub mod preset;
+#[doc(hidden)]
+pub mod project;
+#[doc(hidden)]
+pub mod redaction;
+#[doc(hidden)]
+pub mod script;
+#[doc(hidden)]
+pub mod selection;
+#[doc(hidden)]
+pub mod text;
+#[doc(hidden)]
+pub mod transparency;
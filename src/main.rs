@@ -1,18 +1,84 @@
 mod image_editor;
+mod canvas;
+mod autosave;
+mod recent;
+mod preferences;
+mod keymap;
+mod batch;
+use std::ops::RangeInclusive;
+use std::path::Path;
 use eframe::{App, egui, Frame, NativeOptions, run_native};
-use eframe::egui::{CentralPanel, Context, vec2};
-use crate::egui::{Align, FontId, Layout, SidePanel, TopBottomPanel};
+use eframe::egui::{CentralPanel, Color32, Context, Painter, pos2, Rect, Stroke, vec2};
+use crate::egui::{Align, FontId, Layout, PointerButton, SidePanel, TopBottomPanel};
 use crate::egui::FontFamily::{Proportional};
 use crate::egui::TextStyle::{Body, Button, Monospace};
 use crate::image_editor::State;
+use my_image_editor::convolution::{EdgeMode, Kernel, KERNEL_SIZES};
+use my_image_editor::denoise::DenoiseMethod;
+use my_image_editor::channels::{Channel, ChannelOperation};
+use my_image_editor::transparency::TransparencyOperation;
+use my_image_editor::selection::{SelectionMode, SelectionTool};
+use crate::canvas::Canvas;
+use my_image_editor::paint::PaintTool;
+use my_image_editor::annotation::{DashStyle, ShapeKind, MAX_STROKE_WIDTH};
+use my_image_editor::redaction::RedactionStyle;
+use my_image_editor::layers::{BlendMode, SCALE_RANGE};
+use my_image_editor::project::PROJECT_EXTENSION;
+use my_image_editor::text::{MAX_OUTLINE_WIDTH, MAX_TEXT_SIZE};
+use crate::recent::THUMBNAIL_SIZE;
+use crate::keymap::{Action, Shortcut};
+use my_image_editor::preset::{self, PRESET_EXTENSIONS};
+use my_image_editor::operation::{ParamKind, ParamSpec, ParamValue};
+use my_image_editor::plugin;
+use my_image_editor::script::{self, SCRIPT_EXTENSION};
+use crate::batch::{BatchSource, FileStatus, IMAGE_EXTENSIONS};
+use crate::preferences::{ExportFormat, Preferences, PreviewQuality, Theme, FONT_SIZE_RANGE, HISTORY_SIZE_RANGE, UI_SCALE_RANGE};
 
-const PADDIN: f32 = 5.0;
-const SIDE_PANEL_SIZE: f32 = 150.0;
 const INITIAL_WINDOW_W: f32 = 52.0 * 16.0;
 const INITIAL_WINDOW_H: f32 = 52.0 * 9.0;
+const CHECKER_SIZE: f32 = 8.0;
+const LAYERS_PANEL_SIZE: f32 = 200.0;
+const PRESETS_PANEL_SIZE: f32 = 200.0;
 
 fn main() {
-    let app = image_editor::ImageEditor::new();
+    // Replaying a preset from the command line doesn't open the window
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("--preset") {
+        if args.len() != 4 {
+            eprintln!("Usage: my-image-editor --preset <PRESET> <INFILE> <OUTFILE>");
+            std::process::exit(2);
+        }
+        let quality = Preferences::default().export_quality;
+        let (operations, plugin_errors) = image_editor::load_operations();
+        for err in plugin_errors {
+            eprintln!("{}", err);
+        }
+        if let Err(err) = preset::run(Path::new(&args[1]), Path::new(&args[2]), Path::new(&args[3]), quality, &operations) {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    // Scripts too, the input and output are optional since a script can open and save files
+    if args.first().map(String::as_str) == Some("--script") {
+        if !(2..=4).contains(&args.len()) {
+            eprintln!("Usage: my-image-editor --script <SCRIPT> [INFILE [OUTFILE]]");
+            std::process::exit(2);
+        }
+        let (operations, plugin_errors) = image_editor::load_operations();
+        for err in plugin_errors {
+            eprintln!("{}", err);
+        }
+        let input = args.get(2).map(Path::new);
+        let output = args.get(3).map(Path::new);
+        if let Err(err) = script::run_file(Path::new(&args[1]), input, output, &operations) {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let mut app = image_editor::ImageEditor::new();
     let win_options = NativeOptions {
         icon_data: Some(load_icon("./imgs/icon.png")),
         initial_window_size: Some(vec2(INITIAL_WINDOW_W, INITIAL_WINDOW_H)),
@@ -22,89 +88,164 @@ fn main() {
     run_native(
         "Image Editor",
         win_options,
-        Box::new(|_cc| Box::new(app))
+        Box::new(|cc| {
+            if let Some(storage) = cc.storage {
+                app.load_storage(storage);
+            }
+            Box::new(app)
+        })
     );
 }
 
 impl App for image_editor::ImageEditor {
-    fn update(&mut self, ctx: &Context, _frame: &mut Frame) {
+    fn update(&mut self, ctx: &Context, frame: &mut Frame) {
+        if self.take_preferences_changed() {
+            self.preferences().apply(ctx, frame.info().native_pixels_per_point.unwrap_or(1.0));
+        }
+        self.handle_shortcuts(ctx);
+        let sizes = self.preferences().ui_sizes;
+        let padding = sizes.padding;
 
         SidePanel::left("my_left_panel")
             .resizable(false)
-            .default_width(SIDE_PANEL_SIZE)
+            .default_width(sizes.side_panel_width)
+            .width_range(sizes.side_panel_width..=sizes.side_panel_width)
             // .width_range(80.0..=400.0)
             .show(ctx, |ui| {
-                ui.add_space(2.0*PADDIN);
+                ui.add_space(2.0*padding);
                 ui.vertical_centered(|ui| {
                     egui::ScrollArea::vertical().show(ui, |ui| {
-                        ui.add_sized([SIDE_PANEL_SIZE, 25.], egui::Image::new(self.logo().texture_id(ctx), vec2(50.,50.)));
+                        ui.add_sized([sizes.side_panel_width, 25.], egui::Image::new(self.logo().texture_id(ctx), vec2(50.,50.)));
 
-                        ui.add_space(2.0*PADDIN);
+                        ui.add_space(2.0*padding);
                         ui.separator();
 
                         // Get current context style
                         let mut style = (*ctx.style()).clone();
 
                         style.text_styles = [
-                            (Button, FontId::new(18.0, Proportional))
+                            (Button, FontId::new(sizes.tool_font_size, Proportional))
                         ].into();
                         ui.style_mut().text_styles = style.text_styles;
 
-                        ui.add_space(2.0*PADDIN);
+                        // Tool buttons follow the panel width, with a margin on each side
+                        let tool_button = [(sizes.side_panel_width - 6.0*padding).max(sizes.side_panel_width / 2.0), 40.];
+                        ui.add_space(2.0*padding);
                         // Blur
-                        let blur_button = ui.add_sized([120., 40.], egui::Button::new("Blur"));
+                        let blur_button = ui.add_sized(tool_button, egui::Button::new("Blur"));
                         if blur_button.clicked() && self.current_img_path().is_some() && *self.state() != State::Blur {
-                            self.set_current_img_edited_path(self.current_img_path().clone());
-                            self.set_state(State::Blur);
-                            self.clear_effects_values();
+                            self.select_tool(State::Blur);
                         }
-                        ui.add_space(2.0*PADDIN);
+                        ui.add_space(2.0*padding);
                         // Brighten
-                        let brighten_button = ui.add_sized([120., 40.], egui::Button::new("Brighten"));
+                        let brighten_button = ui.add_sized(tool_button, egui::Button::new("Brighten"));
                         if brighten_button.clicked() && self.current_img_path().is_some() {
-                            self.set_current_img_edited_path(self.current_img_path().clone());
-                            self.set_state(State::Brighten);
-                            self.clear_effects_values();
+                            self.select_tool(State::Brighten);
                         }
-                        ui.add_space(2.0*PADDIN);
+                        ui.add_space(2.0*padding);
                         // Contrast
-                        let contrast_button = ui.add_sized([120., 40.], egui::Button::new("Contrast"));
+                        let contrast_button = ui.add_sized(tool_button, egui::Button::new("Contrast"));
                         if contrast_button.clicked() && self.current_img_path().is_some() {
-                            self.set_current_img_edited_path(self.current_img_path().clone());
-                            self.set_state(State::Contrast);
-                            self.clear_effects_values();
-                        }
-                        ui.add_space(2.0*PADDIN);
-                        // Flip
-                        let flip_button = ui.add_sized([120., 40.], egui::Button::new("Flip Image"));
-                        if flip_button.clicked() && self.current_img_path().is_some() {
-                            self.set_current_img_edited_path(self.current_img_path().clone());
-                            self.set_state(State::Flip);
-                            self.clear_effects_values();
-                        }
-                        ui.add_space(2.0*PADDIN);
-                        // Grayscale
-                        let grayscale_button = ui.add_sized([120., 40.], egui::Button::new("Grayscale"));
-                        if grayscale_button.clicked() && self.current_img_path().is_some() {
-                            self.set_current_img_edited_path(self.current_img_path().clone());
-                            self.set_state(State::Grayscale);
-                            self.clear_effects_values();
-                        }
-                        ui.add_space(2.0*PADDIN);
-                        // Invert
-                        let invert_button = ui.add_sized([120., 40.], egui::Button::new("Invert"));
-                        if invert_button.clicked() && self.current_img_path().is_some() {
-                            self.set_current_img_edited_path(self.current_img_path().clone());
-                            self.set_state(State::Invert);
-                            self.clear_effects_values();
-                        }
-                        ui.add_space(2.0*PADDIN);
-                        // Rotate
-                        let rotate_button = ui.add_sized([120., 40.], egui::Button::new("Rotate"));
-                        if rotate_button.clicked() && self.current_img_path().is_some() {
-                            self.set_current_img_edited_path(self.current_img_path().clone());
-                            self.set_state(State::Rotate);
-                            self.clear_effects_values();
+                            self.select_tool(State::Contrast);
+                        }
+                        ui.add_space(2.0*padding);
+                        // Filters of the operation registry
+                        for index in 0..self.operations().operations().len() {
+                            let name = self.operations().operations()[index].name().to_string();
+                            let operation_button = ui.add_sized(tool_button, egui::Button::new(name));
+                            if operation_button.clicked() && self.current_img_path().is_some() && *self.state() != State::Operation(index) {
+                                self.select_tool(State::Operation(index));
+                            }
+                            ui.add_space(2.0*padding);
+                        }
+                        // Convolution
+                        let convolution_button = ui.add_sized(tool_button, egui::Button::new("Convolution"));
+                        if convolution_button.clicked() && self.current_img_path().is_some() {
+                            self.select_tool(State::Convolution);
+                        }
+                        ui.add_space(2.0*padding);
+                        // Denoise
+                        let denoise_button = ui.add_sized(tool_button, egui::Button::new("Denoise"));
+                        if denoise_button.clicked() && self.current_img_path().is_some() && *self.state() != State::Denoise {
+                            self.select_tool(State::Denoise);
+                        }
+                        ui.add_space(2.0*padding);
+                        // Sepia
+                        let sepia_button = ui.add_sized(tool_button, egui::Button::new("Sepia"));
+                        if sepia_button.clicked() && self.current_img_path().is_some() && *self.state() != State::Sepia {
+                            self.select_tool(State::Sepia);
+                        }
+                        ui.add_space(2.0*padding);
+                        // Posterize
+                        let posterize_button = ui.add_sized(tool_button, egui::Button::new("Posterize"));
+                        if posterize_button.clicked() && self.current_img_path().is_some() && *self.state() != State::Posterize {
+                            self.select_tool(State::Posterize);
+                        }
+                        ui.add_space(2.0*padding);
+                        // Threshold
+                        let threshold_button = ui.add_sized(tool_button, egui::Button::new("Threshold"));
+                        if threshold_button.clicked() && self.current_img_path().is_some() && *self.state() != State::Threshold {
+                            self.select_tool(State::Threshold);
+                        }
+                        ui.add_space(2.0*padding);
+                        // Pixelate
+                        let pixelate_button = ui.add_sized(tool_button, egui::Button::new("Pixelate"));
+                        if pixelate_button.clicked() && self.current_img_path().is_some() && *self.state() != State::Pixelate {
+                            self.select_tool(State::Pixelate);
+                        }
+                        ui.add_space(2.0*padding);
+                        // Vignette
+                        let vignette_button = ui.add_sized(tool_button, egui::Button::new("Vignette"));
+                        if vignette_button.clicked() && self.current_img_path().is_some() && *self.state() != State::Vignette {
+                            self.select_tool(State::Vignette);
+                        }
+                        ui.add_space(2.0*padding);
+                        // Film Grain
+                        let film_grain_button = ui.add_sized(tool_button, egui::Button::new("Film Grain"));
+                        if film_grain_button.clicked() && self.current_img_path().is_some() && *self.state() != State::FilmGrain {
+                            self.select_tool(State::FilmGrain);
+                        }
+                        ui.add_space(2.0*padding);
+                        // Channels
+                        let channels_button = ui.add_sized(tool_button, egui::Button::new("Channels"));
+                        if channels_button.clicked() && self.current_img_path().is_some() && *self.state() != State::Channels {
+                            self.select_tool(State::Channels);
+                        }
+                        ui.add_space(2.0*padding);
+                        // Transparency
+                        let transparency_button = ui.add_sized(tool_button, egui::Button::new("Transparency"));
+                        if transparency_button.clicked() && self.current_img_path().is_some() && *self.state() != State::Transparency {
+                            self.select_tool(State::Transparency);
+                        }
+                        ui.add_space(2.0*padding);
+                        // Select
+                        let select_button = ui.add_sized(tool_button, egui::Button::new("Select"));
+                        if select_button.clicked() && self.current_img_path().is_some() && *self.state() != State::Select {
+                            self.select_tool(State::Select);
+                        }
+                        ui.add_space(2.0*padding);
+                        // Paint
+                        let paint_button = ui.add_sized(tool_button, egui::Button::new("Paint"));
+                        if paint_button.clicked() && self.current_img_path().is_some() && *self.state() != State::Paint {
+                            self.select_tool(State::Paint);
+                        }
+                        ui.add_space(2.0*padding);
+                        // Text
+                        let text_button = ui.add_sized(tool_button, egui::Button::new("Text"));
+                        if text_button.clicked() && self.current_img_path().is_some() && *self.state() != State::Text {
+                            self.select_tool(State::Text);
+                        }
+                        ui.add_space(2.0*padding);
+                        // Shapes
+                        let shapes_button = ui.add_sized(tool_button, egui::Button::new("Shapes"));
+                        if shapes_button.clicked() && self.current_img_path().is_some() && *self.state() != State::Shapes {
+                            self.select_tool(State::Shapes);
+                        }
+                        ui.add_space(2.0*padding);
+                        // Redact
+                        let redact_button = ui.add_sized(tool_button, egui::Button::new("Redact"));
+                        if redact_button.clicked() && self.current_img_path().is_some() && *self.state() != State::Redact {
+                            self.select_tool(State::Redact);
                         }
                     });
                 });
@@ -116,18 +257,18 @@ impl App for image_editor::ImageEditor {
             // .default_height(10.)
             // .min_height(50.0)
             .show(ctx, |ui| {
-                ui.add_space(PADDIN);
+                ui.add_space(padding);
                 ui.horizontal(|ui| {
 
-                    ui.add_space(PADDIN);
+                    ui.add_space(padding);
 
                     // Get current context style
                     let mut style = (*ctx.style()).clone();
 
                     style.text_styles = [
-                        (Button, FontId::new(16.0, Proportional)),
-                        (Body, FontId::new(16.0, Proportional)),
-                        (Monospace, FontId::new(14.0, Proportional)),
+                        (Button, FontId::new(sizes.font_size, Proportional)),
+                        (Body, FontId::new(sizes.font_size, Proportional)),
+                        (Monospace, FontId::new(sizes.monospace_font_size, Proportional)),
                     ].into();
                     ui.style_mut().text_styles = style.text_styles;
 
@@ -136,15 +277,51 @@ impl App for image_editor::ImageEditor {
                         ui.label("Select a image:");
                         let open_file_button = ui.add_sized([60., 20.], egui::Button::new("Open file..."));
                         if open_file_button.clicked() {
+                            self.open_image_dialog();
+                        }
+                        ui.menu_button("Recent", |ui| {
+                            self.recent_files_mut().prune();
+                            if self.recent_files().paths().is_empty() {
+                                ui.label("No recent files");
+                            }
+                            let mut open = None;
+                            for path in self.recent_files().paths().clone() {
+                                ui.horizontal(|ui| {
+                                    let size = vec2(THUMBNAIL_SIZE as f32, THUMBNAIL_SIZE as f32);
+                                    match self.recent_files_mut().thumbnail(&path) {
+                                        Some(thumbnail) => {
+                                            ui.add_sized(size, egui::Image::new(thumbnail.texture_id(ctx), thumbnail.size_vec2()));
+                                        },
+                                        None => {
+                                            ui.allocate_space(size);
+                                        },
+                                    }
+                                    let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+                                    if ui.button(name).on_hover_text(path.display().to_string()).clicked() {
+                                        open = Some(path.clone());
+                                    }
+                                });
+                            }
+                            ui.separator();
+                            ui.checkbox(&mut self.recent_files_mut().reopen_last, "Reopen last image at launch");
+                            if ui.button("Clear").clicked() {
+                                self.recent_files_mut().clear();
+                            }
+                            if let Some(path) = open {
+                                self.open_image(path);
+                                ui.close_menu();
+                            }
+                        });
+                        let open_project_button = ui.add_sized([60., 20.], egui::Button::new("Open project..."));
+                        if open_project_button.clicked() {
                             if let Some(path) = rfd::FileDialog::new()
-                                .add_filter("image", &["png", "jpg", "jpeg"])
+                                .add_filter("project", &[PROJECT_EXTENSION])
                                 .pick_file() {
-                                    self.set_initial_image_path(Some(path.clone()));
-                                    self.set_current_img_path(Some(path.clone()));
-                                    self.set_current_img_edited_path(Some(path));
-                                    self.reset_version_number();
-                                    self.set_state(State::Waiting);
-                                    self.clear_effects_values();
+                                    let message = match self.open_project(&path) {
+                                        Ok(()) => None,
+                                        Err(err) => Some(format!("Could not open project: {}", err)),
+                                    };
+                                    self.set_project_message(message);
                             }
                         }
                         if self.initial_image_path().is_some() {
@@ -153,39 +330,63 @@ impl App for image_editor::ImageEditor {
                     });
 
                     ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
+                        let settings_button = ui.add_sized([60., 20.], egui::Button::new("Settings"));
+                        if settings_button.clicked() {
+                            self.set_settings_open(!self.settings_open());
+                        }
+                        let batch_button = ui.add_sized([60., 20.], egui::Button::new("Batch..."));
+                        if batch_button.clicked() {
+                            self.set_batch_open(!self.batch_open());
+                        }
+                        let script_button = ui.add_sized([60., 20.], egui::Button::new("Script..."));
+                        if script_button.clicked() {
+                            self.set_script_open(!self.script_open());
+                        }
+                        let shortcuts_button = ui.add_sized([20., 20.], egui::Button::new("?"));
+                        if shortcuts_button.on_hover_text("Keyboard shortcuts").clicked() {
+                            self.set_shortcuts_open(!self.shortcuts_open());
+                        }
                         // Save image button
+                        let save_as_button = ui.add_sized([60., 20.], egui::Button::new("Save as..."));
+                        if save_as_button.clicked() {
+                            self.save_image(true);
+                        }
                         let save_button = ui.add_sized([60., 20.], egui::Button::new("Save"));
-                        if save_button.clicked() && self.current_img_path().is_some() {
+                        if save_button.clicked() {
+                            self.save_image(false);
+                        }
+                        let save_project_button = ui.add_sized([60., 20.], egui::Button::new("Save project"));
+                        if save_project_button.clicked() && self.current_img_path().is_some() {
+                            let file_name = std::path::Path::new(&self.initial_image_name().unwrap()).with_extension(PROJECT_EXTENSION);
                             if let Some(path) = rfd::FileDialog::new()
-                                .add_filter("image", &["png", "jpg", "jpeg"])
-                                .set_file_name(&*self.initial_image_name().as_ref().unwrap())
+                                .add_filter("project", &[PROJECT_EXTENSION])
+                                .set_file_name(&file_name.to_string_lossy())
                                 .save_file() {
-                                let img = image::open(self.current_img_path().as_ref().unwrap()).expect("Failed to open INFILE.");
-                                img.save(path).expect("Failed writing OUTFILE.");
+                                    let message = match self.save_project(&path) {
+                                        Ok(()) if self.has_redactions() => {
+                                            Some(String::from("Saved, but the project keeps the unredacted versions in its history"))
+                                        },
+                                        Ok(()) => None,
+                                        Err(err) => Some(format!("Could not save project: {}", err)),
+                                    };
+                                    self.set_project_message(message);
                             }
                         }
+                        if let Some(message) = self.project_message() {
+                            ui.colored_label(Color32::RED, message);
+                        }
                         let redo_button = ui.add_sized([20., 20.], egui::Button::new("▶"));
-                        if redo_button.clicked() && !self.versions_discart().is_empty() {
-                            // current_img_path > versions
-                            self.push_back_versions(self.current_img_path().clone());
-
-                            // versions_discart.pop() > current_img_path
-                            let new_current_img_path = self.pop_back_versions_discart();
-                            self.set_current_img_path(new_current_img_path);
+                        if redo_button.clicked() {
+                            self.redo();
                         }
 
                         let undo_button = ui.add_sized([20., 20.], egui::Button::new("◀"));
-                        if undo_button.clicked() && !self.versions().is_empty() {
-                            // current_img_path > versions_discart
-                            self.push_back_versions_discart(self.current_img_path().clone());
-
-                            // versions.pop() > current_img_path
-                            let new_current_img_path = self.pop_back_versions();
-                            self.set_current_img_path(new_current_img_path);
+                        if undo_button.clicked() {
+                            self.undo();
                         }
                     })
                 });
-                ui.add_space(PADDIN);
+                ui.add_space(padding);
             });
 
 
@@ -197,74 +398,425 @@ impl App for image_editor::ImageEditor {
 
                     ui.with_layout(Layout::left_to_right(Align::Center), |ui| {
                         if self.current_img_path().is_some() {
-                            ui.add_space(2.0*PADDIN);
+                            ui.add_space(2.0*padding);
                             match self.state() {
                                 State::Blur => {
-                                    let mut scalar = self.intensity();
-                                    ui.add(egui::Slider::new(&mut scalar, 0.0..=20.0));
-                                    if self.intensity() != scalar {
-                                        self.set_intensity(scalar);
-                                        self.set_current_img_edited_path(self.apply_blur());
+                                    let mut params = *self.params();
+                                    effect_slider(ui, &mut params.blur, 0.0..=self.preferences().slider_ranges.blur, "", 0.0);
+                                    if *self.params() != params {
+                                        self.set_params(params);
+                                        self.set_current_img_edited_path(self.apply_effects());
                                     }
                                 },
                                 State::Contrast => {
-                                    let mut scalar = self.intensity();
-                                    ui.add(egui::Slider::new(&mut scalar, -20.0..=20.0));
-                                    if self.intensity() != scalar {
-                                        self.set_intensity(scalar);
-                                        self.set_current_img_edited_path(self.apply_contrast());
+                                    let mut params = *self.params();
+                                    let range = self.preferences().slider_ranges.contrast;
+                                    effect_slider(ui, &mut params.contrast, -range..=range, "", 0.0);
+                                    if *self.params() != params {
+                                        self.set_params(params);
+                                        self.set_current_img_edited_path(self.apply_effects());
                                     }
                                 },
                                 State::Brighten => {
-                                    let mut scalar = self.intensity() as i32;
-                                    ui.add(egui::Slider::new(&mut scalar, -100..=100));
-                                    if self.intensity() != (scalar as f32) {
-                                        self.set_intensity(scalar as f32);
-                                        self.set_current_img_edited_path(self.apply_brighten());
+                                    let mut params = *self.params();
+                                    let range = self.preferences().slider_ranges.brighten;
+                                    effect_slider(ui, &mut params.brighten, -range..=range, "", 0);
+                                    if *self.params() != params {
+                                        self.set_params(params);
+                                        self.set_current_img_edited_path(self.apply_effects());
+                                    }
+                                },
+                                State::Convolution => {
+                                    let mut kernel = self.kernel().clone();
+                                    let mut edge_mode = *self.edge_mode();
+                                    egui::ComboBox::from_label("Kernel")
+                                        .selected_text(kernel.name.clone())
+                                        .show_ui(ui, |ui| {
+                                            for item in Kernel::builtin().into_iter().chain(self.custom_kernels().iter().cloned()) {
+                                                let name = item.name.clone();
+                                                ui.selectable_value(&mut kernel, item, name);
+                                            }
+                                        });
+                                    ui.add_space(padding);
+                                    egui::ComboBox::from_label("Edges")
+                                        .selected_text(edge_mode.name())
+                                        .show_ui(ui, |ui| {
+                                            for mode in EdgeMode::ALL {
+                                                ui.selectable_value(&mut edge_mode, mode, mode.name());
+                                            }
+                                        });
+                                    ui.add_space(padding);
+                                    let edit_kernel_button = ui.add_sized([40., 20.], egui::Button::new("Edit Kernel..."));
+                                    if edit_kernel_button.clicked() {
+                                        self.set_kernel_editor_open(true);
+                                    }
+                                    if *self.kernel() != kernel || *self.edge_mode() != edge_mode {
+                                        self.set_kernel(kernel);
+                                        self.set_edge_mode(edge_mode);
+                                        self.set_current_img_edited_path(self.apply_convolution());
+                                    }
+                                },
+                                State::Denoise => {
+                                    let mut params = *self.params();
+                                    egui::ComboBox::from_label("Method")
+                                        .selected_text(params.denoise_method.name())
+                                        .show_ui(ui, |ui| {
+                                            for item in DenoiseMethod::ALL {
+                                                ui.selectable_value(&mut params.denoise_method, item, item.name());
+                                            }
+                                        });
+                                    ui.add_space(padding);
+                                    let radius_slider = effect_slider(ui, &mut params.denoise_radius, 1..=params.denoise_method.max_radius(), "Radius", 1);
+                                    ui.add_space(padding);
+                                    let strength_slider = effect_slider(ui, &mut params.denoise_strength, 0.0..=100.0, "Strength", 0.0);
+
+                                    // These filters are slow, so only preview once the slider is released
+                                    let changed = *self.params() != params || radius_slider.changed() || strength_slider.changed();
+                                    let dragging = radius_slider.dragged() || strength_slider.dragged();
+                                    let released = radius_slider.drag_released() || strength_slider.drag_released();
+                                    self.set_params(params);
+                                    if released || (changed && !dragging) {
+                                        self.set_current_img_edited_path(self.apply_effects());
+                                    }
+                                },
+                                State::Sepia => {
+                                    let mut params = *self.params();
+                                    effect_slider(ui, &mut params.sepia, 0.0..=100.0, "Amount", 0.0);
+                                    if *self.params() != params {
+                                        self.set_params(params);
+                                        self.set_current_img_edited_path(self.apply_effects());
+                                    }
+                                },
+                                State::Posterize => {
+                                    // A single level stands for no effect, like a block size of one when pixelating
+                                    let mut params = *self.params();
+                                    effect_slider(ui, &mut params.posterize_levels, 1..=32, "Levels", 1);
+                                    if *self.params() != params {
+                                        self.set_params(params);
+                                        self.set_current_img_edited_path(self.apply_effects());
+                                    }
+                                },
+                                State::Threshold => {
+                                    // Zero stands for no effect, every pixel would turn white anyway
+                                    let mut params = *self.params();
+                                    effect_slider(ui, &mut params.threshold, 0..=255, "Level", 0);
+                                    ui.add_space(padding);
+                                    let otsu_button = ui.add_sized([40., 20.], egui::Button::new("Auto (Otsu)"));
+                                    if otsu_button.clicked() {
+                                        params.threshold = self.otsu_threshold();
+                                    }
+                                    if *self.params() != params {
+                                        self.set_params(params);
+                                        self.set_current_img_edited_path(self.apply_effects());
+                                    }
+                                },
+                                State::Pixelate => {
+                                    let mut params = *self.params();
+                                    effect_slider(ui, &mut params.pixelate_block_size, 1..=100, "Block Size", 1);
+                                    if *self.params() != params {
+                                        self.set_params(params);
+                                        self.set_current_img_edited_path(self.apply_effects());
+                                    }
+                                },
+                                State::Vignette => {
+                                    let mut params = *self.params();
+                                    effect_slider(ui, &mut params.vignette_strength, 0.0..=100.0, "Strength", 0.0);
+                                    ui.add_space(padding);
+                                    effect_slider(ui, &mut params.vignette_radius, 0.0..=1.0, "Radius", 0.5);
+                                    ui.add_space(padding);
+                                    effect_slider(ui, &mut params.vignette_feather, 0.0..=1.0, "Feather", 0.5);
+                                    if *self.params() != params {
+                                        self.set_params(params);
+                                        self.set_current_img_edited_path(self.apply_effects());
+                                    }
+                                },
+                                State::FilmGrain => {
+                                    let mut params = *self.params();
+                                    effect_slider(ui, &mut params.grain_amount, 0.0..=100.0, "Amount", 0.0);
+                                    ui.add_space(padding);
+                                    effect_slider(ui, &mut params.grain_size, 1..=8, "Grain Size", 1);
+                                    if *self.params() != params {
+                                        self.set_params(params);
+                                        self.set_current_img_edited_path(self.apply_effects());
+                                    }
+                                },
+                                State::Channels => {
+                                    let mut operation = *self.channel_operation();
+                                    let mut channel = *self.channel();
+                                    let mut swap_channel = *self.swap_channel();
+                                    let mut mixer = self.channel_mixer().clone();
+                                    egui::ComboBox::from_id_source("channel_operation")
+                                        .selected_text(operation.name())
+                                        .show_ui(ui, |ui| {
+                                            for item in ChannelOperation::ALL {
+                                                ui.selectable_value(&mut operation, item, item.name());
+                                            }
+                                        });
+                                    ui.add_space(padding);
+                                    match operation {
+                                        ChannelOperation::Isolate | ChannelOperation::Grayscale | ChannelOperation::Swap => {
+                                            for item in Channel::ALL {
+                                                ui.selectable_value(&mut channel, item, item.name());
+                                            }
+                                            if operation == ChannelOperation::Swap {
+                                                ui.label("⇄");
+                                                for item in Channel::ALL {
+                                                    ui.selectable_value(&mut swap_channel, item, item.name());
+                                                }
+                                            }
+                                        },
+                                        ChannelOperation::Mixer => {
+                                            egui::Grid::new("channel_mixer").show(ui, |ui| {
+                                                ui.label("");
+                                                for name in ["Red", "Green", "Blue", "Offset"] {
+                                                    ui.label(name);
+                                                }
+                                                ui.end_row();
+                                                for (row, name) in ["Red", "Green", "Blue"].iter().enumerate() {
+                                                    if mixer.monochrome && row > 0 {
+                                                        break;
+                                                    }
+                                                    ui.label(if mixer.monochrome { "Gray" } else { name });
+                                                    for col in 0..3 {
+                                                        ui.add(egui::DragValue::new(&mut mixer.matrix[row][col]).speed(0.01).clamp_range(-2.0..=2.0));
+                                                    }
+                                                    ui.add(egui::DragValue::new(&mut mixer.offset[row]).speed(1.0).clamp_range(-255.0..=255.0));
+                                                    ui.end_row();
+                                                }
+                                            });
+                                            ui.add_space(padding);
+                                            ui.checkbox(&mut mixer.monochrome, "Monochrome");
+                                        },
+                                    }
+                                    if *self.channel_operation() != operation || *self.channel() != channel || *self.swap_channel() != swap_channel || *self.channel_mixer() != mixer {
+                                        self.set_channel_operation(operation);
+                                        self.set_channel(channel);
+                                        self.set_swap_channel(swap_channel);
+                                        self.set_channel_mixer(mixer);
+                                        self.set_current_img_edited_path(self.apply_channels());
+                                    }
+                                },
+                                State::Transparency => {
+                                    let mut operation = *self.transparency_operation();
+                                    egui::ComboBox::from_id_source("transparency_operation")
+                                        .selected_text(operation.name())
+                                        .show_ui(ui, |ui| {
+                                            for item in TransparencyOperation::ALL {
+                                                ui.selectable_value(&mut operation, item, item.name());
+                                            }
+                                        });
+                                    let mut changed = *self.transparency_operation() != operation;
+                                    self.set_transparency_operation(operation);
+                                    ui.add_space(padding);
+                                    match operation {
+                                        TransparencyOperation::Opacity => {
+                                            let mut opacity = self.opacity();
+                                            changed |= effect_slider(ui, &mut opacity, 0.0..=100.0, "Opacity", 100.0).changed();
+                                            self.set_opacity(opacity);
+                                        },
+                                        TransparencyOperation::RemoveAlpha => {
+                                            let mut color = self.background_color();
+                                            ui.label("Background:");
+                                            changed |= ui.color_edit_button_srgb(&mut color).changed();
+                                            self.set_background_color(color);
+                                        },
+                                        TransparencyOperation::ChromaKey => {
+                                            let mut color = self.key_color();
+                                            ui.label("Color:");
+                                            changed |= ui.color_edit_button_srgb(&mut color).changed();
+                                            self.set_key_color(color);
+                                            ui.add_space(padding);
+                                            let mut tolerance = self.key_tolerance();
+                                            changed |= effect_slider(ui, &mut tolerance, 0.0..=100.0, "Tolerance", 0.0).changed();
+                                            self.set_key_tolerance(tolerance);
+                                        },
+                                    }
+                                    if changed {
+                                        self.set_current_img_edited_path(self.apply_transparency());
+                                    }
+                                },
+                                State::Select => {
+                                    let mut tool = *self.selection_tool();
+                                    for item in SelectionTool::ALL {
+                                        ui.selectable_value(&mut tool, item, item.name());
+                                    }
+                                    self.set_selection_tool(tool);
+                                    ui.separator();
+                                    let mut mode = *self.selection_mode();
+                                    for item in SelectionMode::ALL {
+                                        ui.selectable_value(&mut mode, item, item.name());
+                                    }
+                                    self.set_selection_mode(mode);
+                                    ui.separator();
+                                    if tool == SelectionTool::MagicWand {
+                                        let mut tolerance = self.wand_tolerance();
+                                        effect_slider(ui, &mut tolerance, 0.0..=100.0, "Tolerance", 15.0);
+                                        self.set_wand_tolerance(tolerance);
+                                        ui.add_space(padding);
+                                    }
+                                    let mut feather = self.selection_feather();
+                                    let feather_slider = effect_slider(ui, &mut feather, 0.0..=50.0, "Feather", 0.0);
+                                    if feather_slider.changed() {
+                                        self.set_selection_feather(feather);
+                                    }
+                                    ui.add_space(padding);
+                                    let invert_selection_button = ui.add_sized([40., 20.], egui::Button::new("Invert"));
+                                    if invert_selection_button.clicked() {
+                                        self.invert_selection();
+                                    }
+                                    ui.add_space(padding);
+                                    let deselect_button = ui.add_sized([40., 20.], egui::Button::new("Deselect"));
+                                    if deselect_button.clicked() {
+                                        self.deselect();
                                     }
                                 },
-                                State::Invert => {
-                                    let apply_effect_button = ui.add_sized([40., 20.], egui::Button::new("Invert Image"));
-                                    if apply_effect_button.clicked() {
-                                        self.set_current_img_edited_path(self.apply_invert());
+                                State::Paint => {
+                                    let mut brush = *self.brush();
+                                    for item in PaintTool::ALL {
+                                        ui.selectable_value(&mut brush.tool, item, item.name());
+                                    }
+                                    ui.separator();
+                                    if brush.tool == PaintTool::Brush {
+                                        ui.color_edit_button_srgb(&mut brush.color);
+                                        ui.add_space(padding);
+                                    }
+                                    if brush.tool != PaintTool::ColorPicker {
+                                        ui.add(egui::Slider::new(&mut brush.size, 1.0..=self.preferences().slider_ranges.brush_size).text("Size"));
+                                        ui.add_space(padding);
+                                        ui.add(egui::Slider::new(&mut brush.hardness, 0.0..=100.0).text("Hardness"));
+                                        ui.add_space(padding);
+                                        ui.add(egui::Slider::new(&mut brush.opacity, 0.0..=100.0).text("Opacity"));
+                                    }
+                                    if brush.tool == PaintTool::CloneStamp {
+                                        ui.add_space(padding);
+                                        ui.label("Ctrl/Alt-click to set the source");
+                                    }
+                                    self.set_brush(brush);
+                                },
+                                State::Text => {
+                                    match self.selected_text_box() {
+                                        Some(index) => {
+                                            let mut text_box = self.text_boxes()[index].clone();
+                                            let mut delete = false;
+                                            ui.vertical(|ui| {
+                                                ui.horizontal(|ui| {
+                                                    ui.add(egui::TextEdit::multiline(&mut text_box.text).desired_rows(1).desired_width(200.));
+                                                    ui.add_space(padding);
+                                                    egui::ComboBox::from_id_source("text_font")
+                                                        .selected_text(text_box.font.clone())
+                                                        .width(150.)
+                                                        .show_ui(ui, |ui| {
+                                                            for font in self.fonts() {
+                                                                ui.selectable_value(&mut text_box.font, font.name.clone(), &font.name);
+                                                            }
+                                                        });
+                                                    ui.add_space(padding);
+                                                    ui.add(egui::Slider::new(&mut text_box.size, 6.0..=MAX_TEXT_SIZE).text("Size"));
+                                                    ui.add_space(padding);
+                                                    ui.color_edit_button_srgb(&mut text_box.color);
+                                                });
+                                                ui.horizontal(|ui| {
+                                                    ui.add(egui::Slider::new(&mut text_box.outline_width, 0.0..=MAX_OUTLINE_WIDTH).text("Outline"));
+                                                    ui.color_edit_button_srgb(&mut text_box.outline_color);
+                                                    ui.add_space(padding);
+                                                    ui.checkbox(&mut text_box.background, "Background");
+                                                    ui.color_edit_button_srgba_unmultiplied(&mut text_box.background_color);
+                                                    ui.add_space(padding);
+                                                    ui.add(egui::Slider::new(&mut text_box.rotation, -180.0..=180.0).text("Rotation"));
+                                                    ui.add_space(padding);
+                                                    delete = ui.add_sized([40., 20.], egui::Button::new("Delete")).clicked();
+                                                });
+                                            });
+                                            self.update_text_box(index, text_box);
+                                            if delete {
+                                                self.remove_selected_text_box();
+                                            }
+                                        },
+                                        None => {
+                                            ui.label("Click on the image to add a text box");
+                                        },
                                     }
                                 },
-                                State::Grayscale => {
-                                    let apply_effect_button = ui.add_sized([40., 20.], egui::Button::new("Convert to Grayscale"));
-                                    if apply_effect_button.clicked() {
-                                        self.set_current_img_edited_path(self.apply_grayscale());
+                                State::Shapes => {
+                                    let mut tool = self.annotation_tool();
+                                    egui::ComboBox::from_id_source("shape_tool")
+                                        .selected_text(tool.name())
+                                        .show_ui(ui, |ui| {
+                                            for item in ShapeKind::ALL {
+                                                ui.selectable_value(&mut tool, item, item.name());
+                                            }
+                                        });
+                                    self.set_annotation_tool(tool);
+                                    ui.add_space(padding);
+                                    let mut style = *self.annotation_style();
+                                    ui.color_edit_button_srgb(&mut style.color);
+                                    ui.add_space(padding);
+                                    ui.add(egui::Slider::new(&mut style.stroke_width, 1.0..=MAX_STROKE_WIDTH).text("Width"));
+                                    ui.add_space(padding);
+                                    ui.checkbox(&mut style.fill, "Fill");
+                                    ui.color_edit_button_srgba_unmultiplied(&mut style.fill_color);
+                                    ui.add_space(padding);
+                                    egui::ComboBox::from_id_source("shape_dash")
+                                        .selected_text(style.dash.name())
+                                        .show_ui(ui, |ui| {
+                                            for item in DashStyle::ALL {
+                                                ui.selectable_value(&mut style.dash, item, item.name());
+                                            }
+                                        });
+                                    self.set_annotation_style(style);
+                                    if self.selected_annotation().is_some() {
+                                        ui.add_space(padding);
+                                        let delete_button = ui.add_sized([40., 20.], egui::Button::new("Delete"));
+                                        if delete_button.clicked() {
+                                            self.remove_selected_annotation();
+                                        }
                                     }
                                 },
-                                State::Flip => {
-                                    let apply_effect_button = ui.add_sized([40., 20.], egui::Button::new("Flip Horizontally"));
-                                    if apply_effect_button.clicked() {
-                                        self.set_current_img_edited_path(self.apply_flip_horizontal());
+                                State::Redact => {
+                                    let mut style = self.redaction_style();
+                                    for item in RedactionStyle::ALL {
+                                        ui.selectable_value(&mut style, item, item.name());
+                                    }
+                                    let mut block_size = self.redaction_block_size();
+                                    let mut changed = style != self.redaction_style();
+                                    if style != RedactionStyle::Solid {
+                                        ui.add_space(padding);
+                                        let block_slider = effect_slider(ui, &mut block_size, 4..=64, "Block Size", 16);
+                                        // Redaction of large regions is slow, so only preview once the slider is released
+                                        changed |= (block_slider.changed() && !block_slider.dragged()) || block_slider.drag_released();
                                     }
-                                    ui.add_space(PADDIN);
-                                    let apply_effect_button = ui.add_sized([40., 20.], egui::Button::new("Flip Vertically"));
-                                    if apply_effect_button.clicked() {
-                                        self.set_current_img_edited_path(self.apply_flip_vertical());
+                                    self.set_redaction_style(style);
+                                    self.set_redaction_block_size(block_size);
+                                    if changed {
+                                        self.set_current_img_edited_path(self.apply_redaction());
                                     }
-                                    ui.add_space(PADDIN);
-                                    let apply_effect_button = ui.add_sized([40., 20.], egui::Button::new("Flip Horizontally + Vertically"));
-                                    if apply_effect_button.clicked() {
-                                        self.set_current_img_edited_path(self.apply_flip_horizontal_vertical());
+                                    ui.add_space(padding);
+                                    let clear_button = ui.add_sized([40., 20.], egui::Button::new("Clear"));
+                                    if clear_button.clicked() {
+                                        self.clear_redaction_regions();
                                     }
+                                    ui.add_space(padding);
+                                    ui.label(format!("{} region(s)", self.redaction_regions().len()));
                                 },
-                                State::Rotate => {
-                                    let apply_effect_button = ui.add_sized([40., 20.], egui::Button::new("Rotate Image 90°"));
-                                    if apply_effect_button.clicked() {
-                                        self.set_current_img_edited_path(self.apply_rotate90());
+                                &State::Operation(index) => {
+                                    let specs = self.operations().get(index).map(|operation| operation.params()).unwrap_or_default();
+                                    let mut values = self.operation_values().clone();
+                                    if specs.is_empty() {
+                                        ui.label("Nothing to set, apply to keep the preview");
                                     }
-                                    ui.add_space(PADDIN);
-                                    let apply_effect_button = ui.add_sized([40., 20.], egui::Button::new("Rotate Image 180°"));
-                                    if apply_effect_button.clicked() {
-                                        self.set_current_img_edited_path(self.apply_rotate180());
+                                    for (spec, value) in specs.iter().zip(values.iter_mut()) {
+                                        operation_control(ui, spec, value);
+                                        ui.add_space(padding);
                                     }
-                                    ui.add_space(PADDIN);
-                                    let apply_effect_button = ui.add_sized([40., 20.], egui::Button::new("Rotate Image 270°"));
-                                    if apply_effect_button.clicked() {
-                                        self.set_current_img_edited_path(self.apply_rotate270());
+                                    if *self.operation_values() != values {
+                                        self.set_operation_values(values);
+                                        self.preview_operation();
+                                    }
+                                    if self.operation_running() {
+                                        ui.spinner();
+                                    }
+                                    if let Some(message) = self.operation_message() {
+                                        ui.colored_label(Color32::RED, message);
                                     }
                                 },
                                 State::Waiting => {},
@@ -273,15 +825,29 @@ impl App for image_editor::ImageEditor {
                     });
 
                     ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
-                        if *self.state() != State::Waiting {
-                            ui.add_space(2.0 * PADDIN);
+                        if *self.state() == State::Select || *self.state() == State::Paint {
+                            // Selections are not edits and strokes are applied as they are drawn
+                            ui.add_space(2.0 * padding);
+                            let done_button = ui.add_sized([20., 20.], egui::Button::new("Done"));
+                            if done_button.clicked() {
+                                self.apply_changes();
+                            }
+                        } else if *self.state() != State::Waiting {
+                            ui.add_space(2.0 * padding);
+                            let cancel_button = ui.add_sized([20., 20.], egui::Button::new("Cancel"));
+                            if cancel_button.clicked() {
+                                self.cancel_edit();
+                            }
                             let confirm_button = ui.add_sized([20., 20.], egui::Button::new("Apply Changes"));
 
                             if confirm_button.clicked() {
-                                self.prepare_new_edition();
-                                self.set_state(State::Waiting);
-                                self.set_current_img_path(self.current_img_edited_path().clone());
-                                self.clear_effects_values();
+                                self.apply_changes();
+                            }
+                            if self.state().uses_params() {
+                                let effects = self.params().active_effects();
+                                if !effects.is_empty() {
+                                    ui.label(format!("Pending: {}", effects.join(" + ")));
+                                }
                             }
                         }
                     });
@@ -290,18 +856,1150 @@ impl App for image_editor::ImageEditor {
 
 
 
+        if self.current_img_path().is_some() {
+            SidePanel::left("layers_panel")
+                .resizable(false)
+                .default_width(LAYERS_PANEL_SIZE)
+                .show(ctx, |ui| {
+                    ui.add_space(padding);
+                    ui.heading("Layers");
+                    ui.add_space(padding);
+                    ui.horizontal(|ui| {
+                        if ui.button("Add Layer").clicked() {
+                            if let Some(path) = rfd::FileDialog::new()
+                                .add_filter("image", &["png", "jpg", "jpeg", "webp"])
+                                .pick_file() {
+                                    let message = self.add_layer(&path).err().map(|err| format!("Could not add layer: {}", err));
+                                    self.set_layer_message(message);
+                            }
+                        }
+                        if ui.add_enabled(!self.layers().is_empty(), egui::Button::new("Flatten")).clicked() {
+                            self.flatten_layers();
+                        }
+                    });
+                    if let Some(message) = self.layer_message() {
+                        ui.colored_label(Color32::RED, message);
+                    }
+                    ui.separator();
+
+                    // Top layer first, like the order they are drawn in
+                    for index in (0..self.layers().len()).rev() {
+                        let mut settings = self.layers()[index].settings;
+                        ui.horizontal(|ui| {
+                            ui.checkbox(&mut settings.visible, "");
+                            let selected = self.selected_layer() == Some(index);
+                            if ui.selectable_label(selected, &self.layers()[index].name).clicked() {
+                                self.select_layer(Some(index));
+                            }
+                            ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
+                                if ui.small_button("🗑").clicked() {
+                                    self.remove_layer(index);
+                                } else if ui.small_button("⏷").clicked() {
+                                    self.move_layer(index, false);
+                                } else if ui.small_button("⏶").clicked() {
+                                    self.move_layer(index, true);
+                                } else {
+                                    self.set_layer_settings(index, settings);
+                                }
+                            });
+                        });
+                    }
+                    ui.label("Background");
+
+                    if let Some(index) = self.selected_layer() {
+                        ui.separator();
+                        let mut settings = self.layers()[index].settings;
+                        ui.add(egui::Slider::new(&mut settings.opacity, 0.0..=100.0).text("Opacity"));
+                        egui::ComboBox::from_label("Blend")
+                            .selected_text(settings.blend_mode.name())
+                            .show_ui(ui, |ui| {
+                                for mode in BlendMode::ALL {
+                                    ui.selectable_value(&mut settings.blend_mode, mode, mode.name());
+                                }
+                            });
+                        ui.add(egui::Slider::new(&mut settings.scale, SCALE_RANGE).text("Scale"));
+                        ui.horizontal(|ui| {
+                            ui.label("X");
+                            ui.add(egui::DragValue::new(&mut settings.position.0).speed(1.0));
+                            ui.label("Y");
+                            ui.add(egui::DragValue::new(&mut settings.position.1).speed(1.0));
+                        });
+                        self.set_layer_settings(index, settings);
+                    }
+
+                    // Edits applied so far, oldest first
+                    if !self.adjustments().is_empty() {
+                        ui.separator();
+                        ui.collapsing("History", |ui| {
+                            for adjustment in self.adjustments() {
+                                ui.label(adjustment.name());
+                            }
+                        });
+                    }
+                });
+        }
+
+        if self.current_img_path().is_some() {
+            SidePanel::right("presets_panel")
+                .resizable(false)
+                .default_width(PRESETS_PANEL_SIZE)
+                .show(ctx, |ui| {
+                    ui.add_space(padding);
+                    ui.heading("Presets");
+                    ui.add_space(padding);
+                    // The edits of this session, recorded under a name
+                    ui.horizontal(|ui| {
+                        let mut name = self.preset_name().clone();
+                        ui.add(egui::TextEdit::singleline(&mut name).hint_text("Name").desired_width(110.));
+                        self.set_preset_name(name);
+                        let can_save = !self.preset_name().trim().is_empty() && !self.adjustments().is_empty();
+                        if ui.add_enabled(can_save, egui::Button::new("Save")).on_hover_text("Save the applied edits as a preset").clicked() {
+                            let message = self.save_preset().err().map(|err| format!("Could not save preset: {}", err));
+                            self.set_preset_message(message);
+                        }
+                    });
+                    if ui.button("Import...").clicked() {
+                        if let Some(path) = rfd::FileDialog::new()
+                            .add_filter("preset", &PRESET_EXTENSIONS)
+                            .pick_file() {
+                                let message = self.import_preset(&path).err().map(|err| format!("Could not import preset: {}", err));
+                                self.set_preset_message(message);
+                        }
+                    }
+                    if let Some(message) = self.preset_message() {
+                        ui.colored_label(Color32::RED, message);
+                    }
+                    ui.separator();
+
+                    if self.presets().is_empty() {
+                        ui.label("No presets");
+                    }
+                    egui::ScrollArea::vertical().show(ui, |ui| {
+                        // Deleting shifts the entries after it, so it waits until the list is drawn
+                        let mut delete = None;
+                        for index in 0..self.presets().len() {
+                            ui.horizontal(|ui| {
+                                let size = vec2(THUMBNAIL_SIZE as f32, THUMBNAIL_SIZE as f32);
+                                match self.preset_thumbnail(index) {
+                                    Some(thumbnail) => {
+                                        ui.add_sized(size, egui::Image::new(thumbnail.texture_id(ctx), thumbnail.size_vec2()));
+                                    },
+                                    None => {
+                                        ui.allocate_space(size);
+                                    },
+                                }
+                                ui.vertical(|ui| {
+                                    let preset = &self.presets()[index].1;
+                                    let steps: Vec<&str> = preset.adjustments.iter().map(|adjustment| adjustment.name()).collect();
+                                    ui.label(&preset.name).on_hover_text(steps.join(", "));
+                                    ui.horizontal(|ui| {
+                                        if ui.small_button("Apply").clicked() {
+                                            self.cancel_edit();
+                                            self.apply_preset(index);
+                                        } else if ui.small_button("Export...").clicked() {
+                                            let file_name = preset::file_name(&self.presets()[index].1.name, "json");
+                                            if let Some(path) = rfd::FileDialog::new()
+                                                .add_filter("preset", &PRESET_EXTENSIONS)
+                                                .set_file_name(&file_name)
+                                                .save_file() {
+                                                    let message = self.export_preset(index, &path).err().map(|err| format!("Could not export preset: {}", err));
+                                                    self.set_preset_message(message);
+                                            }
+                                        } else if ui.small_button("🗑").clicked() {
+                                            delete = Some(index);
+                                        }
+                                    });
+                                });
+                            });
+                        }
+                        if let Some(index) = delete {
+                            let message = self.delete_preset(index).err().map(|err| format!("Could not delete preset: {}", err));
+                            self.set_preset_message(message);
+                        }
+
+                        // Scripts stored next to the presets, opened in the console to run them
+                        ui.separator();
+                        ui.label("Scripts");
+                        if self.scripts().is_empty() {
+                            ui.label("No scripts");
+                        }
+                        let mut delete = None;
+                        for index in 0..self.scripts().len() {
+                            ui.horizontal(|ui| {
+                                let path = self.scripts()[index].clone();
+                                let name = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+                                ui.label(name).on_hover_text(path.display().to_string());
+                                if ui.small_button("Open").clicked() {
+                                    let message = self.open_script(&path).err().map(|err| format!("Could not open script: {}", err));
+                                    self.set_preset_message(message);
+                                } else if ui.small_button("🗑").clicked() {
+                                    delete = Some(index);
+                                }
+                            });
+                        }
+                        if let Some(index) = delete {
+                            let message = self.delete_script(index).err().map(|err| format!("Could not delete script: {}", err));
+                            self.set_preset_message(message);
+                        }
+                    });
+                });
+        }
+
         CentralPanel::default().show(ctx, |ui| {
-            // Display Image
+            // Display Image, with the layers on top while no effect is being previewed
             let image_to_display = match *self.state() {
+                State::Waiting if self.layers_composite().is_some() => self.layers_composite(),
                 State::Waiting => self.current_img(),
                 _ => self.current_img_edited(),
             };
 
             if let Some(img) = image_to_display {
-                let max_size = ui.available_size();
-                img.show_max_size(ui, max_size);
+                let panel_rect = ui.available_rect_before_wrap();
+                let max_size = panel_rect.size();
+                let mut size = img.size_vec2();
+                size *= (max_size.x / size.x).min(max_size.y / size.y).min(1.0) * self.zoom();
+                let rect = Rect::from_min_size(panel_rect.min + self.pan(), size);
+                let response = ui.allocate_rect(panel_rect, egui::Sense::click_and_drag());
+                let canvas = Canvas::new(rect, img.size());
+                // Checkerboard behind the image so transparent pixels are visible
+                paint_checkerboard(ui.painter(), rect.intersect(panel_rect));
+                egui::Image::new(img.texture_id(ctx), size).paint_at(ui, rect);
+
+                if let Some(overlay) = self.selection_overlay() {
+                    egui::Image::new(overlay.texture_id(ctx), size).paint_at(ui, rect);
+                }
+                match self.state() {
+                    State::Select => self.handle_selection_input(ui, &response, &canvas),
+                    State::Paint => self.handle_paint_input(ui, &response, &canvas),
+                    State::Text => self.handle_text_input(ui, &response, &canvas),
+                    State::Shapes => self.handle_shapes_input(ui, &response, &canvas),
+                    State::Redact => self.handle_redaction_input(ui, &response, &canvas),
+                    State::Waiting => self.handle_layer_input(ui, &response, &canvas),
+                    _ => {},
+                }
+                self.handle_view_input(ui, &response, &canvas);
             }
         });
+
+
+        if *self.state() == State::Convolution && self.kernel_editor_open() {
+            let mut open = true;
+            let mut kernel = self.kernel().clone();
+            let mut preview = false;
+            egui::Window::new("Kernel Editor")
+                .open(&mut open)
+                .resizable(false)
+                .show(ctx, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label("Name:");
+                        ui.text_edit_singleline(&mut kernel.name);
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Size:");
+                        for size in KERNEL_SIZES {
+                            if ui.selectable_label(kernel.size == size, format!("{0}×{0}", size)).clicked() {
+                                kernel.resize(size);
+                            }
+                        }
+                    });
+                    ui.add_space(padding);
+                    egui::Grid::new("kernel_grid").show(ui, |ui| {
+                        for row in 0..kernel.size {
+                            for col in 0..kernel.size {
+                                let mut value = kernel.get(row, col);
+                                ui.add(egui::DragValue::new(&mut value).speed(0.1));
+                                kernel.set(row, col, value);
+                            }
+                            ui.end_row();
+                        }
+                    });
+                    ui.add_space(padding);
+                    ui.horizontal(|ui| {
+                        ui.label("Divisor:");
+                        ui.add(egui::DragValue::new(&mut kernel.divisor).speed(0.1));
+                        ui.label("Offset:");
+                        ui.add(egui::DragValue::new(&mut kernel.offset).speed(1.0));
+                        if ui.button("Normalize").clicked() {
+                            kernel.normalize();
+                        }
+                    });
+                    ui.separator();
+                    ui.horizontal(|ui| {
+                        if ui.button("Preview").clicked() {
+                            preview = true;
+                        }
+                        if ui.button("Save Kernel...").clicked() {
+                            if let Some(path) = rfd::FileDialog::new()
+                                .add_filter("kernel", &["kernel"])
+                                .set_file_name(&format!("{}.kernel", kernel.name))
+                                .save_file() {
+                                match kernel.save(&path) {
+                                    Ok(()) => {
+                                        self.add_custom_kernel(kernel.clone());
+                                        self.set_kernel_message(Some(format!("Saved \"{}\"", kernel.name)));
+                                    },
+                                    Err(err) => self.set_kernel_message(Some(format!("Could not save kernel: {}", err))),
+                                }
+                            }
+                        }
+                        if ui.button("Load Kernel...").clicked() {
+                            if let Some(path) = rfd::FileDialog::new()
+                                .add_filter("kernel", &["kernel"])
+                                .pick_file() {
+                                match Kernel::load(&path) {
+                                    Ok(loaded) => {
+                                        self.add_custom_kernel(loaded.clone());
+                                        self.set_kernel_message(Some(format!("Loaded \"{}\"", loaded.name)));
+                                        kernel = loaded;
+                                        preview = true;
+                                    },
+                                    Err(err) => self.set_kernel_message(Some(format!("Could not load kernel: {}", err))),
+                                }
+                            }
+                        }
+                    });
+                    if let Some(message) = self.kernel_message() {
+                        ui.label(message);
+                    }
+                });
+            self.set_kernel(kernel);
+            self.set_kernel_editor_open(open);
+            if preview {
+                self.set_current_img_edited_path(self.apply_convolution());
+            }
+        }
+
+        if self.settings_open() {
+            let mut open = true;
+            let mut preferences = self.preferences().clone();
+            egui::Window::new("Settings")
+                .open(&mut open)
+                .resizable(false)
+                .show(ctx, |ui| {
+                    egui::Grid::new("settings_grid").num_columns(2).show(ui, |ui| {
+                        ui.label("Theme:");
+                        ui.horizontal(|ui| {
+                            for theme in Theme::ALL {
+                                ui.selectable_value(&mut preferences.theme, theme, theme.name());
+                            }
+                        });
+                        ui.end_row();
+                        ui.label("UI scale:");
+                        ui.add(egui::Slider::new(&mut preferences.ui_scale, UI_SCALE_RANGE).step_by(0.05));
+                        ui.end_row();
+                        ui.label("Export format:");
+                        egui::ComboBox::from_id_source("export_format")
+                            .selected_text(preferences.export_format.name())
+                            .show_ui(ui, |ui| {
+                                for format in ExportFormat::ALL {
+                                    ui.selectable_value(&mut preferences.export_format, format, format.name());
+                                }
+                            });
+                        ui.end_row();
+                        ui.label("Export quality:");
+                        ui.add_enabled(
+                            preferences.export_format.has_quality(),
+                            egui::Slider::new(&mut preferences.export_quality, 1..=100),
+                        );
+                        ui.end_row();
+                        ui.label("History size:");
+                        ui.add(egui::Slider::new(&mut preferences.history_size, HISTORY_SIZE_RANGE));
+                        ui.end_row();
+                        ui.label("Preview quality:");
+                        ui.horizontal(|ui| {
+                            for quality in PreviewQuality::ALL {
+                                ui.selectable_value(&mut preferences.preview_quality, quality, quality.name());
+                            }
+                        });
+                        ui.end_row();
+                    });
+                    ui.collapsing("Slider ranges", |ui| {
+                        egui::Grid::new("slider_ranges_grid").num_columns(2).show(ui, |ui| {
+                            let ranges = &mut preferences.slider_ranges;
+                            ui.label("Blur:");
+                            ui.add(egui::DragValue::new(&mut ranges.blur).clamp_range(1.0..=200.0));
+                            ui.end_row();
+                            ui.label("Brighten:");
+                            ui.add(egui::DragValue::new(&mut ranges.brighten).clamp_range(1..=255));
+                            ui.end_row();
+                            ui.label("Contrast:");
+                            ui.add(egui::DragValue::new(&mut ranges.contrast).clamp_range(1.0..=200.0));
+                            ui.end_row();
+                            ui.label("Brush size:");
+                            ui.add(egui::DragValue::new(&mut ranges.brush_size).clamp_range(10.0..=1000.0));
+                            ui.end_row();
+                        });
+                    });
+                    ui.collapsing("Sizes", |ui| {
+                        egui::Grid::new("ui_sizes_grid").num_columns(2).show(ui, |ui| {
+                            let sizes = &mut preferences.ui_sizes;
+                            ui.label("Tool buttons text:");
+                            ui.add(egui::DragValue::new(&mut sizes.tool_font_size).clamp_range(FONT_SIZE_RANGE));
+                            ui.end_row();
+                            ui.label("Text:");
+                            ui.add(egui::DragValue::new(&mut sizes.font_size).clamp_range(FONT_SIZE_RANGE));
+                            ui.end_row();
+                            ui.label("Monospace text:");
+                            ui.add(egui::DragValue::new(&mut sizes.monospace_font_size).clamp_range(FONT_SIZE_RANGE));
+                            ui.end_row();
+                            ui.label("Tools panel width:");
+                            ui.add(egui::DragValue::new(&mut sizes.side_panel_width).clamp_range(100.0..=400.0));
+                            ui.end_row();
+                            ui.label("Padding:");
+                            ui.add(egui::DragValue::new(&mut sizes.padding).clamp_range(0.0..=20.0));
+                            ui.end_row();
+                        });
+                    });
+                    ui.collapsing("Keyboard shortcuts", |ui| {
+                        egui::ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                            egui::Grid::new("keymap_grid").num_columns(3).show(ui, |ui| {
+                                for action in Action::ALL {
+                                    ui.label(action.name());
+                                    let label = match (self.rebinding(), preferences.keymap.shortcut(action)) {
+                                        (Some(rebinding), _) if rebinding == action => String::from("Press a key..."),
+                                        (_, Some(shortcut)) => shortcut.label(),
+                                        (_, None) => String::from("None"),
+                                    };
+                                    if ui.button(label).clicked() {
+                                        self.set_rebinding(Some(action));
+                                    }
+                                    if ui.small_button("✖").on_hover_text("Remove shortcut").clicked() {
+                                        preferences.keymap.bind(action, None);
+                                    }
+                                    ui.end_row();
+                                }
+                            });
+                        });
+                    });
+                    ui.collapsing("Plugins", |ui| {
+                        if let Some(dir) = plugin::plugins_dir() {
+                            ui.label("WebAssembly filters are loaded from:");
+                            ui.monospace(dir.display().to_string());
+                        }
+                        for err in self.plugin_errors() {
+                            ui.colored_label(Color32::RED, err);
+                        }
+                        if ui.button("Reload plugins").clicked() {
+                            // The filter being previewed may move or go away
+                            if matches!(self.state(), State::Operation(_)) {
+                                self.cancel_edit();
+                            }
+                            self.reload_plugins();
+                        }
+                    });
+                    ui.add_space(padding);
+                    if ui.button("Restore defaults").clicked() {
+                        preferences = Default::default();
+                    }
+                });
+            self.set_preferences(preferences);
+            self.set_settings_open(open);
+            if !open {
+                self.set_rebinding(None);
+            }
+        }
+
+        if self.batch_open() {
+            let mut open = true;
+            let mut settings = self.batch_settings().clone();
+            let running = self.batch().as_ref().is_some_and(|batch| batch.is_running());
+            let mut start = false;
+            let mut cancel = false;
+            egui::Window::new("Batch")
+                .open(&mut open)
+                .resizable(false)
+                .show(ctx, |ui| {
+                    ui.add_enabled_ui(!running, |ui| {
+                        ui.horizontal(|ui| {
+                            ui.label(format!("{} files", settings.inputs.len()));
+                            if ui.button("Add files...").clicked() {
+                                if let Some(paths) = rfd::FileDialog::new()
+                                    .add_filter("image", &IMAGE_EXTENSIONS)
+                                    .pick_files() {
+                                        settings.add_inputs(paths);
+                                }
+                            }
+                            if ui.button("Add folder...").clicked() {
+                                if let Some(dir) = rfd::FileDialog::new().pick_folder() {
+                                    settings.add_inputs(batch::images_in(&dir));
+                                }
+                            }
+                            if ui.button("Clear").clicked() {
+                                settings.inputs.clear();
+                            }
+                        });
+                        egui::Grid::new("batch_grid").num_columns(2).show(ui, |ui| {
+                            ui.label("Edits:");
+                            let selected = match &settings.source {
+                                BatchSource::Session => format!("Session edits ({})", self.adjustments().len()),
+                                BatchSource::Preset(path) => self.presets()
+                                    .iter()
+                                    .find(|(p, _)| p == path)
+                                    .map_or_else(|| String::from("Missing preset"), |(_, preset)| preset.name.clone()),
+                            };
+                            egui::ComboBox::from_id_source("batch_source")
+                                .selected_text(selected)
+                                .show_ui(ui, |ui| {
+                                    let label = format!("Session edits ({})", self.adjustments().len());
+                                    ui.selectable_value(&mut settings.source, BatchSource::Session, label);
+                                    for (path, preset) in self.presets() {
+                                        ui.selectable_value(&mut settings.source, BatchSource::Preset(path.clone()), &preset.name);
+                                    }
+                                });
+                            ui.end_row();
+                            ui.label("Output folder:");
+                            ui.horizontal(|ui| {
+                                if ui.button("Choose...").clicked() {
+                                    if let Some(dir) = rfd::FileDialog::new().pick_folder() {
+                                        settings.output_dir = Some(dir);
+                                    }
+                                }
+                                if let Some(dir) = &settings.output_dir {
+                                    ui.monospace(dir.display().to_string());
+                                }
+                            });
+                            ui.end_row();
+                            ui.label("Naming pattern:");
+                            ui.text_edit_singleline(&mut settings.pattern)
+                                .on_hover_text("{name}: input file name\n{ext}: format extension\n{index}: position in the batch");
+                            ui.end_row();
+                            ui.label("Format:");
+                            egui::ComboBox::from_id_source("batch_format")
+                                .selected_text(settings.format.name())
+                                .show_ui(ui, |ui| {
+                                    for format in ExportFormat::ALL {
+                                        ui.selectable_value(&mut settings.format, format, format.name());
+                                    }
+                                });
+                            ui.end_row();
+                        });
+                        if let Some(input) = settings.inputs.first() {
+                            ui.label(format!("e.g. {}", batch::output_name(&settings.pattern, input, 0, settings.format)));
+                        }
+                    });
+                    ui.add_space(padding);
+                    ui.horizontal(|ui| {
+                        let can_start = !running
+                            && !settings.inputs.is_empty()
+                            && settings.output_dir.is_some()
+                            && self.batch_preset().is_some_and(|preset| !preset.adjustments.is_empty());
+                        if ui.add_enabled(can_start, egui::Button::new("Start")).clicked() {
+                            start = true;
+                        }
+                        if ui.add_enabled(running, egui::Button::new("Cancel")).clicked() {
+                            cancel = true;
+                        }
+                    });
+
+                    if let Some(batch) = self.batch() {
+                        ui.separator();
+                        let total = batch.statuses().len();
+                        let finished = batch.finished_count();
+                        let label = if running && batch.is_cancelled() { String::from("Cancelling...") } else { format!("{}/{}", finished, total) };
+                        ui.add(egui::ProgressBar::new(finished as f32 / total.max(1) as f32).text(label));
+                        egui::ScrollArea::vertical().max_height(200.0).show(ui, |ui| {
+                            egui::Grid::new("batch_files_grid").num_columns(2).show(ui, |ui| {
+                                for (path, status) in batch.inputs().iter().zip(batch.statuses()) {
+                                    ui.label(path.file_name().unwrap_or_default().to_string_lossy().to_string())
+                                        .on_hover_text(path.display().to_string());
+                                    match status {
+                                        FileStatus::Waiting => ui.label("Waiting"),
+                                        FileStatus::Processing => ui.label("Processing..."),
+                                        FileStatus::Done(output) => ui.label("Done").on_hover_text(output.display().to_string()),
+                                        FileStatus::Failed(_) => ui.colored_label(Color32::RED, "Failed"),
+                                        FileStatus::Cancelled => ui.label("Cancelled"),
+                                    };
+                                    ui.end_row();
+                                }
+                            });
+                        });
+                        let failures = batch.failures();
+                        if !failures.is_empty() {
+                            ui.collapsing(format!("Failures ({})", failures.len()), |ui| {
+                                for (path, err) in failures {
+                                    ui.colored_label(Color32::RED, format!("{}: {}", path.display(), err));
+                                }
+                            });
+                        }
+                    }
+                });
+            self.set_batch_settings(settings);
+            if start {
+                self.start_batch();
+            }
+            if cancel {
+                self.cancel_batch();
+            }
+            self.set_batch_open(open);
+        }
+        if self.script_open() {
+            let mut open = true;
+            let mut name = self.script_name().clone();
+            let mut source = self.script_source().clone();
+            let mut run = false;
+            let mut save = false;
+            egui::Window::new("Script Console")
+                .open(&mut open)
+                .default_width(400.)
+                .show(ctx, |ui| {
+                    ui.label("The open image is the `image` variable, what is left in it becomes a new version");
+                    ui.add(egui::TextEdit::multiline(&mut source)
+                        .code_editor()
+                        .desired_rows(12)
+                        .desired_width(f32::INFINITY)
+                        .hint_text("image.blur(2.0);"));
+                    ui.horizontal(|ui| {
+                        run = ui.add_enabled(!source.trim().is_empty() && !self.script_running(), egui::Button::new("Run")).clicked();
+                        if self.script_running() {
+                            ui.spinner();
+                        }
+                        ui.add(egui::TextEdit::singleline(&mut name).hint_text("Name").desired_width(110.));
+                        save = ui.add_enabled(!name.trim().is_empty(), egui::Button::new("Save"))
+                            .on_hover_text("Keep the script in the presets folder")
+                            .clicked();
+                        if ui.button("Open...").clicked() {
+                            if let Some(path) = rfd::FileDialog::new()
+                                .add_filter("script", &[SCRIPT_EXTENSION])
+                                .pick_file() {
+                                    if let Err(err) = self.open_script(&path) {
+                                        self.log_script(format!("Could not open script: {}", err));
+                                    }
+                                    name = self.script_name().clone();
+                                    source = self.script_source().clone();
+                            }
+                        }
+                        if ui.button("Clear output").clicked() {
+                            self.clear_script_log();
+                        }
+                    });
+                    ui.separator();
+                    egui::ScrollArea::vertical()
+                        .max_height(150.)
+                        .stick_to_bottom(true)
+                        .show(ui, |ui| {
+                            for line in self.script_log() {
+                                ui.monospace(line);
+                            }
+                        });
+                });
+            self.set_script_name(name);
+            self.set_script_source(source);
+            if run {
+                self.cancel_edit();
+                self.run_script();
+            }
+            if save {
+                if let Err(err) = self.save_script() {
+                    self.log_script(format!("Could not save script: {}", err));
+                }
+            }
+            self.set_script_open(open);
+        }
+
+        // The workers don't wake the window, so it keeps polling while they run
+        self.poll_batch();
+        if self.batch().as_ref().is_some_and(|batch| batch.is_running()) {
+            ctx.request_repaint();
+        }
+        self.poll_operation();
+        self.poll_script();
+        if self.operation_running() || self.script_running() || self.preset_thumbnails_running() {
+            ctx.request_repaint();
+        }
+
+        if self.shortcuts_open() {
+            let mut open = true;
+            egui::Window::new("Keyboard Shortcuts")
+                .open(&mut open)
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, vec2(0.0, 0.0))
+                .show(ctx, |ui| {
+                    egui::Grid::new("shortcuts_grid").num_columns(4).spacing([20.0, 4.0]).show(ui, |ui| {
+                        let keymap = &self.preferences().keymap;
+                        let bound: Vec<(Action, Shortcut)> = Action::ALL
+                            .iter()
+                            .filter_map(|action| keymap.shortcut(*action).map(|shortcut| (*action, shortcut)))
+                            .collect();
+                        for row in bound.chunks(2) {
+                            for (action, shortcut) in row {
+                                ui.monospace(shortcut.label());
+                                ui.label(action.name());
+                            }
+                            ui.end_row();
+                        }
+                    });
+                });
+            if !open {
+                self.set_shortcuts_open(false);
+            }
+        }
+
+        // Session left behind by a crash
+        if self.unsaved_session().is_some() {
+            let mut restore = false;
+            let mut discard = false;
+            egui::Window::new("Restore session")
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, vec2(0.0, 0.0))
+                .show(ctx, |ui| {
+                    ui.label("The editor was not closed properly last time. Restore the unsaved session?");
+                    ui.horizontal(|ui| {
+                        restore = ui.button("Restore").clicked();
+                        discard = ui.button("Discard").clicked();
+                    });
+                });
+            if restore {
+                self.restore_unsaved_session();
+            } else if discard {
+                self.discard_unsaved_session();
+            }
+        }
+        self.autosave();
+    }
+
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        self.save_storage(storage);
+    }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        self.discard_autosave();
+    }
+}
+
+impl image_editor::ImageEditor {
+    fn open_image_dialog(&mut self) {
+        if let Some(path) = rfd::FileDialog::new()
+            .add_filter("image", &["png", "jpg", "jpeg", "webp"])
+            .pick_file() {
+                self.open_image(path);
+        }
+    }
+
+    // Save writes again where the image was last saved, Save As always asks
+    fn save_image(&mut self, save_as: bool) {
+        if self.current_img_path().is_none() {
+            return;
+        }
+        if let (false, Some(path)) = (save_as, self.export_path().clone()) {
+            self.export_current_image(&path);
+            return;
+        }
+        let file_name = std::path::Path::new(&self.initial_image_name().unwrap()).with_extension(self.preferences().export_format.extension());
+        if let Some(path) = rfd::FileDialog::new()
+            .add_filter("image", &["png", "jpg", "jpeg", "webp"])
+            .set_file_name(&file_name.to_string_lossy())
+            .save_file() {
+            self.export_current_image(&path);
+            self.set_export_path(Some(path));
+        }
+    }
+
+    fn undo(&mut self) {
+        if self.versions().is_empty() {
+            return;
+        }
+        // current_img_path > versions_discart
+        self.push_back_versions_discart(self.current_img_path().clone());
+
+        // versions.pop() > current_img_path
+        let new_current_img_path = self.pop_back_versions();
+        self.set_current_img_path(new_current_img_path);
+        self.undo_adjustment();
+        if *self.state() == State::Paint {
+            self.set_current_img_edited_path(self.current_img_path().clone());
+        } else if *self.state() == State::Text || *self.state() == State::Shapes {
+            self.refresh_overlay_preview();
+        }
+    }
+
+    fn redo(&mut self) {
+        if self.versions_discart().is_empty() {
+            return;
+        }
+        // current_img_path > versions
+        self.push_back_versions(self.current_img_path().clone());
+
+        // versions_discart.pop() > current_img_path
+        let new_current_img_path = self.pop_back_versions_discart();
+        self.set_current_img_path(new_current_img_path);
+        self.redo_adjustment();
+        if *self.state() == State::Paint {
+            self.set_current_img_edited_path(self.current_img_path().clone());
+        } else if *self.state() == State::Text || *self.state() == State::Shapes {
+            self.refresh_overlay_preview();
+        }
+    }
+
+    // Whatever the previous mode was previewing is discarded, except for slider
+    // effects which keep adding up in the same pending edit
+    fn select_tool(&mut self, state: State) {
+        if self.current_img_path().is_none() {
+            return;
+        }
+        if !(self.state().uses_params() && state.uses_params()) {
+            self.cancel_edit();
+        }
+        let text = state == State::Text;
+        let operation = matches!(state, State::Operation(_));
+        self.set_state(state);
+        if text {
+            self.load_fonts();
+            self.refresh_overlay_preview();
+        }
+        // Filters are previewed with their default values right away
+        if operation {
+            self.reset_operation_values();
+            self.preview_operation();
+        }
+    }
+
+    fn apply_changes(&mut self) {
+        match self.state() {
+            State::Waiting => {},
+            // Selections are not edits and strokes are applied as they are drawn
+            State::Select | State::Paint => self.set_state(State::Waiting),
+            _ => {
+                // An edit that can't be replayed, like the filter of a plugin gone since, is not applied
+                let Some(adjustment) = self.current_adjustment() else {
+                    self.cancel_edit();
+                    return;
+                };
+                self.finish_pending_edit();
+                self.prepare_new_edition(adjustment);
+                self.set_state(State::Waiting);
+                self.set_current_img_path(self.current_img_edited_path().clone());
+                self.clear_effects_values();
+            },
+        }
+    }
+
+    // Leave the current mode without applying what is being previewed
+    fn cancel_edit(&mut self) {
+        if self.current_img_path().is_some() {
+            self.set_current_img_edited_path(self.current_img_path().clone());
+        }
+        self.set_state(State::Waiting);
+        self.clear_effects_values();
+    }
+
+    fn run_action(&mut self, action: Action) {
+        match action {
+            Action::Open => self.open_image_dialog(),
+            Action::Save => self.save_image(false),
+            Action::SaveAs => self.save_image(true),
+            Action::Undo => self.undo(),
+            Action::Redo => self.redo(),
+            Action::Apply => self.apply_changes(),
+            Action::Cancel if self.shortcuts_open() => self.set_shortcuts_open(false),
+            Action::Cancel => self.cancel_edit(),
+            Action::Shortcuts => self.set_shortcuts_open(!self.shortcuts_open()),
+            _ => {
+                let operation = action.operation().and_then(|name| {
+                    self.operations().operations().iter().position(|operation| operation.name() == name)
+                });
+                if let Some(state) = action.state().or(operation.map(State::Operation)) {
+                    if *self.state() != state {
+                        self.select_tool(state);
+                    }
+                }
+            },
+        }
+    }
+
+    fn handle_shortcuts(&mut self, ctx: &Context) {
+        // The keymap editor takes the next key press as the new shortcut
+        if let Some(action) = self.rebinding() {
+            let pressed = ctx.input().events.iter().find_map(|event| match event {
+                egui::Event::Key { key, pressed: true, modifiers } => Some(Shortcut::from_event(*key, *modifiers)),
+                _ => None,
+            });
+            if let Some(shortcut) = pressed {
+                ctx.input_mut().consume_key(shortcut.modifiers(), shortcut.key);
+                let mut preferences = self.preferences().clone();
+                preferences.keymap.bind(action, Some(shortcut));
+                self.set_preferences(preferences);
+                self.set_rebinding(None);
+            }
+            return;
+        }
+        // While typing, keys without Ctrl belong to the text field and so do its own undo and redo
+        let typing = ctx.wants_keyboard_input();
+        let bindings = self.preferences().keymap.bindings().clone();
+        for (action, shortcut) in bindings {
+            if typing && (!shortcut.ctrl || action == Action::Undo || action == Action::Redo) {
+                continue;
+            }
+            if ctx.input_mut().consume_key(shortcut.modifiers(), shortcut.key) {
+                self.run_action(action);
+            }
+        }
+    }
+
+    // Mouse wheel zooms around the pointer and the middle button pans the image
+    fn handle_view_input(&mut self, ui: &egui::Ui, response: &egui::Response, canvas: &Canvas) {
+        let scroll = ui.input().scroll_delta.y;
+        if let (true, Some(pointer)) = (scroll != 0.0, response.hover_pos()) {
+            let old_zoom = self.zoom();
+            self.set_zoom(old_zoom * (scroll / 200.0).exp());
+            // Keep the pixel under the pointer in place
+            let offset = pointer - canvas.rect().min;
+            let new_min = pointer - offset * (self.zoom() / old_zoom);
+            self.set_pan(self.pan() + (new_min - canvas.rect().min));
+        }
+        if response.dragged_by(PointerButton::Middle) {
+            self.set_pan(self.pan() + response.drag_delta());
+        }
+        if response.double_clicked_by(PointerButton::Middle) {
+            self.reset_view();
+        }
+    }
+
+    fn handle_paint_input(&mut self, ui: &egui::Ui, response: &egui::Response, canvas: &Canvas) {
+        let pointer = response.interact_pointer_pos().map(|pos| canvas.to_image(pos));
+        let primary_down = ui.input().pointer.primary_down();
+        if self.brush().tool == PaintTool::ColorPicker {
+            if let (true, Some(point)) = (response.clicked_by(PointerButton::Primary), pointer) {
+                self.pick_color(point);
+            }
+            return;
+        }
+
+        let modifiers = ui.input().modifiers;
+        if self.brush().tool == PaintTool::CloneStamp && (modifiers.ctrl || modifiers.alt || modifiers.command) && !self.is_painting() {
+            if let (true, Some(point)) = (primary_down && response.is_pointer_button_down_on(), pointer) {
+                self.set_clone_source(point);
+            }
+        } else if let (true, Some(point)) = (primary_down && response.is_pointer_button_down_on(), pointer) {
+            if self.is_painting() {
+                self.continue_stroke(point);
+            } else {
+                self.begin_stroke(point);
+            }
+        } else if self.is_painting() && !primary_down {
+            self.end_stroke();
+        }
+
+        // Brush outline under the pointer
+        if let Some(pos) = response.hover_pos() {
+            let radius = self.brush().size / 2.0 * canvas.scale();
+            ui.painter().with_clip_rect(response.rect).circle_stroke(pos, radius, Stroke::new(1.0, Color32::GRAY));
+        }
+
+        // Cross on the pixels the clone stamp copies from
+        if self.brush().tool == PaintTool::CloneStamp {
+            if let Some(source) = response.hover_pos().and_then(|pos| self.clone_source_for(canvas.to_image(pos))) {
+                let center = canvas.to_screen(source);
+                let painter = ui.painter().with_clip_rect(response.rect);
+                let stroke = Stroke::new(1.5, Color32::from_rgb(0, 170, 255));
+                painter.line_segment([center - vec2(6.0, 0.0), center + vec2(6.0, 0.0)], stroke);
+                painter.line_segment([center - vec2(0.0, 6.0), center + vec2(0.0, 6.0)], stroke);
+            }
+        }
+    }
+
+    fn handle_layer_input(&mut self, ui: &egui::Ui, response: &egui::Response, canvas: &Canvas) {
+        let pointer = response.interact_pointer_pos().map(|pos| canvas.to_image(pos));
+        let primary_down = ui.input().pointer.primary_down();
+        if let (true, Some(point)) = (response.drag_started() && primary_down, pointer) {
+            self.grab_layer(point);
+        } else if let (true, Some(point)) = (response.dragged_by(PointerButton::Primary), pointer) {
+            self.drag_layer(point);
+        }
+        if response.drag_released() {
+            self.release_layer();
+        }
+
+        // Frame around the selected layer
+        if let Some(layer) = self.selected_layer().map(|index| &self.layers()[index]) {
+            let (x, y) = layer.settings.position;
+            let (w, h) = layer.size();
+            let rect = Rect::from_two_pos(canvas.to_screen((x, y)), canvas.to_screen((x + w, y + h)));
+            ui.painter().with_clip_rect(response.rect).rect_stroke(rect, 0.0, Stroke::new(1.5, Color32::from_rgb(0, 170, 255)));
+        }
+    }
+
+    fn handle_redaction_input(&mut self, ui: &egui::Ui, response: &egui::Response, canvas: &Canvas) {
+        let pointer = response.interact_pointer_pos().map(|pos| canvas.to_image(pos));
+        let primary_down = ui.input().pointer.primary_down();
+        if let (true, Some(point)) = (response.drag_started() && primary_down, pointer) {
+            self.start_redaction_region(point);
+        } else if let (true, Some(point)) = (response.dragged_by(PointerButton::Primary), pointer) {
+            self.extend_redaction_region(point);
+        }
+        if response.drag_released() {
+            self.finish_redaction_region();
+        }
+
+        // Outline of the regions, the one being dragged is highlighted
+        let painter = ui.painter().with_clip_rect(response.rect);
+        for (a, b) in self.redaction_regions() {
+            let rect = Rect::from_two_pos(canvas.to_screen(*a), canvas.to_screen(*b));
+            painter.rect_stroke(rect, 0.0, Stroke::new(1.0, Color32::from_gray(160)));
+        }
+        if let Some((a, b)) = self.redaction_drag() {
+            let rect = Rect::from_two_pos(canvas.to_screen(a), canvas.to_screen(b));
+            painter.rect_stroke(rect, 0.0, Stroke::new(1.5, Color32::from_rgb(0, 170, 255)));
+        }
+    }
+
+    fn handle_shapes_input(&mut self, ui: &egui::Ui, response: &egui::Response, canvas: &Canvas) {
+        let pointer = response.interact_pointer_pos().map(|pos| canvas.to_image(pos));
+        let primary_down = ui.input().pointer.primary_down();
+        if let (true, Some(point)) = (response.drag_started() && primary_down, pointer) {
+            // A few screen points of slack to grab thin lines
+            self.begin_annotation(point, 4.0 / canvas.scale());
+        } else if let (true, Some(point)) = (response.dragged_by(PointerButton::Primary), pointer) {
+            self.drag_annotation(point);
+        }
+        if response.drag_released() {
+            self.end_annotation();
+        }
+
+        // Frame around the selected shape
+        if let Some(index) = self.selected_annotation() {
+            let (min, max) = self.annotations()[index].bounds();
+            let rect = Rect::from_two_pos(canvas.to_screen(min), canvas.to_screen(max));
+            ui.painter().with_clip_rect(response.rect).rect_stroke(rect, 0.0, Stroke::new(1.5, Color32::from_rgb(0, 170, 255)));
+        }
+    }
+
+    fn handle_text_input(&mut self, ui: &egui::Ui, response: &egui::Response, canvas: &Canvas) {
+        let pointer = response.interact_pointer_pos().map(|pos| canvas.to_image(pos));
+        let primary_down = ui.input().pointer.primary_down();
+        if let (true, Some(point)) = (response.drag_started() && primary_down, pointer) {
+            self.grab_text_box(point);
+        } else if let (true, Some(point)) = (response.dragged_by(PointerButton::Primary), pointer) {
+            self.drag_text_box(point);
+        } else if let (true, Some(point)) = (response.clicked_by(PointerButton::Primary), pointer) {
+            if !self.grab_text_box(point) {
+                self.add_text_box(point);
+            }
+        }
+        if response.drag_released() {
+            self.release_text_box();
+        }
+
+        // Frame around every box, highlighting the selected one
+        let painter = ui.painter().with_clip_rect(response.rect);
+        for index in 0..self.text_boxes().len() {
+            if let Some(corners) = self.text_box_corners(index) {
+                let points = corners.iter().map(|c| canvas.to_screen(*c)).collect();
+                let stroke = if self.selected_text_box() == Some(index) {
+                    Stroke::new(1.5, Color32::from_rgb(0, 170, 255))
+                } else {
+                    Stroke::new(1.0, Color32::from_gray(160))
+                };
+                painter.add(egui::Shape::closed_line(points, stroke));
+            }
+        }
+    }
+
+    fn handle_selection_input(&mut self, ui: &egui::Ui, response: &egui::Response, canvas: &Canvas) {
+        if *self.selection_tool() == SelectionTool::MagicWand {
+            if let (true, Some(pos)) = (response.clicked_by(PointerButton::Primary), response.interact_pointer_pos()) {
+                self.magic_wand_select(canvas.to_image(pos));
+            }
+            return;
+        }
+
+        let primary_down = ui.input().pointer.primary_down();
+        if let (true, Some(pos)) = (response.drag_started() && primary_down, response.interact_pointer_pos()) {
+            self.start_selection(canvas.to_image(pos));
+        } else if let (true, Some(pos)) = (response.dragged_by(PointerButton::Primary), response.interact_pointer_pos()) {
+            self.extend_selection(canvas.to_image(pos));
+        }
+        if response.drag_released() && !self.selection_points().is_empty() {
+            self.finish_selection();
+        }
+
+        // Outline of the shape being dragged
+        let points: Vec<egui::Pos2> = self.selection_points().iter().map(|p| canvas.to_screen(*p)).collect();
+        if points.len() < 2 {
+            return;
+        }
+        let stroke = Stroke::new(1.5, Color32::from_rgb(0, 170, 255));
+        let painter = ui.painter().with_clip_rect(canvas.rect());
+        let bounds = Rect::from_two_pos(points[0], points[points.len() - 1]);
+        match self.selection_tool() {
+            SelectionTool::Rectangle => {
+                painter.rect_stroke(bounds, 0.0, stroke);
+            },
+            SelectionTool::Ellipse => {
+                let outline = (0..=64)
+                    .map(|i| {
+                        let angle = i as f32 / 64.0 * std::f32::consts::TAU;
+                        bounds.center() + vec2(angle.cos() * bounds.width() / 2.0, angle.sin() * bounds.height() / 2.0)
+                    })
+                    .collect();
+                painter.add(egui::Shape::line(outline, stroke));
+            },
+            SelectionTool::Lasso => {
+                painter.add(egui::Shape::closed_line(points, stroke));
+            },
+            SelectionTool::MagicWand => {},
+        }
+    }
+}
+
+// Slider with a box to type an exact value and a button that puts the default back.
+// A reset counts as a change of the returned response
+fn effect_slider<N: egui::emath::Numeric>(ui: &mut egui::Ui, value: &mut N, range: RangeInclusive<N>, text: &str, default: N) -> egui::Response {
+    let speed = (range.end().to_f64() - range.start().to_f64()) / 200.0;
+    let mut response = ui.add(egui::Slider::new(value, range.clone()).show_value(false));
+    response |= ui.add(egui::DragValue::new(value).clamp_range(range).speed(speed));
+    if !text.is_empty() {
+        ui.label(text);
+    }
+    if ui.small_button("⟲").on_hover_text("Reset").clicked() && *value != default {
+        *value = default;
+        response.mark_changed();
+    }
+    response
+}
+
+// The widget for a parameter of a registry filter, picked from its type
+fn operation_control(ui: &mut egui::Ui, spec: &ParamSpec, value: &mut ParamValue) {
+    match (&spec.kind, value) {
+        (ParamKind::Float { min, max, default }, ParamValue::Float(value)) => {
+            effect_slider(ui, value, *min..=*max, &spec.name, *default);
+        },
+        (ParamKind::Int { min, max, default }, ParamValue::Int(value)) => {
+            effect_slider(ui, value, *min..=*max, &spec.name, *default);
+        },
+        (ParamKind::Bool { .. }, ParamValue::Bool(value)) => {
+            ui.checkbox(value, &spec.name);
+        },
+        (ParamKind::Choice { options, .. }, ParamValue::Choice(value)) => {
+            egui::ComboBox::from_label(&spec.name)
+                .selected_text(options.get(*value).cloned().unwrap_or_default())
+                .show_ui(ui, |ui| {
+                    for (index, option) in options.iter().enumerate() {
+                        ui.selectable_value(value, index, option);
+                    }
+                });
+        },
+        _ => {},
+    }
+}
+
+fn paint_checkerboard(painter: &Painter, rect: Rect) {
+    painter.rect_filled(rect, 0.0, Color32::from_gray(204));
+    let mut y = rect.top();
+    let mut row = 0;
+    while y < rect.bottom() {
+        let mut x = rect.left() + if row % 2 == 0 { 0.0 } else { CHECKER_SIZE };
+        while x < rect.right() {
+            let square = Rect::from_min_max(
+                pos2(x, y),
+                pos2((x + CHECKER_SIZE).min(rect.right()), (y + CHECKER_SIZE).min(rect.bottom())),
+            );
+            painter.rect_filled(square, 0.0, Color32::from_gray(153));
+            x += 2.0 * CHECKER_SIZE;
+        }
+        y += CHECKER_SIZE;
+        row += 1;
     }
 }
 
@@ -0,0 +1,172 @@
+use std::sync::Arc;
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+use crate::filters;
+use crate::ops::{Error, Result};
+
+// The type of a parameter, with the values it accepts and the one it starts at
+#[derive(PartialEq, Clone, Debug)]
+pub enum ParamKind {
+    Float { min: f32, max: f32, default: f32 },
+    Int { min: i64, max: i64, default: i64 },
+    Bool { default: bool },
+    Choice { options: Vec<String>, default: usize },
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct ParamSpec {
+    pub name: String,
+    pub kind: ParamKind,
+}
+
+impl ParamSpec {
+    pub fn float(name: &str, min: f32, max: f32, default: f32) -> Self {
+        Self { name: name.to_string(), kind: ParamKind::Float { min, max, default } }
+    }
+
+    pub fn int(name: &str, min: i64, max: i64, default: i64) -> Self {
+        Self { name: name.to_string(), kind: ParamKind::Int { min, max, default } }
+    }
+
+    pub fn bool(name: &str, default: bool) -> Self {
+        Self { name: name.to_string(), kind: ParamKind::Bool { default } }
+    }
+
+    pub fn choice(name: &str, options: &[&str], default: usize) -> Self {
+        Self {
+            name: name.to_string(),
+            kind: ParamKind::Choice { options: options.iter().map(|option| option.to_string()).collect(), default },
+        }
+    }
+
+    pub fn default_value(&self) -> ParamValue {
+        match &self.kind {
+            ParamKind::Float { default, .. } => ParamValue::Float(*default),
+            ParamKind::Int { default, .. } => ParamValue::Int(*default),
+            ParamKind::Bool { default } => ParamValue::Bool(*default),
+            ParamKind::Choice { default, .. } => ParamValue::Choice(*default),
+        }
+    }
+
+    pub fn check(&self, value: &ParamValue) -> Result<()> {
+        let valid = match (&self.kind, value) {
+            (ParamKind::Float { min, max, .. }, ParamValue::Float(value)) => (*min..=*max).contains(value),
+            (ParamKind::Int { min, max, .. }, ParamValue::Int(value)) => (*min..=*max).contains(value),
+            (ParamKind::Bool { .. }, ParamValue::Bool(_)) => true,
+            (ParamKind::Choice { options, .. }, ParamValue::Choice(value)) => *value < options.len(),
+            _ => false,
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(Error::InvalidParameter {
+                name: self.name.clone(),
+                reason: format!("{:?} doesn't fit {:?}", value, self.kind),
+            })
+        }
+    }
+}
+
+// Tagged next to the value, as TOML can't hold the default enum layout
+#[derive(PartialEq, Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "value")]
+pub enum ParamValue {
+    Float(f32),
+    Int(i64),
+    Bool(bool),
+    Choice(usize),
+}
+
+// The getters give the zero value for a parameter of another type,
+// which can't happen once the values were checked
+impl ParamValue {
+    pub fn as_f32(&self) -> f32 {
+        match self {
+            ParamValue::Float(value) => *value,
+            _ => 0.0,
+        }
+    }
+
+    pub fn as_i64(&self) -> i64 {
+        match self {
+            ParamValue::Int(value) => *value,
+            _ => 0,
+        }
+    }
+
+    pub fn as_bool(&self) -> bool {
+        matches!(self, ParamValue::Bool(true))
+    }
+
+    pub fn as_choice(&self) -> usize {
+        match self {
+            ParamValue::Choice(value) => *value,
+            _ => 0,
+        }
+    }
+}
+
+// A filter the editor shows without knowing about it: a side panel button named
+// after it and one control per parameter in the bottom panel
+pub trait Operation: Send + Sync {
+    fn name(&self) -> &str;
+    fn params(&self) -> Vec<ParamSpec>;
+    // The values come in the order of `params`, already checked against them
+    fn apply(&self, img: &DynamicImage, values: &[ParamValue]) -> Result<DynamicImage>;
+}
+
+// Operations by name, in the order they were registered. Cloning shares the operations
+#[derive(Clone, Default)]
+pub struct Registry {
+    operations: Vec<Arc<dyn Operation>>,
+}
+
+impl Registry {
+    // The in-house filters, see `filters`
+    pub fn with_builtins() -> Self {
+        let mut registry = Self::default();
+        filters::register_builtins(&mut registry);
+        registry
+    }
+
+    // An operation with the name of one already registered replaces it
+    pub fn register(&mut self, operation: Arc<dyn Operation>) {
+        match self.operations.iter().position(|o| o.name() == operation.name()) {
+            Some(index) => self.operations[index] = operation,
+            None => self.operations.push(operation),
+        }
+    }
+
+    pub fn operations(&self) -> &Vec<Arc<dyn Operation>> {
+        &self.operations
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Arc<dyn Operation>> {
+        self.operations.get(index)
+    }
+
+    pub fn find(&self, name: &str) -> Option<&Arc<dyn Operation>> {
+        self.operations.iter().find(|operation| operation.name() == name)
+    }
+
+    pub fn validate(&self, name: &str, values: &[ParamValue]) -> Result<()> {
+        let operation = self.find(name).ok_or_else(|| Error::UnknownOperation(name.to_string()))?;
+        let specs = operation.params();
+        if specs.len() != values.len() {
+            return Err(Error::InvalidParameter {
+                name: name.to_string(),
+                reason: format!("expected {} values, got {}", specs.len(), values.len()),
+            });
+        }
+        specs.iter().zip(values).try_for_each(|(spec, value)| spec.check(value))
+    }
+
+    pub fn apply(&self, img: &DynamicImage, name: &str, values: &[ParamValue]) -> Result<DynamicImage> {
+        self.validate(name, values)?;
+        self.find(name).unwrap().apply(img, values)
+    }
+}
+
+pub fn default_values(operation: &dyn Operation) -> Vec<ParamValue> {
+    operation.params().iter().map(ParamSpec::default_value).collect()
+}
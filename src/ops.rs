@@ -0,0 +1,305 @@
+use std::fmt;
+use std::ops::RangeInclusive;
+use image::DynamicImage;
+use crate::adjustment::Adjustment;
+use crate::annotation::{Annotation, MAX_STROKE_WIDTH};
+use crate::convolution;
+use crate::layers::{self, LayerSettings};
+use crate::operation::Registry;
+use crate::params::{self, Params};
+use crate::preset::Preset;
+use crate::script::{self, FileAccess};
+use crate::text::{FontEntry, TextBox, MAX_OUTLINE_WIDTH, MAX_TEXT_SIZE};
+
+pub const MAX_POSTERIZE_LEVELS: u32 = 256;
+// The blur gets slower with the sigma, past this it would hold the caller for minutes
+pub const MAX_BLUR_SIGMA: f32 = 200.0;
+
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Error {
+    // A value the operation can't work with, like a negative blur or a rotation of 45 degrees
+    InvalidParameter { name: String, reason: String },
+    // An operation that is not in the registry, like the one of a plugin that isn't installed
+    UnknownOperation(String),
+    // An operation that could not finish, like a plugin that trapped or ran out of time
+    Failed { operation: String, reason: String },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidParameter { name, reason } => write!(f, "Invalid {}: {}", name, reason),
+            Error::UnknownOperation(name) => write!(f, "Unknown operation {}", name),
+            Error::Failed { operation, reason } => write!(f, "{} failed: {}", operation, reason),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+// NaN is never inside a range, so it is rejected with the out of range values
+fn in_range<T: PartialOrd + fmt::Debug>(name: &'static str, value: T, range: RangeInclusive<T>) -> Result<()> {
+    if range.contains(&value) {
+        Ok(())
+    } else {
+        Err(Error::InvalidParameter {
+            name: name.to_string(),
+            reason: format!("{:?} is outside {:?}..={:?}", value, range.start(), range.end()),
+        })
+    }
+}
+
+fn at_least<T: PartialOrd + fmt::Debug>(name: &'static str, value: T, min: T) -> Result<()> {
+    if value >= min {
+        Ok(())
+    } else {
+        Err(Error::InvalidParameter { name: name.to_string(), reason: format!("{:?} is below {:?}", value, min) })
+    }
+}
+
+fn finite(name: &'static str, value: f32) -> Result<()> {
+    if value.is_finite() {
+        Ok(())
+    } else {
+        Err(Error::InvalidParameter { name: name.to_string(), reason: format!("{:?} is not a number", value) })
+    }
+}
+
+pub fn validate_params(params: &Params) -> Result<()> {
+    in_range("blur", params.blur, 0.0..=MAX_BLUR_SIGMA)?;
+    finite("contrast", params.contrast)?;
+    in_range("denoise radius", params.denoise_radius, 1..=params.denoise_method.max_radius())?;
+    in_range("denoise strength", params.denoise_strength, 0.0..=100.0)?;
+    in_range("sepia", params.sepia, 0.0..=100.0)?;
+    in_range("posterize levels", params.posterize_levels, 1..=MAX_POSTERIZE_LEVELS)?;
+    at_least("pixelate block size", params.pixelate_block_size, 1)?;
+    in_range("vignette strength", params.vignette_strength, 0.0..=100.0)?;
+    in_range("vignette radius", params.vignette_radius, 0.0..=1.0)?;
+    in_range("vignette feather", params.vignette_feather, 0.0..=1.0)?;
+    in_range("grain amount", params.grain_amount, 0.0..=100.0)?;
+    at_least("grain size", params.grain_size, 1)
+}
+
+// The points of a shape are indexed by its kind, and the stroke width sizes what is drawn
+pub fn validate_annotations(annotations: &[Annotation]) -> Result<()> {
+    annotations.iter().try_for_each(|annotation| {
+        if annotation.points.len() < annotation.kind.min_points() {
+            return Err(Error::InvalidParameter {
+                name: String::from("shape"),
+                reason: format!("a {} needs {} points", annotation.kind.name(), annotation.kind.min_points()),
+            });
+        }
+        annotation.points.iter().try_for_each(|point| {
+            finite("shape point", point.0)?;
+            finite("shape point", point.1)
+        })?;
+        in_range("stroke width", annotation.style.stroke_width, 0.0..=MAX_STROKE_WIDTH)
+    })
+}
+
+// The text is drawn into a buffer sized by the font size, and the outline is a dilation of it
+pub fn validate_text_boxes(text_boxes: &[TextBox]) -> Result<()> {
+    text_boxes.iter().try_for_each(|text_box| {
+        finite("text position", text_box.position.0)?;
+        finite("text position", text_box.position.1)?;
+        finite("text rotation", text_box.rotation)?;
+        in_range("text size", text_box.size, 0.0..=MAX_TEXT_SIZE)?;
+        in_range("text outline", text_box.outline_width, 0.0..=MAX_OUTLINE_WIDTH)
+    })
+}
+
+// Layers can be any size, they are scaled and clipped when composited
+pub fn validate_layer_settings(settings: &LayerSettings) -> Result<()> {
+    finite("layer position", settings.position.0)?;
+    finite("layer position", settings.position.1)?;
+    in_range("layer scale", settings.scale, layers::SCALE_RANGE)?;
+    in_range("layer opacity", settings.opacity, 0.0..=100.0)
+}
+
+pub fn validate_adjustment(adjustment: &Adjustment, operations: &Registry) -> Result<()> {
+    match adjustment {
+        Adjustment::Blur { sigma } => in_range("blur", *sigma, 0.0..=MAX_BLUR_SIGMA),
+        Adjustment::Contrast { value } => finite("contrast", *value),
+        Adjustment::Rotate { degrees } if ![0, 90, 180, 270].contains(degrees) => Err(Error::InvalidParameter {
+            name: String::from("rotation"),
+            reason: format!("{} is not a multiple of 90 degrees", degrees),
+        }),
+        Adjustment::Convolution { kernel, .. } if !convolution::is_odd_square(kernel.size, kernel.values.len()) => {
+            Err(Error::InvalidParameter {
+                name: String::from("kernel"),
+                reason: format!(
+                    "{} values don't make an odd sized {}x{} kernel of at most {}",
+                    kernel.values.len(), kernel.size, kernel.size, convolution::MAX_KERNEL_SIZE,
+                ),
+            })
+        },
+        Adjustment::Denoise { method, radius, strength } => {
+            in_range("denoise radius", *radius, 1..=method.max_radius())?;
+            in_range("denoise strength", *strength, 0.0..=100.0)
+        },
+        Adjustment::Sepia { intensity } => in_range("sepia", *intensity, 0.0..=100.0),
+        Adjustment::Posterize { levels } => in_range("posterize levels", *levels, 1..=MAX_POSTERIZE_LEVELS),
+        Adjustment::Pixelate { block_size } => at_least("pixelate block size", *block_size, 1),
+        Adjustment::Vignette { strength, radius, feather } => {
+            in_range("vignette strength", *strength, 0.0..=100.0)?;
+            in_range("vignette radius", *radius, 0.0..=1.0)?;
+            in_range("vignette feather", *feather, 0.0..=1.0)
+        },
+        Adjustment::FilmGrain { amount, grain_size } => {
+            in_range("grain amount", *amount, 0.0..=100.0)?;
+            at_least("grain size", *grain_size, 1)
+        },
+        Adjustment::Effects { params } => validate_params(params),
+        Adjustment::Transparency { opacity, tolerance, .. } => {
+            in_range("opacity", *opacity, 0.0..=100.0)?;
+            in_range("tolerance", *tolerance, 0.0..=100.0)
+        },
+        Adjustment::Redact { block_size, .. } => at_least("redaction block size", *block_size, 1),
+        Adjustment::Operation { name, values } => operations.validate(name, values),
+        Adjustment::Text { text_boxes } => validate_text_boxes(text_boxes),
+        Adjustment::Annotate { annotations } => validate_annotations(annotations),
+        Adjustment::Preset { adjustments, .. } => {
+            adjustments.iter().try_for_each(|adjustment| validate_adjustment(adjustment, operations))
+        },
+        // Any value of these can be applied, scripts are checked when they run
+        Adjustment::Brighten { .. }
+        | Adjustment::Flip { .. }
+        | Adjustment::Grayscale
+        | Adjustment::Invert
+        | Adjustment::Rotate { .. }
+        | Adjustment::Convolution { .. }
+        | Adjustment::Threshold { .. }
+        | Adjustment::Channels { .. }
+        | Adjustment::Paint { .. }
+        | Adjustment::FlattenLayers
+        | Adjustment::Script { .. } => Ok(()),
+    }
+}
+
+// Every slider effect at once, in the order the editor previews them
+pub fn apply(img: DynamicImage, params: Params) -> Result<DynamicImage> {
+    validate_params(&params)?;
+    if params.is_identity() {
+        return Ok(img);
+    }
+    Ok(params::apply_params(&img, &params))
+}
+
+// Text boxes are drawn with the font of that name, or the first one of the list.
+// Paint strokes and flattened layers are not kept in the adjustment and change nothing
+pub fn apply_adjustment(img: DynamicImage, adjustment: &Adjustment, fonts: &[FontEntry], operations: &Registry) -> Result<DynamicImage> {
+    validate_adjustment(adjustment, operations)?;
+    // Scripts are only checked by running them, so their errors come from the run
+    if let Adjustment::Script { name, source } = adjustment {
+        return script::run(source, Some(img), fonts, operations, FileAccess::Denied, &mut Vec::new())?.ok_or_else(|| Error::Failed {
+            operation: name.clone(),
+            reason: String::from("the script left no image"),
+        });
+    }
+    Ok(adjustment.apply(&img, fonts, operations))
+}
+
+// Nothing is applied unless every edit of the preset is valid
+pub fn apply_preset(img: DynamicImage, preset: &Preset, fonts: &[FontEntry], operations: &Registry) -> Result<DynamicImage> {
+    preset.adjustments.iter().try_for_each(|adjustment| validate_adjustment(adjustment, operations))?;
+    Ok(preset.apply(&img, fonts, operations))
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+    use crate::annotation::{AnnotationStyle, ShapeKind};
+    use crate::convolution::{EdgeMode, Kernel};
+    use crate::denoise::DenoiseMethod;
+    use super::*;
+
+    fn image() -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(8, 6, |x, y| Rgba([x as u8 * 30, y as u8 * 40, 200, 255])))
+    }
+
+    fn invalid_parameter(result: Result<()>) -> String {
+        match result {
+            Err(Error::InvalidParameter { name, .. }) => name,
+            _ => panic!("expected an invalid parameter"),
+        }
+    }
+
+    #[test]
+    fn rejects_kernels_that_are_not_odd_sized_squares() {
+        let registry = Registry::default();
+        let mut kernel = Kernel::new("Even", 3);
+        kernel.size = 4;
+        let even = Adjustment::Convolution { kernel, edge_mode: EdgeMode::Extend };
+        assert_eq!(invalid_parameter(validate_adjustment(&even, &registry)), "kernel");
+
+        let mut kernel = Kernel::new("Short", 3);
+        kernel.values.pop();
+        let short = Adjustment::Convolution { kernel, edge_mode: EdgeMode::Extend };
+        assert_eq!(invalid_parameter(validate_adjustment(&short, &registry)), "kernel");
+
+        // 4294967297² wraps around to 1 on 64 bits
+        let mut kernel = Kernel::new("Huge", 1);
+        kernel.size = 4294967297;
+        let huge = Adjustment::Convolution { kernel, edge_mode: EdgeMode::Extend };
+        assert_eq!(invalid_parameter(validate_adjustment(&huge, &registry)), "kernel");
+        assert!(Kernel::from_values("Huge", 4294967297, &[1.0], 1.0, 0.0).is_err());
+    }
+
+    #[test]
+    fn rejects_out_of_range_radius() {
+        let registry = Registry::default();
+        let method = DenoiseMethod::Median;
+        for radius in [0, method.max_radius() + 1] {
+            let denoise = Adjustment::Denoise { method, radius, strength: 50.0 };
+            assert_eq!(invalid_parameter(validate_adjustment(&denoise, &registry)), "denoise radius");
+        }
+        let params = Params { denoise_radius: 0, ..Params::default() };
+        assert_eq!(invalid_parameter(validate_params(&params)), "denoise radius");
+    }
+
+    #[test]
+    fn rejects_out_of_range_blur() {
+        let registry = Registry::default();
+        for sigma in [-1.0, f32::NAN, MAX_BLUR_SIGMA + 1.0] {
+            assert_eq!(invalid_parameter(validate_adjustment(&Adjustment::Blur { sigma }, &registry)), "blur");
+        }
+    }
+
+    #[test]
+    fn rejects_shapes_missing_points() {
+        let registry = Registry::default();
+        let style = AnnotationStyle::default();
+        let mut arrow = Annotation::new(ShapeKind::Arrow, (1.0, 1.0), style, 1);
+        arrow.points.truncate(1);
+        let mut marker = Annotation::new(ShapeKind::Marker, (1.0, 1.0), style, 1);
+        marker.points.clear();
+        for annotation in [arrow, marker] {
+            let preset = Preset { name: String::from("Shapes"), adjustments: vec![Adjustment::Annotate { annotations: vec![annotation] }] };
+            assert!(matches!(apply_preset(image(), &preset, &[], &registry), Err(Error::InvalidParameter { .. })));
+        }
+
+        let wide = Annotation::new(ShapeKind::Line, (1.0, 1.0), AnnotationStyle { stroke_width: f32::INFINITY, ..style }, 1);
+        assert_eq!(invalid_parameter(validate_annotations(&[wide])), "stroke width");
+        assert!(validate_annotations(&[Annotation::new(ShapeKind::Rectangle, (1.0, 1.0), style, 1)]).is_ok());
+    }
+
+    #[test]
+    fn rejects_oversized_text() {
+        let registry = Registry::default();
+        let huge = TextBox { size: 1e9, ..TextBox::new((0.0, 0.0)) };
+        assert_eq!(invalid_parameter(validate_adjustment(&Adjustment::Text { text_boxes: vec![huge] }, &registry)), "text size");
+        let outlined = TextBox { outline_width: MAX_OUTLINE_WIDTH * 10.0, ..TextBox::new((0.0, 0.0)) };
+        assert_eq!(invalid_parameter(validate_text_boxes(&[outlined])), "text outline");
+        assert!(validate_text_boxes(&[TextBox::new((0.0, 0.0))]).is_ok());
+    }
+
+    #[test]
+    fn identity_params_return_the_input() {
+        let img = image();
+        let out = apply(img.clone(), Params::default()).unwrap();
+        assert_eq!(out.as_bytes(), img.as_bytes());
+    }
+}
@@ -0,0 +1,192 @@
+use image::{GrayImage, Luma, Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+use crate::healing;
+
+#[derive(PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum PaintTool {
+    Brush,
+    Eraser,
+    CloneStamp,
+    Heal,
+    ColorPicker,
+}
+
+impl PaintTool {
+    pub const ALL: [PaintTool; 5] = [PaintTool::Brush, PaintTool::Eraser, PaintTool::CloneStamp, PaintTool::Heal, PaintTool::ColorPicker];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PaintTool::Brush => "Brush",
+            PaintTool::Eraser => "Eraser",
+            PaintTool::CloneStamp => "Clone Stamp",
+            PaintTool::Heal => "Healing Brush",
+            PaintTool::ColorPicker => "Color Picker",
+        }
+    }
+}
+
+// Size is the diameter in pixels, hardness and opacity go from 0 to 100
+#[derive(PartialEq, Clone, Copy)]
+pub struct Brush {
+    pub tool: PaintTool,
+    pub size: f32,
+    pub hardness: f32,
+    pub opacity: f32,
+    pub color: [u8; 3],
+}
+
+impl Default for Brush {
+    fn default() -> Self {
+        Self {
+            tool: PaintTool::Brush,
+            size: 20.0,
+            hardness: 80.0,
+            opacity: 100.0,
+            color: [0, 0, 0],
+        }
+    }
+}
+
+impl Brush {
+    // Coverage of a dab at a given distance from its center, from 0 to 1
+    fn coverage(&self, distance: f32) -> f32 {
+        let radius = (self.size / 2.0).max(0.5);
+        let d = distance / radius;
+        let hard = (self.hardness / 100.0).clamp(0.0, 0.99);
+        if d <= hard {
+            1.0
+        } else if d >= 1.0 {
+            0.0
+        } else {
+            let t = (d - hard) / (1.0 - hard);
+            1.0 - t * t * (3.0 - 2.0 * t)
+        }
+    }
+}
+
+// Tint shown over the spot while painting with the healing brush
+const HEAL_PREVIEW_COLOR: [u8; 3] = [255, 0, 200];
+
+// A stroke remembers the highest coverage each pixel got, so overlapping dabs
+// don't build up more than the brush opacity
+pub struct Stroke {
+    brush: Brush,
+    source_offset: (f32, f32),
+    base: RgbaImage,
+    coverage: GrayImage,
+    result: RgbaImage,
+    mask: Option<GrayImage>,
+    last_point: Option<(f32, f32)>,
+}
+
+impl Stroke {
+    pub fn new(base: RgbaImage, brush: Brush, mask: Option<GrayImage>) -> Self {
+        let (w, h) = base.dimensions();
+        Self {
+            brush,
+            source_offset: (0.0, 0.0),
+            coverage: GrayImage::new(w, h),
+            result: base.clone(),
+            base,
+            mask,
+            last_point: None,
+        }
+    }
+
+    pub fn result(&self) -> &RgbaImage {
+        &self.result
+    }
+
+    // The healing brush only fixes the spot once the stroke is over
+    pub fn into_result(self) -> RgbaImage {
+        match self.brush.tool {
+            PaintTool::Heal => healing::heal(&self.base, &self.coverage, self.brush.opacity),
+            _ => self.result,
+        }
+    }
+
+    // Where the clone stamp copies from, relative to the painted pixel
+    pub fn set_source_offset(&mut self, source_offset: (f32, f32)) {
+        self.source_offset = source_offset;
+    }
+
+    // Stamp dabs from the previous point to this one
+    pub fn add_point(&mut self, point: (f32, f32)) {
+        let spacing = (self.brush.size * 0.15).max(0.5);
+        match self.last_point {
+            None => self.dab(point),
+            Some(last) => {
+                let (dx, dy) = (point.0 - last.0, point.1 - last.1);
+                let distance = (dx * dx + dy * dy).sqrt();
+                let steps = (distance / spacing).ceil().max(1.0) as u32;
+                for i in 1..=steps {
+                    let t = i as f32 / steps as f32;
+                    self.dab((last.0 + dx * t, last.1 + dy * t));
+                }
+            },
+        }
+        self.last_point = Some(point);
+    }
+
+    fn dab(&mut self, center: (f32, f32)) {
+        let (w, h) = self.base.dimensions();
+        let radius = self.brush.size / 2.0;
+        let x0 = (center.0 - radius).floor().max(0.0) as u32;
+        let y0 = (center.1 - radius).floor().max(0.0) as u32;
+        let x1 = ((center.0 + radius).ceil().max(0.0) as u32).min(w);
+        let y1 = ((center.1 + radius).ceil().max(0.0) as u32).min(h);
+
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let (dx, dy) = (x as f32 + 0.5 - center.0, y as f32 + 0.5 - center.1);
+                let mut coverage = self.brush.coverage((dx * dx + dy * dy).sqrt());
+                if let Some(mask) = &self.mask {
+                    coverage *= mask.get_pixel(x, y)[0] as f32 / 255.0;
+                }
+                let coverage = (coverage * 255.0).round() as u8;
+                if coverage <= self.coverage.get_pixel(x, y)[0] {
+                    continue;
+                }
+                self.coverage.put_pixel(x, y, Luma([coverage]));
+                let weight = coverage as f32 / 255.0 * self.brush.opacity / 100.0;
+                let px = self.paint_pixel(x, y, weight);
+                self.result.put_pixel(x, y, px);
+            }
+        }
+    }
+
+    fn paint_pixel(&self, x: u32, y: u32, weight: f32) -> Rgba<u8> {
+        let base = *self.base.get_pixel(x, y);
+        let base_alpha = base[3] as f32 / 255.0;
+        match self.brush.tool {
+            PaintTool::Eraser => Rgba([base[0], base[1], base[2], (base[3] as f32 * (1.0 - weight)).round() as u8]),
+            PaintTool::CloneStamp => {
+                // Copy from the image as it was when the stroke started
+                let source_x = (x as f32 + self.source_offset.0).round();
+                let source_y = (y as f32 + self.source_offset.1).round();
+                if source_x < 0.0 || source_y < 0.0 || source_x >= self.base.width() as f32 || source_y >= self.base.height() as f32 {
+                    return base;
+                }
+                let source = self.base.get_pixel(source_x as u32, source_y as u32);
+                let mix = |c: usize| (base[c] as f32 + (source[c] as f32 - base[c] as f32) * weight).round() as u8;
+                Rgba([mix(0), mix(1), mix(2), mix(3)])
+            },
+            PaintTool::Heal => {
+                let tint = weight * 0.5;
+                let mix = |c: usize| (base[c] as f32 + (HEAL_PREVIEW_COLOR[c] as f32 - base[c] as f32) * tint).round() as u8;
+                Rgba([mix(0), mix(1), mix(2), base[3].max((tint * 255.0) as u8)])
+            },
+            _ => {
+                // Paint color composited over the base pixel
+                let alpha = weight + base_alpha * (1.0 - weight);
+                if alpha <= 0.0 {
+                    return base;
+                }
+                let mix = |c: usize| {
+                    ((self.brush.color[c] as f32 * weight + base[c] as f32 * base_alpha * (1.0 - weight)) / alpha).round() as u8
+                };
+                Rgba([mix(0), mix(1), mix(2), (alpha * 255.0).round() as u8])
+            },
+        }
+    }
+}
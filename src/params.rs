@@ -0,0 +1,108 @@
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+use crate::artistic;
+use crate::denoise::{denoise, DenoiseMethod};
+
+// Values of every slider effect. The defaults leave the image untouched, so any mix
+// of them can be previewed together and applied as a single edit
+#[derive(PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Params {
+    pub blur: f32,
+    pub brighten: i32,
+    pub contrast: f32,
+    pub denoise_method: DenoiseMethod,
+    pub denoise_radius: u32,
+    pub denoise_strength: f32,
+    pub sepia: f32,
+    // One level and a block size of one mean no effect, so does a threshold of zero
+    pub posterize_levels: u32,
+    pub threshold: u8,
+    pub pixelate_block_size: u32,
+    pub vignette_strength: f32,
+    pub vignette_radius: f32,
+    pub vignette_feather: f32,
+    pub grain_amount: f32,
+    pub grain_size: u32,
+}
+
+impl Default for Params {
+    fn default() -> Self {
+        Self {
+            blur: 0.0,
+            brighten: 0,
+            contrast: 0.0,
+            denoise_method: DenoiseMethod::Median,
+            denoise_radius: 1,
+            denoise_strength: 0.0,
+            sepia: 0.0,
+            posterize_levels: 1,
+            threshold: 0,
+            pixelate_block_size: 1,
+            vignette_strength: 0.0,
+            vignette_radius: 0.5,
+            vignette_feather: 0.5,
+            grain_amount: 0.0,
+            grain_size: 1,
+        }
+    }
+}
+
+impl Params {
+    // Names of the effects that change the image, in the order they are applied
+    pub fn active_effects(&self) -> Vec<&'static str> {
+        let effects = [
+            ("Denoise", self.denoise_strength > 0.0),
+            ("Blur", self.blur > 0.0),
+            ("Brighten", self.brighten != 0),
+            ("Contrast", self.contrast != 0.0),
+            ("Sepia", self.sepia > 0.0),
+            ("Posterize", self.posterize_levels >= 2),
+            ("Threshold", self.threshold > 0),
+            ("Pixelate", self.pixelate_block_size >= 2),
+            ("Vignette", self.vignette_strength > 0.0),
+            ("Film Grain", self.grain_amount > 0.0),
+        ];
+        effects.iter().filter(|(_, active)| *active).map(|(name, _)| *name).collect()
+    }
+
+    pub fn is_identity(&self) -> bool {
+        self.active_effects().is_empty()
+    }
+}
+
+// Run the active effects one after the other, cleanup first and stylization last
+pub fn apply_params(img: &DynamicImage, params: &Params) -> DynamicImage {
+    let mut img = img.clone();
+    if params.denoise_strength > 0.0 {
+        img = denoise(&img, params.denoise_method, params.denoise_radius, params.denoise_strength);
+    }
+    if params.blur > 0.0 {
+        img = img.blur(params.blur);
+    }
+    if params.brighten != 0 {
+        img = img.brighten(params.brighten);
+    }
+    if params.contrast != 0.0 {
+        img = img.adjust_contrast(params.contrast);
+    }
+    if params.sepia > 0.0 {
+        img = artistic::sepia(&img, params.sepia);
+    }
+    if params.posterize_levels >= 2 {
+        img = artistic::posterize(&img, params.posterize_levels);
+    }
+    if params.threshold > 0 {
+        img = artistic::threshold(&img, params.threshold);
+    }
+    if params.pixelate_block_size >= 2 {
+        img = artistic::pixelate(&img, params.pixelate_block_size);
+    }
+    if params.vignette_strength > 0.0 {
+        img = artistic::vignette(&img, params.vignette_strength, params.vignette_radius, params.vignette_feather);
+    }
+    if params.grain_amount > 0.0 {
+        img = artistic::film_grain(&img, params.grain_amount, params.grain_size);
+    }
+    img
+}
@@ -0,0 +1,212 @@
+// Filters loaded from WebAssembly modules. A module exports:
+//   memory
+//   alloc(size: i32) -> i32                 a buffer of that size in its memory
+//   schema() -> i32, schema_len() -> i32    the UTF-8 JSON schema, see `Schema`
+//   process(pixels: i32, width: i32, height: i32, params: i32, count: i32)
+// `process` edits the RGBA pixels in place. The parameters are f64 in the order of the
+// schema, booleans as 0 or 1 and choices as the index of the option.
+// Modules get no imports, so they can't reach the files, the network or the editor,
+// and their memory and running time are limited
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use directories_next::ProjectDirs;
+use image::{DynamicImage, RgbaImage};
+use serde::Deserialize;
+use wasmi::{Config, Engine, Instance, Linker, Module, Store, StoreLimits, StoreLimitsBuilder};
+use crate::operation::{Operation, ParamSpec, ParamValue, Registry};
+use crate::ops::{Error, Result};
+
+pub const PLUGIN_EXTENSION: &str = "wasm";
+const MAX_MEMORY: usize = 1 << 30;
+const MAX_SCHEMA_LEN: usize = 64 * 1024;
+// Enough for about a thousand instructions per pixel, a module stuck in a loop is stopped
+const FUEL_PER_PIXEL: u64 = 1_000;
+const BASE_FUEL: u64 = 100_000_000;
+
+// Persistent folder the plugins are loaded from, None when the platform has no app data directory
+pub fn plugins_dir() -> Option<PathBuf> {
+    ProjectDirs::from("", "", "Image Editor").map(|dirs| dirs.data_dir().join("plugins"))
+}
+
+#[derive(Deserialize)]
+struct Schema {
+    name: String,
+    #[serde(default)]
+    params: Vec<SchemaParam>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum SchemaParam {
+    Float { name: String, min: f32, max: f32, default: f32 },
+    Int { name: String, min: i64, max: i64, default: i64 },
+    Bool { name: String, default: bool },
+    Choice { name: String, options: Vec<String>, default: usize },
+}
+
+impl SchemaParam {
+    fn spec(self) -> ParamSpec {
+        match self {
+            SchemaParam::Float { name, min, max, default } => ParamSpec::float(&name, min, max, default),
+            SchemaParam::Int { name, min, max, default } => ParamSpec::int(&name, min, max, default),
+            SchemaParam::Bool { name, default } => ParamSpec::bool(&name, default),
+            SchemaParam::Choice { name, options, default } => {
+                let options: Vec<&str> = options.iter().map(String::as_str).collect();
+                ParamSpec::choice(&name, &options, default)
+            },
+        }
+    }
+}
+
+pub struct WasmFilter {
+    name: String,
+    params: Vec<ParamSpec>,
+    engine: Engine,
+    module: Module,
+}
+
+fn failed(name: &str, err: impl Display) -> Error {
+    Error::Failed { operation: name.to_string(), reason: err.to_string() }
+}
+
+impl WasmFilter {
+    pub fn load(path: &Path) -> Result<Self> {
+        let name = path.display().to_string();
+        let bytes = fs::read(path).map_err(|err| failed(&name, err))?;
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, &bytes[..]).map_err(|err| failed(&name, err))?;
+        let mut filter = Self { name, params: Vec::new(), engine, module };
+
+        // The schema is read once, a fresh instance is made for every run
+        let (mut store, instance) = filter.instantiate(BASE_FUEL)?;
+        let schema = filter.call::<(), i32>(&mut store, &instance, "schema", ())?;
+        let schema_len = filter.call::<(), i32>(&mut store, &instance, "schema_len", ())?;
+        // Both come from the module, so they are checked before anything is allocated for them
+        let memory = filter.memory(&store, &instance)?;
+        let (start, len) = (schema as u32 as usize, schema_len as u32 as usize);
+        if len > MAX_SCHEMA_LEN || start.checked_add(len).is_none_or(|end| end > memory.data(&store).len()) {
+            return Err(failed(&filter.name, format!("schema of {} bytes at {} is not inside the module memory", schema_len, schema)));
+        }
+        let mut schema_bytes = vec![0; len];
+        memory.read(&store, start, &mut schema_bytes).map_err(|err| failed(&filter.name, err))?;
+        let schema: Schema = serde_json::from_slice(&schema_bytes).map_err(|err| failed(&filter.name, err))?;
+        filter.name = schema.name;
+        filter.params = schema.params.into_iter().map(SchemaParam::spec).collect();
+        for spec in &filter.params {
+            spec.check(&spec.default_value())?;
+        }
+        Ok(filter)
+    }
+
+    fn instantiate(&self, fuel: u64) -> Result<(Store<StoreLimits>, Instance)> {
+        let mut store = Store::new(&self.engine, StoreLimitsBuilder::new().memory_size(MAX_MEMORY).build());
+        store.limiter(|limits| limits);
+        store.add_fuel(fuel).map_err(|err| failed(&self.name, err))?;
+        let instance = Linker::new(&self.engine)
+            .instantiate(&mut store, &self.module)
+            .and_then(|instance| instance.start(&mut store))
+            .map_err(|err| failed(&self.name, err))?;
+        Ok((store, instance))
+    }
+
+    fn memory(&self, store: &Store<StoreLimits>, instance: &Instance) -> Result<wasmi::Memory> {
+        instance.get_memory(store, "memory").ok_or_else(|| failed(&self.name, "no exported memory"))
+    }
+
+    fn call<Params: wasmi::WasmParams, Results: wasmi::WasmResults>(
+        &self,
+        store: &mut Store<StoreLimits>,
+        instance: &Instance,
+        function: &str,
+        params: Params,
+    ) -> Result<Results> {
+        instance
+            .get_typed_func::<Params, Results>(&*store, function)
+            .map_err(|err| failed(&self.name, format!("{}: {}", function, err)))?
+            .call(store, params)
+            .map_err(|err| failed(&self.name, format!("{}: {}", function, err)))
+    }
+
+    // Copy the bytes into a buffer the module allocated, and return where they are
+    fn write(&self, store: &mut Store<StoreLimits>, instance: &Instance, bytes: &[u8]) -> Result<i32> {
+        let ptr = self.call::<i32, i32>(store, instance, "alloc", bytes.len() as i32)?;
+        self.memory(store, instance)?
+            .write(&mut *store, ptr as u32 as usize, bytes)
+            .map_err(|err| failed(&self.name, err))?;
+        Ok(ptr)
+    }
+
+    fn run(&self, img: &DynamicImage, values: &[ParamValue]) -> Result<DynamicImage> {
+        let rgba = img.to_rgba8();
+        let (width, height) = rgba.dimensions();
+        let fuel = BASE_FUEL.saturating_add(FUEL_PER_PIXEL.saturating_mul(width as u64 * height as u64));
+        let (mut store, instance) = self.instantiate(fuel)?;
+        let params: Vec<u8> = values
+            .iter()
+            .map(|value| match value {
+                ParamValue::Float(value) => *value as f64,
+                ParamValue::Int(value) => *value as f64,
+                ParamValue::Bool(value) => *value as u8 as f64,
+                ParamValue::Choice(value) => *value as f64,
+            })
+            .flat_map(f64::to_le_bytes)
+            .collect();
+        let pixels = self.write(&mut store, &instance, rgba.as_raw())?;
+        let params_ptr = self.write(&mut store, &instance, &params)?;
+        self.call::<(i32, i32, i32, i32, i32), ()>(
+            &mut store,
+            &instance,
+            "process",
+            (pixels, width as i32, height as i32, params_ptr, values.len() as i32),
+        )?;
+        let mut out = vec![0; rgba.len()];
+        self.memory(&store, &instance)?
+            .read(&store, pixels as u32 as usize, &mut out)
+            .map_err(|err| failed(&self.name, err))?;
+        Ok(DynamicImage::ImageRgba8(RgbaImage::from_raw(width, height, out).unwrap()))
+    }
+}
+
+impl Operation for WasmFilter {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn params(&self) -> Vec<ParamSpec> {
+        self.params.clone()
+    }
+
+    fn apply(&self, img: &DynamicImage, values: &[ParamValue]) -> Result<DynamicImage> {
+        self.run(img, values)
+    }
+}
+
+// Every module of the folder is added to the registry. The ones that fail to load
+// are reported by file, and don't stop the others
+pub fn register_plugins(registry: &mut Registry, dir: &Path) -> Vec<String> {
+    let mut paths: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case(PLUGIN_EXTENSION)))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    paths.sort();
+    let mut errors = Vec::new();
+    for path in paths {
+        match WasmFilter::load(&path) {
+            // A plugin doesn't get to replace a builtin or an earlier plugin, edits using that name would change meaning
+            Ok(filter) if registry.find(filter.name()).is_some() => {
+                errors.push(failed(&path.display().to_string(), format!("an operation named {} already exists", filter.name())).to_string());
+            },
+            Ok(filter) => registry.register(Arc::new(filter)),
+            Err(err) => errors.push(err.to_string()),
+        }
+    }
+    errors
+}
@@ -0,0 +1,167 @@
+use eframe::egui::{Context, TextureFilter, Visuals};
+use serde::{Deserialize, Serialize};
+use crate::keymap::Keymap;
+
+pub const UI_SCALE_RANGE: std::ops::RangeInclusive<f32> = 0.5..=3.0;
+pub const HISTORY_SIZE_RANGE: std::ops::RangeInclusive<usize> = 1..=100;
+pub const FONT_SIZE_RANGE: std::ops::RangeInclusive<f32> = 8.0..=40.0;
+
+#[derive(PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum Theme {
+    Dark,
+    Light,
+}
+
+impl Theme {
+    pub const ALL: [Theme; 2] = [Theme::Dark, Theme::Light];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Theme::Dark => "Dark",
+            Theme::Light => "Light",
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum ExportFormat {
+    Png,
+    Jpeg,
+    WebP,
+}
+
+impl ExportFormat {
+    pub const ALL: [ExportFormat; 3] = [ExportFormat::Png, ExportFormat::Jpeg, ExportFormat::WebP];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExportFormat::Png => "PNG",
+            ExportFormat::Jpeg => "JPEG",
+            ExportFormat::WebP => "WebP",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Png => "png",
+            ExportFormat::Jpeg => "jpg",
+            ExportFormat::WebP => "webp",
+        }
+    }
+
+    // Only the lossy encoders look at the quality
+    pub fn has_quality(&self) -> bool {
+        *self != ExportFormat::Png
+    }
+}
+
+// How the image is sampled when the view doesn't show it at its real size
+#[derive(PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum PreviewQuality {
+    Smooth,
+    Pixelated,
+}
+
+impl PreviewQuality {
+    pub const ALL: [PreviewQuality; 2] = [PreviewQuality::Smooth, PreviewQuality::Pixelated];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PreviewQuality::Smooth => "Smooth",
+            PreviewQuality::Pixelated => "Pixelated",
+        }
+    }
+
+    pub fn texture_filter(&self) -> TextureFilter {
+        match self {
+            PreviewQuality::Smooth => TextureFilter::Linear,
+            PreviewQuality::Pixelated => TextureFilter::Nearest,
+        }
+    }
+}
+
+// Upper ends of the effect sliders, the lower ends mirror them where the effect goes both ways
+#[derive(PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SliderRanges {
+    pub blur: f32,
+    pub brighten: i32,
+    pub contrast: f32,
+    pub brush_size: f32,
+}
+
+impl Default for SliderRanges {
+    fn default() -> Self {
+        Self {
+            blur: 20.0,
+            brighten: 100,
+            contrast: 20.0,
+            brush_size: 200.0,
+        }
+    }
+}
+
+// Text sizes and spacing of the panels, in points before the UI scale
+#[derive(PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UiSizes {
+    pub tool_font_size: f32,
+    pub font_size: f32,
+    pub monospace_font_size: f32,
+    pub side_panel_width: f32,
+    pub padding: f32,
+}
+
+impl Default for UiSizes {
+    fn default() -> Self {
+        Self {
+            tool_font_size: 18.0,
+            font_size: 16.0,
+            monospace_font_size: 14.0,
+            side_panel_width: 150.0,
+            padding: 5.0,
+        }
+    }
+}
+
+// Missing fields fall back to their defaults, so older saved preferences still load
+#[derive(PartialEq, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Preferences {
+    pub theme: Theme,
+    pub ui_scale: f32,
+    pub export_format: ExportFormat,
+    pub export_quality: u8,
+    pub history_size: usize,
+    pub preview_quality: PreviewQuality,
+    pub slider_ranges: SliderRanges,
+    pub ui_sizes: UiSizes,
+    pub keymap: Keymap,
+}
+
+impl Default for Preferences {
+    fn default() -> Self {
+        Self {
+            theme: Theme::Dark,
+            ui_scale: 1.0,
+            export_format: ExportFormat::Png,
+            export_quality: 90,
+            history_size: 5,
+            preview_quality: PreviewQuality::Smooth,
+            slider_ranges: SliderRanges::default(),
+            ui_sizes: UiSizes::default(),
+            keymap: Keymap::default(),
+        }
+    }
+}
+
+impl Preferences {
+    // The scale multiplies the one picked by the system for the monitor
+    pub fn apply(&self, ctx: &Context, native_pixels_per_point: f32) {
+        ctx.set_visuals(match self.theme {
+            Theme::Dark => Visuals::dark(),
+            Theme::Light => Visuals::light(),
+        });
+        ctx.set_pixels_per_point(native_pixels_per_point * self.ui_scale);
+    }
+}
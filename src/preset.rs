@@ -0,0 +1,189 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use directories_next::ProjectDirs;
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+use crate::adjustment::{self, Adjustment};
+use crate::operation::Registry;
+use crate::ops;
+use crate::text::{self, FontEntry};
+use crate::transparency;
+
+pub const PRESET_EXTENSIONS: [&str; 2] = ["json", "toml"];
+
+// A named sequence of edits that can be replayed on any image
+#[derive(PartialEq, Clone, Serialize, Deserialize)]
+pub struct Preset {
+    pub name: String,
+    pub adjustments: Vec<Adjustment>,
+}
+
+impl Preset {
+    // Paint strokes and flattened layers depend on the image they were made on, so they are left out
+    pub fn record(name: &str, adjustments: &[Adjustment]) -> Self {
+        Self {
+            name: name.to_string(),
+            adjustments: adjustments
+                .iter()
+                .filter(|adjustment| !matches!(adjustment, Adjustment::Paint { .. } | Adjustment::FlattenLayers))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    pub fn apply(&self, img: &DynamicImage, fonts: &[FontEntry], operations: &Registry) -> DynamicImage {
+        adjustment::apply_all(img, &self.adjustments, fonts, operations)
+    }
+
+    // Every edit checked as `ops` would before replaying it. Filters of plugins that
+    // aren't installed are let through, applying the preset tells about them
+    pub fn validate(&self, operations: &Registry) -> ops::Result<()> {
+        self.adjustments.iter().try_for_each(|adjustment| match ops::validate_adjustment(adjustment, operations) {
+            Err(ops::Error::UnknownOperation(_)) => Ok(()),
+            result => result,
+        })
+    }
+
+    // Fonts are slow to list, so they are only loaded for presets that draw text
+    pub fn uses_fonts(&self) -> bool {
+        fn uses_fonts(adjustments: &[Adjustment]) -> bool {
+            adjustments.iter().any(|adjustment| match adjustment {
+                Adjustment::Text { .. } => true,
+                Adjustment::Preset { adjustments, .. } => uses_fonts(adjustments),
+                _ => false,
+            })
+        }
+        uses_fonts(&self.adjustments)
+    }
+
+    // Replayed from the editor, the whole preset is a single edit
+    pub fn to_adjustment(&self) -> Adjustment {
+        Adjustment::Preset { name: self.name.clone(), adjustments: self.adjustments.clone() }
+    }
+}
+
+// Persistent folder the presets sidebar lists, None when the platform has no app data directory
+pub fn presets_dir() -> Option<PathBuf> {
+    ProjectDirs::from("", "", "Image Editor").map(|dirs| dirs.data_dir().join("presets"))
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn is_toml(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("toml"))
+}
+
+// TOML for .toml files, JSON for anything else
+pub fn save(path: &Path, preset: &Preset) -> io::Result<()> {
+    let contents = if is_toml(path) {
+        // Going through a value puts the plain fields before the tables, as TOML requires
+        let value = toml::Value::try_from(preset).map_err(|err| invalid(err.to_string()))?;
+        toml::to_string_pretty(&value).map_err(|err| invalid(err.to_string()))?
+    } else {
+        serde_json::to_string_pretty(preset)?
+    };
+    fs::write(path, contents)
+}
+
+pub fn load(path: &Path) -> io::Result<Preset> {
+    let contents = fs::read_to_string(path)?;
+    if is_toml(path) {
+        toml::from_str(&contents).map_err(|err| invalid(format!("{} is not a valid preset: {}", path.display(), err)))
+    } else {
+        serde_json::from_str(&contents).map_err(|err| invalid(format!("{} is not a valid preset: {}", path.display(), err)))
+    }
+}
+
+// Every valid preset of the folder, sorted by name, with the errors of the files that were skipped
+pub fn load_all(dir: &Path, operations: &Registry) -> (Vec<(PathBuf, Preset)>, Vec<String>) {
+    let paths: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| PRESET_EXTENSIONS.iter().any(|e| ext.eq_ignore_ascii_case(e))))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    let mut presets = Vec::new();
+    let mut errors = Vec::new();
+    for path in paths {
+        match load(&path).and_then(|preset| preset.validate(operations).map(|_| preset).map_err(|err| invalid(err.to_string()))) {
+            Ok(preset) => presets.push((path, preset)),
+            Err(err) => errors.push(format!("Skipped {}: {}", path.display(), err)),
+        }
+    }
+    presets.sort_by_key(|(_, preset)| preset.name.to_lowercase());
+    (presets, errors)
+}
+
+// File name in the presets folder, without the characters that aren't allowed in paths
+pub fn file_name(name: &str, extension: &str) -> String {
+    let stem: String = name
+        .chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' || c == ' ' { c } else { '_' })
+        .collect();
+    format!("{}.{}", stem.trim(), extension)
+}
+
+// Command line replay: the preset is applied to the input and the result written to the output
+pub fn run(preset_path: &Path, input: &Path, output: &Path, quality: u8, operations: &Registry) -> io::Result<()> {
+    let preset = load(preset_path)?;
+    let img = image::open(input).map_err(|err| invalid(format!("Could not open {}: {}", input.display(), err)))?;
+    let fonts = if preset.uses_fonts() { text::available_fonts() } else { Vec::new() };
+    let img = ops::apply_preset(img, &preset, &fonts, operations).map_err(|err| invalid(err.to_string()))?;
+    transparency::save_with_alpha(&img, output, quality)
+        .map_err(|err| io::Error::other(format!("Could not write {}: {}", output.display(), err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+    use crate::convolution::{EdgeMode, Kernel};
+    use crate::operation::ParamValue;
+    use super::*;
+
+    fn preset() -> Preset {
+        Preset {
+            name: String::from("Round trip"),
+            adjustments: vec![
+                Adjustment::Blur { sigma: 1.5 },
+                Adjustment::Convolution { kernel: Kernel::builtin().remove(0), edge_mode: EdgeMode::Mirror },
+                Adjustment::Operation { name: String::from("Rotate"), values: vec![ParamValue::Choice(1)] },
+                Adjustment::Posterize { levels: 4 },
+            ],
+        }
+    }
+
+    #[test]
+    fn keeps_every_edit_through_json_and_toml() {
+        let dir = tempdir().unwrap();
+        for extension in PRESET_EXTENSIONS {
+            let path = dir.path().join(file_name("Round trip", extension));
+            save(&path, &preset()).unwrap();
+            assert!(load(&path).unwrap() == preset(), "{} changed the preset", extension);
+        }
+    }
+
+    #[test]
+    fn skips_presets_with_invalid_values() {
+        let dir = tempdir().unwrap();
+        save(&dir.path().join("good.toml"), &preset()).unwrap();
+        let bad = Preset { name: String::from("Bad"), adjustments: vec![Adjustment::Blur { sigma: -1.0 }] };
+        save(&dir.path().join("bad.json"), &bad).unwrap();
+        // A filter of a plugin that isn't installed is kept
+        let plugin = Preset {
+            name: String::from("Plugin"),
+            adjustments: vec![Adjustment::Operation { name: String::from("Missing"), values: Vec::new() }],
+        };
+        save(&dir.path().join("plugin.json"), &plugin).unwrap();
+
+        let (presets, errors) = load_all(dir.path(), &Registry::with_builtins());
+        let names: Vec<&str> = presets.iter().map(|(_, preset)| preset.name.as_str()).collect();
+        assert_eq!(names, ["Plugin", "Round trip"]);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("bad.json"));
+    }
+}
@@ -0,0 +1,337 @@
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+use image::{DynamicImage, GrayImage, ImageOutputFormat};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use zip::{ZipArchive, ZipWriter};
+use zip::write::FileOptions;
+use crate::adjustment::Adjustment;
+use crate::annotation::Annotation;
+use crate::layers::{Layer, LayerSettings};
+use crate::operation::Registry;
+use crate::ops;
+use crate::selection::Selection;
+use crate::text::TextBox;
+
+// Bump when the manifest changes in a way older versions can't read
+pub const PROJECT_VERSION: u32 = 1;
+pub const PROJECT_EXTENSION: &str = "imgproj";
+const MANIFEST_NAME: &str = "manifest.json";
+
+// Everything needed to keep editing a session later. Images are files on disk,
+// the original keeps its own format and every other image is stored as PNG
+pub struct Project {
+    pub image_name: String,
+    pub original: PathBuf,
+    pub current: PathBuf,
+    pub history: Vec<PathBuf>,
+    pub redo: Vec<PathBuf>,
+    pub adjustments: Vec<Adjustment>,
+    pub undone_adjustments: Vec<Adjustment>,
+    pub layers: Vec<Layer>,
+    pub selection: Option<Selection>,
+    pub annotations: Vec<Annotation>,
+    pub text_boxes: Vec<TextBox>,
+}
+
+impl Project {
+    // A redaction only hides pixels in the versions after it, the original and the
+    // versions before it still show what was hidden
+    pub fn has_redactions(&self) -> bool {
+        has_redactions(&self.adjustments)
+    }
+
+    // Leave out every version that may show what was redacted, the current image
+    // stands in for the original and the history starts over from it
+    pub fn drop_unredacted(&mut self) {
+        self.image_name = Path::new(&self.image_name).with_extension("png").to_string_lossy().to_string();
+        self.original = self.current.clone();
+        self.history.clear();
+        self.redo.clear();
+        self.undone_adjustments.clear();
+    }
+}
+
+pub fn has_redactions(adjustments: &[Adjustment]) -> bool {
+    adjustments.iter().any(|adjustment| match adjustment {
+        Adjustment::Redact { .. } => true,
+        Adjustment::Preset { adjustments, .. } => has_redactions(adjustments),
+        _ => false,
+    })
+}
+
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    version: u32,
+    image_name: String,
+    original: String,
+    current: String,
+    history: Vec<String>,
+    redo: Vec<String>,
+    adjustments: Vec<Adjustment>,
+    undone_adjustments: Vec<Adjustment>,
+    layers: Vec<LayerEntry>,
+    selection: Option<String>,
+    annotations: Vec<Annotation>,
+    text_boxes: Vec<TextBox>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LayerEntry {
+    name: String,
+    file: String,
+    settings: LayerSettings,
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn png_bytes(img: &DynamicImage) -> io::Result<Vec<u8>> {
+    let mut bytes = Cursor::new(Vec::new());
+    img.write_to(&mut bytes, ImageOutputFormat::Png).map_err(|err| invalid(err.to_string()))?;
+    Ok(bytes.into_inner())
+}
+
+fn open_png(path: &Path) -> io::Result<Vec<u8>> {
+    png_bytes(&image::open(path).map_err(|err| invalid(err.to_string()))?)
+}
+
+pub fn save(path: &Path, project: &Project) -> io::Result<()> {
+    let mut zip = ZipWriter::new(File::create(path)?);
+    let options = FileOptions::default();
+    let mut add = |name: &str, bytes: &[u8]| -> io::Result<()> {
+        zip.start_file(name, options)?;
+        zip.write_all(bytes)
+    };
+
+    let original = format!("original/{}", project.image_name);
+    add(&original, &fs::read(&project.original)?)?;
+    add("current.png", &open_png(&project.current)?)?;
+    let mut history = Vec::new();
+    for (i, version) in project.history.iter().enumerate() {
+        history.push(format!("history/{}.png", i));
+        add(&history[i], &open_png(version)?)?;
+    }
+    let mut redo = Vec::new();
+    for (i, version) in project.redo.iter().enumerate() {
+        redo.push(format!("redo/{}.png", i));
+        add(&redo[i], &open_png(version)?)?;
+    }
+    let mut layers = Vec::new();
+    for (i, layer) in project.layers.iter().enumerate() {
+        let file = format!("layers/{}.png", i);
+        add(&file, &png_bytes(&DynamicImage::ImageRgba8(layer.image().clone()))?)?;
+        layers.push(LayerEntry { name: layer.name.clone(), file, settings: layer.settings });
+    }
+    let selection = match &project.selection {
+        Some(selection) => {
+            add("selection.png", &png_bytes(&DynamicImage::ImageLuma8(selection.mask().clone()))?)?;
+            Some(String::from("selection.png"))
+        },
+        None => None,
+    };
+
+    let manifest = Manifest {
+        version: PROJECT_VERSION,
+        image_name: project.image_name.clone(),
+        original,
+        current: String::from("current.png"),
+        history,
+        redo,
+        adjustments: project.adjustments.clone(),
+        undone_adjustments: project.undone_adjustments.clone(),
+        layers,
+        selection,
+        annotations: project.annotations.clone(),
+        text_boxes: project.text_boxes.clone(),
+    };
+    add(MANIFEST_NAME, &serde_json::to_vec_pretty(&manifest)?)?;
+    zip.finish()?;
+    Ok(())
+}
+
+// Images are extracted into dir, which has to outlive the project. The registry is the one
+// the adjustments are checked against
+pub fn load(path: &Path, dir: &Path, operations: &Registry) -> io::Result<Project> {
+    let mut zip = ZipArchive::new(File::open(path)?)?;
+    let mut read = |name: &str| -> io::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        zip.by_name(name)?.read_to_end(&mut bytes)?;
+        Ok(bytes)
+    };
+
+    // Check the version before reading the rest, newer manifests may not parse at all
+    let manifest_bytes = read(MANIFEST_NAME)?;
+    let value: Value = serde_json::from_slice(&manifest_bytes)?;
+    match value.get("version").and_then(Value::as_u64) {
+        Some(version) if version == PROJECT_VERSION as u64 => {},
+        Some(version) if version > PROJECT_VERSION as u64 => {
+            return Err(invalid(format!("project version {} was made by a newer version of the editor", version)));
+        },
+        Some(version) => return Err(invalid(format!("project version {} is not supported", version))),
+        None => return Err(invalid(String::from("not a project file"))),
+    }
+    let mut manifest: Manifest = serde_json::from_value(value)?;
+    // The name becomes a file name, so anything that looks like a path is dropped
+    manifest.image_name = Path::new(&manifest.image_name)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .ok_or_else(|| invalid(String::from("invalid image name")))?;
+
+    let mut extract = |name: &str, file_name: String| -> io::Result<PathBuf> {
+        let file_path = dir.join(file_name);
+        fs::write(&file_path, read(name)?)?;
+        Ok(file_path)
+    };
+    // Edits that would be replayed with values the engine can't handle make the whole project invalid.
+    // Filters of plugins that aren't installed are kept, they replay as nothing
+    for adjustment in manifest.adjustments.iter().chain(&manifest.undone_adjustments) {
+        match ops::validate_adjustment(adjustment, operations) {
+            Ok(()) | Err(ops::Error::UnknownOperation(_)) => {},
+            Err(err) => return Err(invalid(format!("{} in {}", err, adjustment.name()))),
+        }
+    }
+    ops::validate_annotations(&manifest.annotations).map_err(|err| invalid(err.to_string()))?;
+    ops::validate_text_boxes(&manifest.text_boxes).map_err(|err| invalid(err.to_string()))?;
+    for entry in &manifest.layers {
+        ops::validate_layer_settings(&entry.settings).map_err(|err| invalid(format!("{} in layer {}", err, entry.name)))?;
+    }
+
+    let original = extract(&manifest.original, manifest.image_name.clone())?;
+    let current = extract(&manifest.current, format!("project_current_{}.png", manifest.image_name))?;
+    // The selection is read pixel by pixel over the current image, so its size has to match
+    let size = image::image_dimensions(&current).map_err(|err| invalid(err.to_string()))?;
+    let history = manifest.history
+        .iter()
+        .enumerate()
+        .map(|(i, name)| extract(name, format!("project_history_{}_{}.png", i, manifest.image_name)))
+        .collect::<io::Result<Vec<PathBuf>>>()?;
+    let redo = manifest.redo
+        .iter()
+        .enumerate()
+        .map(|(i, name)| extract(name, format!("project_redo_{}_{}.png", i, manifest.image_name)))
+        .collect::<io::Result<Vec<PathBuf>>>()?;
+
+    let mut layers = Vec::new();
+    for entry in manifest.layers {
+        let image = image::load_from_memory(&read(&entry.file)?).map_err(|err| invalid(err.to_string()))?;
+        let mut layer = Layer::new(entry.name, image.to_rgba8());
+        layer.settings = entry.settings;
+        layers.push(layer);
+    }
+    let selection = match &manifest.selection {
+        Some(name) => {
+            let mask: GrayImage = image::load_from_memory(&read(name)?).map_err(|err| invalid(err.to_string()))?.to_luma8();
+            if mask.dimensions() != size {
+                return Err(invalid(String::from("the selection is not the size of the image")));
+            }
+            Some(Selection::from_mask(mask))
+        },
+        None => None,
+    };
+
+    Ok(Project {
+        image_name: manifest.image_name,
+        original,
+        current,
+        history,
+        redo,
+        adjustments: manifest.adjustments,
+        undone_adjustments: manifest.undone_adjustments,
+        layers,
+        selection,
+        annotations: manifest.annotations,
+        text_boxes: manifest.text_boxes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+    use tempfile::tempdir;
+    use crate::annotation::{AnnotationStyle, ShapeKind};
+    use super::*;
+
+    // A project of a 8x6 image with nothing edited yet
+    fn project(dir: &Path) -> Project {
+        let original = dir.join("original.png");
+        DynamicImage::ImageRgba8(RgbaImage::from_pixel(8, 6, Rgba([10, 20, 30, 255]))).save(&original).unwrap();
+        Project {
+            image_name: String::from("original.png"),
+            original: original.clone(),
+            current: original,
+            history: Vec::new(),
+            redo: Vec::new(),
+            adjustments: Vec::new(),
+            undone_adjustments: Vec::new(),
+            layers: Vec::new(),
+            selection: None,
+            annotations: Vec::new(),
+            text_boxes: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn reopens_the_history_and_the_edits() {
+        let dir = tempdir().unwrap();
+        let mut saved = project(dir.path());
+        let current = dir.path().join("current.png");
+        DynamicImage::ImageRgba8(RgbaImage::from_pixel(8, 6, Rgba([40, 50, 60, 255]))).save(&current).unwrap();
+        saved.history = vec![saved.original.clone()];
+        saved.current = current;
+        saved.adjustments = vec![Adjustment::Blur { sigma: 2.0 }];
+        saved.undone_adjustments = vec![Adjustment::Posterize { levels: 8 }];
+        saved.redo = vec![saved.original.clone()];
+        saved.annotations = vec![Annotation::new(ShapeKind::Marker, (3.0, 3.0), AnnotationStyle::default(), 1)];
+        saved.text_boxes = vec![TextBox::new((1.0, 2.0))];
+        saved.selection = Some(Selection::all(8, 6));
+        let path = dir.path().join("session.imgproj");
+        save(&path, &saved).unwrap();
+
+        let out = tempdir().unwrap();
+        let project = load(&path, out.path(), &Registry::default()).unwrap();
+        assert_eq!(project.history.len(), 1);
+        assert_eq!(project.redo.len(), 1);
+        assert!(project.adjustments == saved.adjustments);
+        assert!(project.undone_adjustments == saved.undone_adjustments);
+        assert!(project.annotations == saved.annotations);
+        assert!(project.text_boxes == saved.text_boxes);
+        assert_eq!(project.selection.unwrap().mask(), Selection::all(8, 6).mask());
+        let current = image::open(&project.current).unwrap().to_rgba8();
+        assert_eq!(current.get_pixel(0, 0), &Rgba([40, 50, 60, 255]));
+    }
+
+    #[test]
+    fn reopens_a_layer_smaller_than_the_image() {
+        let dir = tempdir().unwrap();
+        let mut saved = project(dir.path());
+        let mut layer = Layer::new(String::from("Half"), RgbaImage::from_pixel(4, 3, Rgba([200, 0, 0, 255])));
+        layer.settings.position = (2.0, 1.5);
+        saved.layers.push(layer);
+        let path = dir.path().join("half.imgproj");
+        save(&path, &saved).unwrap();
+
+        let out = tempdir().unwrap();
+        let project = load(&path, out.path(), &Registry::default()).unwrap();
+        assert_eq!(project.layers.len(), 1);
+        assert_eq!(project.layers[0].image().dimensions(), (4, 3));
+        assert_eq!(project.layers[0].settings.position, (2.0, 1.5));
+    }
+
+    #[test]
+    fn rejects_a_layer_scale_out_of_range() {
+        let dir = tempdir().unwrap();
+        let mut saved = project(dir.path());
+        let mut layer = Layer::new(String::from("Huge"), RgbaImage::from_pixel(4, 3, Rgba([200, 0, 0, 255])));
+        layer.settings.scale = 1e9;
+        saved.layers.push(layer);
+        let path = dir.path().join("huge.imgproj");
+        save(&path, &saved).unwrap();
+
+        let out = tempdir().unwrap();
+        let err = load(&path, out.path(), &Registry::default()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
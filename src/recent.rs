@@ -0,0 +1,62 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use eframe::egui::ColorImage;
+use egui_extras::RetainedImage;
+use serde::{Deserialize, Serialize};
+
+pub const MAX_RECENT_FILES: usize = 10;
+pub const THUMBNAIL_SIZE: u32 = 48;
+
+// Most recently opened images first
+#[derive(Default, Serialize, Deserialize)]
+pub struct RecentFiles {
+    paths: Vec<PathBuf>,
+    pub reopen_last: bool,
+    // Loaded the first time the menu shows them, None if the image can't be read
+    #[serde(skip)]
+    thumbnails: HashMap<PathBuf, Option<RetainedImage>>,
+}
+
+impl RecentFiles {
+    pub fn paths(&self) -> &Vec<PathBuf> {
+        &self.paths
+    }
+
+    pub fn add(&mut self, path: &Path) {
+        self.paths.retain(|p| p != path);
+        self.paths.insert(0, path.to_path_buf());
+        self.paths.truncate(MAX_RECENT_FILES);
+        self.thumbnails.remove(path);
+    }
+
+    // Forget the files that were moved or deleted since they were opened
+    pub fn prune(&mut self) {
+        self.paths.retain(|path| path.is_file());
+        let paths = &self.paths;
+        self.thumbnails.retain(|path, _| paths.contains(path));
+    }
+
+    pub fn clear(&mut self) {
+        self.paths.clear();
+        self.thumbnails.clear();
+    }
+
+    pub fn last(&self) -> Option<&PathBuf> {
+        self.paths.first()
+    }
+
+    pub fn thumbnail(&mut self, path: &Path) -> Option<&RetainedImage> {
+        self.thumbnails
+            .entry(path.to_path_buf())
+            .or_insert_with(|| load_thumbnail(path))
+            .as_ref()
+    }
+}
+
+fn load_thumbnail(path: &Path) -> Option<RetainedImage> {
+    let img = image::open(path).ok()?.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE).to_rgba8();
+    Some(RetainedImage::from_color_image(
+        path.display().to_string(),
+        ColorImage::from_rgba_unmultiplied([img.width() as usize, img.height() as usize], img.as_raw()),
+    ))
+}
@@ -0,0 +1,261 @@
+// Scripts in Rhai, for edits that depend on the image. The image being edited is the
+// `image` variable, and what is left in it at the end is the result:
+//   if image.width > 2000 { image.resize(image.width / 2, image.height / 2); }
+//   image.blur(1.5);
+//   image.filter("Rotate", ["180°"]);
+// Images can also be opened and saved when the user runs the script, so it can go through many files
+use std::cell::RefCell;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use image::DynamicImage;
+use image::imageops::FilterType;
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Scope};
+use crate::adjustment::Adjustment;
+use crate::operation::{ParamKind, ParamValue, Registry};
+use crate::ops::{self, Error, Result};
+use crate::preset;
+use crate::text::FontEntry;
+use crate::transparency;
+
+pub const SCRIPT_EXTENSION: &str = "rhai";
+pub const IMAGE_VARIABLE: &str = "image";
+const SAVE_QUALITY: u8 = 90;
+// Keeps a script stuck in a loop from freezing the editor
+const MAX_OPERATIONS: u64 = 50_000_000;
+// Largest image a script may make, 1 GiB of RGBA pixels. A cap of our own, so a
+// typo in `resize` gets an error instead of asking for gigabytes
+const MAX_PIXELS: i64 = 1 << 28;
+
+// Whether `open`, `save` and `apply_preset` are there. Only scripts the user runs get them,
+// the ones replayed from presets, projects or previews can't touch any file
+#[derive(Clone, Copy, PartialEq)]
+pub enum FileAccess {
+    Allowed,
+    Denied,
+}
+
+#[derive(Clone)]
+pub struct ScriptImage(DynamicImage);
+
+type ScriptResult<T> = std::result::Result<T, Box<EvalAltResult>>;
+
+fn script_error(err: impl ToString) -> Box<EvalAltResult> {
+    err.to_string().into()
+}
+
+// Rhai doesn't turn integers into floats, so numbers are taken as either
+fn number(value: &Dynamic) -> Option<f64> {
+    value.as_float().ok().or_else(|| value.as_int().ok().map(|value| value as f64))
+}
+
+// Whole numbers only, `as` would quietly round them or turn -90 into 0
+fn whole(value: f64) -> Option<i64> {
+    (value.fract() == 0.0 && value >= i32::MIN as f64 && value <= i32::MAX as f64).then_some(value as i64)
+}
+
+fn count(value: f64) -> Option<u32> {
+    whole(value).and_then(|value| u32::try_from(value).ok())
+}
+
+fn param_value(kind: &ParamKind, value: &Dynamic) -> Option<ParamValue> {
+    match kind {
+        ParamKind::Float { .. } => number(value).map(|value| ParamValue::Float(value as f32)),
+        ParamKind::Int { .. } => value.as_int().ok().map(ParamValue::Int),
+        ParamKind::Bool { .. } => value.as_bool().ok().map(ParamValue::Bool),
+        // Options can be picked by position or by name
+        ParamKind::Choice { options, .. } => match value.clone().into_string() {
+            Ok(name) => options.iter().position(|option| *option == name).map(ParamValue::Choice),
+            Err(_) => value.as_int().ok().and_then(|index| usize::try_from(index).ok()).map(ParamValue::Choice),
+        },
+    }
+}
+
+fn engine(fonts: Rc<Vec<FontEntry>>, operations: Registry, log: Rc<RefCell<Vec<String>>>, files: FileAccess) -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+    engine.on_print(move |text| log.borrow_mut().push(text.to_string()));
+
+    engine.register_type_with_name::<ScriptImage>("Image");
+    engine.register_get("width", |image: &mut ScriptImage| image.0.width() as i64);
+    engine.register_get("height", |image: &mut ScriptImage| image.0.height() as i64);
+
+    // Every edit goes through the checked operations, so bad values stop the script with a message
+    let register_adjustment = |engine: &mut Engine, name: &'static str, make: fn(f64) -> Option<Adjustment>| {
+        let (fonts, operations) = (fonts.clone(), operations.clone());
+        engine.register_fn(name, move |image: &mut ScriptImage, value: Dynamic| -> ScriptResult<()> {
+            let adjustment = number(&value)
+                .and_then(make)
+                .ok_or_else(|| script_error(format!("{} can't take {}", name, value)))?;
+            image.0 = ops::apply_adjustment(image.0.clone(), &adjustment, &fonts, &operations).map_err(script_error)?;
+            Ok(())
+        });
+    };
+    register_adjustment(&mut engine, "blur", |sigma| Some(Adjustment::Blur { sigma: sigma as f32 }));
+    register_adjustment(&mut engine, "brighten", |value| whole(value).map(|value| Adjustment::Brighten { value: value as i32 }));
+    register_adjustment(&mut engine, "contrast", |value| Some(Adjustment::Contrast { value: value as f32 }));
+    register_adjustment(&mut engine, "rotate", |degrees| count(degrees).map(|degrees| Adjustment::Rotate { degrees }));
+    register_adjustment(&mut engine, "sepia", |intensity| Some(Adjustment::Sepia { intensity: intensity as f32 }));
+    register_adjustment(&mut engine, "posterize", |levels| count(levels).map(|levels| Adjustment::Posterize { levels }));
+    register_adjustment(&mut engine, "pixelate", |block_size| count(block_size).map(|block_size| Adjustment::Pixelate { block_size }));
+    engine.register_fn("grayscale", |image: &mut ScriptImage| image.0 = image.0.grayscale());
+    engine.register_fn("invert", |image: &mut ScriptImage| image.0.invert());
+    engine.register_fn("flip_horizontal", |image: &mut ScriptImage| image.0 = image.0.fliph());
+    engine.register_fn("flip_vertical", |image: &mut ScriptImage| image.0 = image.0.flipv());
+    engine.register_fn("crop", |image: &mut ScriptImage, x: i64, y: i64, width: i64, height: i64| -> ScriptResult<()> {
+        let inside = x >= 0 && y >= 0 && width > 0 && height > 0
+            && x + width <= image.0.width() as i64 && y + height <= image.0.height() as i64;
+        if !inside {
+            return Err(script_error(format!(
+                "crop {}x{} at ({}, {}) is outside the {}x{} image",
+                width, height, x, y, image.0.width(), image.0.height(),
+            )));
+        }
+        image.0 = image.0.crop_imm(x as u32, y as u32, width as u32, height as u32);
+        Ok(())
+    });
+    engine.register_fn("resize", |image: &mut ScriptImage, width: i64, height: i64| -> ScriptResult<()> {
+        if width <= 0 || height <= 0 || width.saturating_mul(height) > MAX_PIXELS {
+            return Err(script_error(format!("can't resize to {}x{}", width, height)));
+        }
+        image.0 = image.0.resize_exact(width as u32, height as u32, FilterType::Lanczos3);
+        Ok(())
+    });
+
+    // Filters of the registry, with their values in the order of their parameters
+    let (filter_fonts, filter_operations) = (fonts.clone(), operations.clone());
+    engine.register_fn("filter", move |image: &mut ScriptImage, name: &str, values: Array| -> ScriptResult<()> {
+        let operation = filter_operations.find(name).ok_or_else(|| script_error(Error::UnknownOperation(name.to_string())))?;
+        let specs = operation.params();
+        if specs.len() != values.len() {
+            return Err(script_error(format!("{} takes {} values, got {}", name, specs.len(), values.len())));
+        }
+        let values = specs
+            .iter()
+            .zip(&values)
+            .map(|(spec, value)| param_value(&spec.kind, value).ok_or_else(|| script_error(format!("wrong type for {}", spec.name))))
+            .collect::<ScriptResult<Vec<ParamValue>>>()?;
+        let adjustment = Adjustment::Operation { name: name.to_string(), values };
+        image.0 = ops::apply_adjustment(image.0.clone(), &adjustment, &filter_fonts, &filter_operations).map_err(script_error)?;
+        Ok(())
+    });
+    if files == FileAccess::Allowed {
+        register_files(&mut engine, fonts, operations);
+    }
+    engine
+}
+
+fn register_files(engine: &mut Engine, fonts: Rc<Vec<FontEntry>>, operations: Registry) {
+    engine.register_fn("open", |path: &str| -> ScriptResult<ScriptImage> {
+        image::open(path).map(ScriptImage).map_err(|err| script_error(format!("Could not open {}: {}", path, err)))
+    });
+    engine.register_fn("save", |image: &mut ScriptImage, path: &str| -> ScriptResult<()> {
+        transparency::save_with_alpha(&image.0, Path::new(path), SAVE_QUALITY)
+            .map_err(|err| script_error(format!("Could not write {}: {}", path, err)))
+    });
+    engine.register_fn("save", |image: &mut ScriptImage, path: &str, quality: i64| -> ScriptResult<()> {
+        transparency::save_with_alpha(&image.0, Path::new(path), quality.clamp(1, 100) as u8)
+            .map_err(|err| script_error(format!("Could not write {}: {}", path, err)))
+    });
+    engine.register_fn("apply_preset", move |image: &mut ScriptImage, path: &str| -> ScriptResult<()> {
+        let preset = preset::load(Path::new(path)).map_err(script_error)?;
+        image.0 = ops::apply_preset(image.0.clone(), &preset, &fonts, &operations).map_err(script_error)?;
+        Ok(())
+    });
+}
+
+// Run the script on the image, if there is one. What `print` writes goes to the log,
+// even when the script fails later on. The result is the image left in `image`
+pub fn run(
+    source: &str,
+    image: Option<DynamicImage>,
+    fonts: &[FontEntry],
+    operations: &Registry,
+    files: FileAccess,
+    log: &mut Vec<String>,
+) -> Result<Option<DynamicImage>> {
+    let printed = Rc::new(RefCell::new(Vec::new()));
+    let engine = engine(Rc::new(fonts.to_vec()), operations.clone(), printed.clone(), files);
+    let mut scope = Scope::new();
+    match image {
+        Some(image) => scope.push(IMAGE_VARIABLE, ScriptImage(image)),
+        None => scope.push(IMAGE_VARIABLE, ()),
+    };
+    let result = engine.run_with_scope(&mut scope, source);
+    log.append(&mut printed.borrow_mut());
+    result.map_err(|err| Error::Failed { operation: String::from("Script"), reason: err.to_string() })?;
+    Ok(scope.get_value::<ScriptImage>(IMAGE_VARIABLE).map(|image| image.0))
+}
+
+// Scripts are kept in the presets folder, next to the presets
+pub fn load_all(dir: &Path) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case(SCRIPT_EXTENSION)))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    paths.sort();
+    paths
+}
+
+// Command line run: the input, if any, is the image of the script, and the result is
+// written to the output. The log is printed as it would show in the console
+pub fn run_file(path: &Path, input: Option<&Path>, output: Option<&Path>, operations: &Registry) -> io::Result<()> {
+    let source = fs::read_to_string(path)?;
+    let image = match input {
+        Some(input) => Some(image::open(input).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("Could not open {}: {}", input.display(), err)))?),
+        None => None,
+    };
+    let mut log = Vec::new();
+    let result = run(&source, image, &crate::text::available_fonts(), operations, FileAccess::Allowed, &mut log);
+    for line in log {
+        println!("{}", line);
+    }
+    let image = result.map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+    match (image, output) {
+        (Some(image), Some(output)) => transparency::save_with_alpha(&image, output, SAVE_QUALITY)
+            .map_err(|err| io::Error::other(format!("Could not write {}: {}", output.display(), err))),
+        (None, Some(_)) => Err(io::Error::new(io::ErrorKind::InvalidData, "The script left no image to write")),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+    use tempfile::tempdir;
+    use super::*;
+
+    fn image() -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_pixel(4, 4, Rgba([10, 20, 30, 255])))
+    }
+
+    fn run_with(source: &str, files: FileAccess) -> Result<Option<DynamicImage>> {
+        run(source, Some(image()), &[], &Registry::with_builtins(), files, &mut Vec::new())
+    }
+
+    #[test]
+    fn replays_can_not_touch_files() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("out.png");
+        let save = format!("save(image, {:?});", path.display().to_string());
+        assert!(run_with(&save, FileAccess::Denied).is_err());
+        assert!(!path.exists());
+        assert!(run_with(&format!("image = open({:?});", path.display().to_string()), FileAccess::Denied).is_err());
+        assert!(run_with("apply_preset(image, \"preset.json\");", FileAccess::Denied).is_err());
+
+        run_with(&save, FileAccess::Allowed).unwrap();
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn edits_go_through_the_checked_operations() {
+        let out = run_with("image.invert(); image.filter(\"Rotate\", [\"90°\"]);", FileAccess::Denied).unwrap().unwrap();
+        assert_eq!(out.to_rgba8().get_pixel(0, 0), &Rgba([245, 235, 225, 255]));
+        assert!(run_with("image.posterize(-3);", FileAccess::Denied).is_err());
+    }
+}
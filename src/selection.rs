@@ -0,0 +1,205 @@
+use image::{DynamicImage, GrayImage, Luma, Rgba, RgbaImage};
+use image::imageops;
+
+// Largest possible distance between two RGB colors
+const MAX_COLOR_DISTANCE: f32 = 441.673;
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum SelectionTool {
+    Rectangle,
+    Ellipse,
+    Lasso,
+    MagicWand,
+}
+
+impl SelectionTool {
+    pub const ALL: [SelectionTool; 4] = [SelectionTool::Rectangle, SelectionTool::Ellipse, SelectionTool::Lasso, SelectionTool::MagicWand];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SelectionTool::Rectangle => "Rectangle",
+            SelectionTool::Ellipse => "Ellipse",
+            SelectionTool::Lasso => "Lasso",
+            SelectionTool::MagicWand => "Magic Wand",
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum SelectionMode {
+    Replace,
+    Add,
+    Subtract,
+    Intersect,
+}
+
+impl SelectionMode {
+    pub const ALL: [SelectionMode; 4] = [SelectionMode::Replace, SelectionMode::Add, SelectionMode::Subtract, SelectionMode::Intersect];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SelectionMode::Replace => "Replace",
+            SelectionMode::Add => "Add",
+            SelectionMode::Subtract => "Subtract",
+            SelectionMode::Intersect => "Intersect",
+        }
+    }
+}
+
+// Coverage mask with the size of the image: 0 is outside, 255 is fully selected
+#[derive(Clone)]
+pub struct Selection {
+    mask: GrayImage,
+}
+
+impl Selection {
+    pub fn all(width: u32, height: u32) -> Self {
+        Self { mask: GrayImage::from_pixel(width, height, Luma([255])) }
+    }
+
+    pub fn from_mask(mask: GrayImage) -> Self {
+        Self { mask }
+    }
+
+    pub fn mask(&self) -> &GrayImage {
+        &self.mask
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        self.mask.dimensions()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mask.pixels().all(|px| px[0] == 0)
+    }
+
+    // Merge a new shape into the selection. Without a previous selection the whole image counts as selected
+    pub fn combine(selection: Option<Selection>, shape: GrayImage, mode: SelectionMode) -> Selection {
+        let mut mask = match (selection, mode) {
+            (_, SelectionMode::Replace) | (None, SelectionMode::Add) | (None, SelectionMode::Intersect) => return Selection { mask: shape },
+            (None, SelectionMode::Subtract) => Selection::all(shape.width(), shape.height()).mask,
+            (Some(selection), _) => selection.mask,
+        };
+        for (px, shape_px) in mask.pixels_mut().zip(shape.pixels()) {
+            let (a, b) = (px[0], shape_px[0]);
+            px[0] = match mode {
+                SelectionMode::Add => a.max(b),
+                SelectionMode::Subtract => a.min(255 - b),
+                SelectionMode::Intersect => a.min(b),
+                SelectionMode::Replace => b,
+            };
+        }
+        Selection { mask }
+    }
+
+    pub fn inverted(&self) -> Selection {
+        let mut mask = self.mask.clone();
+        imageops::invert(&mut mask);
+        Selection { mask }
+    }
+
+    // Soften the selection edges with a gaussian blur
+    pub fn feathered(&self, radius: f32) -> GrayImage {
+        if radius <= 0.0 {
+            return self.mask.clone();
+        }
+        imageops::blur(&self.mask, radius)
+    }
+}
+
+pub fn rectangle_mask(width: u32, height: u32, a: (f32, f32), b: (f32, f32)) -> GrayImage {
+    let (x0, x1) = (a.0.min(b.0), a.0.max(b.0));
+    let (y0, y1) = (a.1.min(b.1), a.1.max(b.1));
+    GrayImage::from_fn(width, height, |x, y| {
+        let (cx, cy) = (x as f32 + 0.5, y as f32 + 0.5);
+        let inside = cx >= x0 && cx <= x1 && cy >= y0 && cy <= y1;
+        Luma([if inside { 255 } else { 0 }])
+    })
+}
+
+// Ellipse inscribed in the rectangle defined by the two corners
+pub fn ellipse_mask(width: u32, height: u32, a: (f32, f32), b: (f32, f32)) -> GrayImage {
+    let (cx, cy) = ((a.0 + b.0) / 2.0, (a.1 + b.1) / 2.0);
+    let (rx, ry) = (((a.0 - b.0) / 2.0).abs().max(0.5), ((a.1 - b.1) / 2.0).abs().max(0.5));
+    GrayImage::from_fn(width, height, |x, y| {
+        let dx = (x as f32 + 0.5 - cx) / rx;
+        let dy = (y as f32 + 0.5 - cy) / ry;
+        Luma([if dx * dx + dy * dy <= 1.0 { 255 } else { 0 }])
+    })
+}
+
+// Polygon fill using the even-odd rule on each row of pixel centers
+pub fn lasso_mask(width: u32, height: u32, points: &[(f32, f32)]) -> GrayImage {
+    let mut mask = GrayImage::new(width, height);
+    if points.len() < 3 {
+        return mask;
+    }
+    for y in 0..height {
+        let cy = y as f32 + 0.5;
+        let mut crossings: Vec<f32> = Vec::new();
+        for i in 0..points.len() {
+            let (p, q) = (points[i], points[(i + 1) % points.len()]);
+            if (p.1 <= cy) != (q.1 <= cy) {
+                crossings.push(p.0 + (cy - p.1) / (q.1 - p.1) * (q.0 - p.0));
+            }
+        }
+        crossings.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        for span in crossings.chunks(2) {
+            if span.len() < 2 {
+                break;
+            }
+            let start = (span[0] - 0.5).ceil().max(0.0) as u32;
+            let end = ((span[1] - 0.5).floor() + 1.0).clamp(0.0, width as f32) as u32;
+            for x in start..end {
+                mask.put_pixel(x, y, Luma([255]));
+            }
+        }
+    }
+    mask
+}
+
+// Flood fill from the seed over the pixels with a similar color. Tolerance goes from 0 to 100
+pub fn magic_wand_mask(img: &DynamicImage, seed: (u32, u32), tolerance: f32) -> GrayImage {
+    let rgba = img.to_rgba8();
+    let (w, h) = rgba.dimensions();
+    let mut mask = GrayImage::new(w, h);
+    if seed.0 >= w || seed.1 >= h {
+        return mask;
+    }
+    let max_distance = (tolerance / 100.0).clamp(0.0, 1.0) * MAX_COLOR_DISTANCE;
+    let target = *rgba.get_pixel(seed.0, seed.1);
+    let similar = |px: &Rgba<u8>| {
+        (0..3).map(|c| (px[c] as f32 - target[c] as f32).powi(2)).sum::<f32>().sqrt() <= max_distance
+    };
+
+    let mut stack = vec![seed];
+    mask.put_pixel(seed.0, seed.1, Luma([255]));
+    while let Some((x, y)) = stack.pop() {
+        let neighbours = [
+            (x.wrapping_sub(1), y),
+            (x + 1, y),
+            (x, y.wrapping_sub(1)),
+            (x, y + 1),
+        ];
+        for (nx, ny) in neighbours {
+            if nx < w && ny < h && mask.get_pixel(nx, ny)[0] == 0 && similar(rgba.get_pixel(nx, ny)) {
+                mask.put_pixel(nx, ny, Luma([255]));
+                stack.push((nx, ny));
+            }
+        }
+    }
+    mask
+}
+
+// Mix the edited image over the original using the mask as weight
+pub fn blend_masked(original: &DynamicImage, edited: &DynamicImage, mask: &GrayImage) -> DynamicImage {
+    let original = original.to_rgba8();
+    let edited = edited.to_rgba8();
+    let out = RgbaImage::from_fn(original.width(), original.height(), |x, y| {
+        let weight = mask.get_pixel(x, y)[0] as f32 / 255.0;
+        let (a, b) = (original.get_pixel(x, y), edited.get_pixel(x, y));
+        let mix = |c: usize| (a[c] as f32 + (b[c] as f32 - a[c] as f32) * weight).round() as u8;
+        Rgba([mix(0), mix(1), mix(2), mix(3)])
+    });
+    DynamicImage::ImageRgba8(out)
+}
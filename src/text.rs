@@ -0,0 +1,359 @@
+use std::borrow::Cow;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use ab_glyph::{point, Font, FontRef, PxScale, ScaleFont};
+#[cfg(feature = "gui")]
+use epaint::text::FontDefinitions;
+use image::{Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+// Fonts shipped with egui, only there when the editor window is built
+const BUNDLED_FONTS: [&str; 2] = ["Ubuntu-Light", "Hack"];
+// How deep the font directories are searched
+const MAX_FONT_DIR_DEPTH: usize = 4;
+pub const MAX_TEXT_SIZE: f32 = 400.0;
+pub const MAX_OUTLINE_WIDTH: f32 = 20.0;
+// A long enough text makes a box of any size, past this it is not drawn
+const MAX_BOX_PIXELS: u64 = 1 << 26;
+
+#[derive(Clone)]
+pub struct FontEntry {
+    pub name: String,
+    source: FontSource,
+}
+
+#[derive(Clone)]
+enum FontSource {
+    Bundled(Cow<'static, [u8]>),
+    File(PathBuf),
+}
+
+impl FontEntry {
+    pub fn data(&self) -> Option<Cow<'static, [u8]>> {
+        match &self.source {
+            FontSource::Bundled(data) => Some(data.clone()),
+            FontSource::File(path) => fs::read(path).ok().map(Cow::Owned),
+        }
+    }
+}
+
+// Bundled fonts first, then every TTF/OTF file found in the usual system folders
+pub fn available_fonts() -> Vec<FontEntry> {
+    let mut fonts: Vec<FontEntry> = BUNDLED_FONTS
+        .iter()
+        .filter_map(|name| bundled_font(name).map(|data| FontEntry {
+            name: name.to_string(),
+            source: FontSource::Bundled(data),
+        }))
+        .collect();
+
+    let mut system_fonts = Vec::new();
+    for dir in font_dirs() {
+        collect_font_files(&dir, 0, &mut system_fonts);
+    }
+    system_fonts.sort_by(|a, b| a.name.cmp(&b.name));
+    system_fonts.dedup_by(|a, b| a.name == b.name);
+    fonts.extend(system_fonts);
+    fonts
+}
+
+#[cfg(feature = "gui")]
+fn bundled_font(name: &str) -> Option<Cow<'static, [u8]>> {
+    FontDefinitions::default().font_data.remove(name).map(|data| data.font)
+}
+
+#[cfg(not(feature = "gui"))]
+fn bundled_font(_name: &str) -> Option<Cow<'static, [u8]>> {
+    None
+}
+
+// Data of the first bundled font, used where there is no font to pick.
+// Without the bundled fonts it is the first one found on the system
+pub fn default_font_data() -> Option<Cow<'static, [u8]>> {
+    bundled_font(BUNDLED_FONTS[0]).or_else(|| available_fonts().first().and_then(FontEntry::data))
+}
+
+// Data of the font with that name, falling back to the first available one
+pub fn font_data(fonts: &[FontEntry], name: &str) -> Option<Cow<'static, [u8]>> {
+    match fonts.iter().find(|font| font.name == name).or_else(|| fonts.first()) {
+        Some(font) => font.data(),
+        None => default_font_data(),
+    }
+}
+
+fn font_dirs() -> Vec<PathBuf> {
+    let mut dirs = vec![
+        PathBuf::from("/usr/share/fonts"),
+        PathBuf::from("/usr/local/share/fonts"),
+        PathBuf::from("/System/Library/Fonts"),
+        PathBuf::from("/Library/Fonts"),
+    ];
+    if let Some(home) = env::var_os("HOME") {
+        let home = PathBuf::from(home);
+        dirs.push(home.join(".fonts"));
+        dirs.push(home.join(".local/share/fonts"));
+        dirs.push(home.join("Library/Fonts"));
+    }
+    if let Some(windir) = env::var_os("WINDIR") {
+        dirs.push(PathBuf::from(windir).join("Fonts"));
+    }
+    dirs
+}
+
+fn collect_font_files(dir: &Path, depth: usize, fonts: &mut Vec<FontEntry>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            if depth < MAX_FONT_DIR_DEPTH {
+                collect_font_files(&path, depth + 1, fonts);
+            }
+            continue;
+        }
+        let extension = path.extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase());
+        if let (Some("ttf" | "otf"), Some(name)) = (extension.as_deref(), path.file_stem().and_then(|n| n.to_str())) {
+            fonts.push(FontEntry {
+                name: name.to_string(),
+                source: FontSource::File(path.clone()),
+            });
+        }
+    }
+}
+
+// Text placed over the image. Position is the top left corner before rotation,
+// rotation is in degrees around the center of the box
+#[derive(PartialEq, Clone, Serialize, Deserialize)]
+pub struct TextBox {
+    pub text: String,
+    pub font: String,
+    pub position: (f32, f32),
+    pub size: f32,
+    pub color: [u8; 3],
+    pub outline_width: f32,
+    pub outline_color: [u8; 3],
+    pub background: bool,
+    pub background_color: [u8; 4],
+    pub rotation: f32,
+}
+
+impl TextBox {
+    pub fn new(position: (f32, f32)) -> Self {
+        Self {
+            text: String::from("Text"),
+            font: String::from(BUNDLED_FONTS[0]),
+            position,
+            size: 48.0,
+            color: [255, 255, 255],
+            outline_width: 2.0,
+            outline_color: [0, 0, 0],
+            background: false,
+            background_color: [0, 0, 0, 160],
+            rotation: 0.0,
+        }
+    }
+
+    fn padding(&self) -> f32 {
+        let background_padding = if self.background { self.size * 0.2 } else { 0.0 };
+        self.outline_width.max(0.0).ceil() + background_padding
+    }
+
+    // Size of the box in pixels, including outline and background padding
+    pub fn measure(&self, font_data: &[u8]) -> (f32, f32) {
+        let font = match FontRef::try_from_slice(font_data) {
+            Ok(font) => font,
+            Err(_) => return (0.0, 0.0),
+        };
+        let (width, height, _) = layout(&self.text, &font.as_scaled(PxScale::from(self.size)));
+        let padding = self.padding();
+        (width + 2.0 * padding, height + 2.0 * padding)
+    }
+
+    // Corners of the rotated box in image coordinates
+    pub fn corners(&self, font_data: &[u8]) -> [(f32, f32); 4] {
+        let (w, h) = self.measure(font_data);
+        let center = (self.position.0 + w / 2.0, self.position.1 + h / 2.0);
+        let (sin, cos) = self.rotation.to_radians().sin_cos();
+        [(-w, -h), (w, -h), (w, h), (-w, h)].map(|(dx, dy)| {
+            let (dx, dy) = (dx / 2.0, dy / 2.0);
+            (center.0 + dx * cos - dy * sin, center.1 + dx * sin + dy * cos)
+        })
+    }
+
+    pub fn contains(&self, font_data: &[u8], point: (f32, f32)) -> bool {
+        let (w, h) = self.measure(font_data);
+        let center = (self.position.0 + w / 2.0, self.position.1 + h / 2.0);
+        let (sin, cos) = (-self.rotation.to_radians()).sin_cos();
+        let (dx, dy) = (point.0 - center.0, point.1 - center.1);
+        let (x, y) = (dx * cos - dy * sin, dx * sin + dy * cos);
+        x.abs() <= w / 2.0 && y.abs() <= h / 2.0
+    }
+}
+
+// Glyph positions of every line, plus the total width and height
+fn layout<F: Font>(text: &str, font: &ab_glyph::PxScaleFont<F>) -> (f32, f32, Vec<(ab_glyph::GlyphId, f32, f32)>) {
+    let line_height = font.height() + font.line_gap();
+    let mut glyphs = Vec::new();
+    let mut width = 0.0f32;
+    let lines: Vec<&str> = text.split('\n').collect();
+    for (i, line) in lines.iter().enumerate() {
+        let baseline = i as f32 * line_height + font.ascent();
+        let mut x = 0.0;
+        let mut previous = None;
+        for c in line.chars() {
+            let id = font.glyph_id(c);
+            if let Some(previous) = previous {
+                x += font.kern(previous, id);
+            }
+            glyphs.push((id, x, baseline));
+            x += font.h_advance(id);
+            previous = Some(id);
+        }
+        width = width.max(x);
+    }
+    let height = lines.len() as f32 * line_height - font.line_gap();
+    (width, height, glyphs)
+}
+
+// Draw the box (background, outline and fill) into its own unrotated layer
+fn rasterize(text_box: &TextBox, font_data: &[u8]) -> Option<RgbaImage> {
+    let font = FontRef::try_from_slice(font_data).ok()?;
+    let scale = PxScale::from(text_box.size);
+    let scaled = font.as_scaled(scale);
+    let (width, height, glyphs) = layout(&text_box.text, &scaled);
+    let padding = text_box.padding();
+    let (w, h) = ((width + 2.0 * padding).ceil() as u32, (height + 2.0 * padding).ceil() as u32);
+    if w == 0 || h == 0 || w as u64 * h as u64 > MAX_BOX_PIXELS {
+        return None;
+    }
+
+    let mut fill = vec![0.0f32; (w * h) as usize];
+    for (id, x, baseline) in glyphs {
+        let glyph = id.with_scale_and_position(scale, point(x + padding, baseline + padding));
+        if let Some(outlined) = font.outline_glyph(glyph) {
+            let bounds = outlined.px_bounds();
+            outlined.draw(|gx, gy, coverage| {
+                let (px, py) = (bounds.min.x as i64 + gx as i64, bounds.min.y as i64 + gy as i64);
+                if px >= 0 && py >= 0 && (px as u32) < w && (py as u32) < h {
+                    let index = (py as u32 * w + px as u32) as usize;
+                    fill[index] = fill[index].max(coverage);
+                }
+            });
+        }
+    }
+
+    let outline = dilate(&fill, w, h, text_box.outline_width);
+    let mut layer = RgbaImage::new(w, h);
+    for (i, px) in layer.pixels_mut().enumerate() {
+        if text_box.background {
+            *px = Rgba(text_box.background_color);
+        }
+        if text_box.outline_width > 0.0 {
+            *px = over(*px, text_box.outline_color, outline[i]);
+        }
+        *px = over(*px, text_box.color, fill[i]);
+    }
+    Some(layer)
+}
+
+// Grow the coverage by the radius, used to draw the outline around the glyphs
+fn dilate(coverage: &[f32], w: u32, h: u32, radius: f32) -> Vec<f32> {
+    if radius <= 0.0 {
+        return coverage.to_vec();
+    }
+    let reach = radius.ceil() as i64;
+    let mut out = vec![0.0f32; coverage.len()];
+    for y in 0..h as i64 {
+        for x in 0..w as i64 {
+            let mut value = 0.0f32;
+            for dy in -reach..=reach {
+                for dx in -reach..=reach {
+                    let (nx, ny) = (x + dx, y + dy);
+                    if nx < 0 || ny < 0 || nx >= w as i64 || ny >= h as i64 {
+                        continue;
+                    }
+                    let distance = ((dx * dx + dy * dy) as f32).sqrt();
+                    let weight = (radius - distance + 0.5).clamp(0.0, 1.0);
+                    value = value.max(coverage[(ny * w as i64 + nx) as usize] * weight);
+                }
+            }
+            out[(y * w as i64 + x) as usize] = value;
+        }
+    }
+    out
+}
+
+// Composite a color with the given coverage over a pixel
+fn over(base: Rgba<u8>, color: [u8; 3], coverage: f32) -> Rgba<u8> {
+    over_rgba(base, Rgba([color[0], color[1], color[2], 255]), coverage)
+}
+
+pub fn over_rgba(base: Rgba<u8>, top: Rgba<u8>, coverage: f32) -> Rgba<u8> {
+    let top_alpha = top[3] as f32 / 255.0 * coverage;
+    if top_alpha <= 0.0 {
+        return base;
+    }
+    let base_alpha = base[3] as f32 / 255.0;
+    let alpha = top_alpha + base_alpha * (1.0 - top_alpha);
+    let mix = |c: usize| ((top[c] as f32 * top_alpha + base[c] as f32 * base_alpha * (1.0 - top_alpha)) / alpha).round() as u8;
+    Rgba([mix(0), mix(1), mix(2), (alpha * 255.0).round() as u8])
+}
+
+// Draw the text box over the image, rotating its layer around the center
+pub fn render_text_box(img: &mut RgbaImage, text_box: &TextBox, font_data: &[u8]) {
+    let layer = match rasterize(text_box, font_data) {
+        Some(layer) => layer,
+        None => return,
+    };
+    let (lw, lh) = (layer.width() as f32, layer.height() as f32);
+    let center = (text_box.position.0 + lw / 2.0, text_box.position.1 + lh / 2.0);
+    let corners = text_box.corners(font_data);
+    let min_x = corners.iter().map(|c| c.0).fold(f32::MAX, f32::min).floor().max(0.0) as u32;
+    let min_y = corners.iter().map(|c| c.1).fold(f32::MAX, f32::min).floor().max(0.0) as u32;
+    let max_x = (corners.iter().map(|c| c.0).fold(f32::MIN, f32::max).ceil().max(0.0) as u32).min(img.width());
+    let max_y = (corners.iter().map(|c| c.1).fold(f32::MIN, f32::max).ceil().max(0.0) as u32).min(img.height());
+    let (sin, cos) = (-text_box.rotation.to_radians()).sin_cos();
+
+    for y in min_y..max_y {
+        for x in min_x..max_x {
+            let (dx, dy) = (x as f32 + 0.5 - center.0, y as f32 + 0.5 - center.1);
+            let u = dx * cos - dy * sin + lw / 2.0;
+            let v = dx * sin + dy * cos + lh / 2.0;
+            if let Some(px) = sample_bilinear(&layer, u, v) {
+                let base = *img.get_pixel(x, y);
+                img.put_pixel(x, y, over_rgba(base, px, 1.0));
+            }
+        }
+    }
+}
+
+// Bilinear sample with premultiplied alpha so the edges don't get dark fringes
+fn sample_bilinear(layer: &RgbaImage, u: f32, v: f32) -> Option<Rgba<u8>> {
+    let (w, h) = (layer.width() as i64, layer.height() as i64);
+    let (fx, fy) = (u - 0.5, v - 0.5);
+    if fx < -1.0 || fy < -1.0 || fx >= w as f32 || fy >= h as f32 {
+        return None;
+    }
+    let (x0, y0) = (fx.floor() as i64, fy.floor() as i64);
+    let (tx, ty) = (fx - x0 as f32, fy - y0 as f32);
+    let mut sum = [0.0f32; 4];
+    for (sx, sy, weight) in [(x0, y0, (1.0 - tx) * (1.0 - ty)), (x0 + 1, y0, tx * (1.0 - ty)), (x0, y0 + 1, (1.0 - tx) * ty), (x0 + 1, y0 + 1, tx * ty)] {
+        if sx < 0 || sy < 0 || sx >= w || sy >= h {
+            continue;
+        }
+        let px = layer.get_pixel(sx as u32, sy as u32);
+        let alpha = px[3] as f32 / 255.0;
+        for c in 0..3 {
+            sum[c] += px[c] as f32 * alpha * weight;
+        }
+        sum[3] += alpha * weight;
+    }
+    if sum[3] <= 0.0 {
+        return None;
+    }
+    let color = |c: usize| (sum[c] / sum[3]).round().clamp(0.0, 255.0) as u8;
+    Some(Rgba([color(0), color(1), color(2), (sum[3] * 255.0).round() as u8]))
+}
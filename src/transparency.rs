@@ -0,0 +1,96 @@
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::Path;
+use image::{DynamicImage, ImageError, ImageResult};
+use image::codecs::jpeg::JpegEncoder;
+use serde::{Deserialize, Serialize};
+
+// Largest possible distance between two RGB colors
+const MAX_COLOR_DISTANCE: f32 = 441.673;
+
+#[derive(PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum TransparencyOperation {
+    Opacity,
+    RemoveAlpha,
+    ChromaKey,
+}
+
+impl TransparencyOperation {
+    pub const ALL: [TransparencyOperation; 3] = [TransparencyOperation::Opacity, TransparencyOperation::RemoveAlpha, TransparencyOperation::ChromaKey];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TransparencyOperation::Opacity => "Opacity",
+            TransparencyOperation::RemoveAlpha => "Remove Alpha",
+            TransparencyOperation::ChromaKey => "Make Color Transparent",
+        }
+    }
+}
+
+// Opacity goes from 0 (fully transparent) to 100 (unchanged)
+pub fn set_opacity(img: &DynamicImage, opacity: f32) -> DynamicImage {
+    let factor = (opacity / 100.0).clamp(0.0, 1.0);
+    let mut out = img.to_rgba8();
+    for px in out.pixels_mut() {
+        px[3] = (px[3] as f32 * factor).round() as u8;
+    }
+    DynamicImage::ImageRgba8(out)
+}
+
+// Composite the image over a solid color, the result is fully opaque
+pub fn remove_alpha(img: &DynamicImage, background: [u8; 3]) -> DynamicImage {
+    let mut out = img.to_rgba8();
+    for px in out.pixels_mut() {
+        let alpha = px[3] as f32 / 255.0;
+        for c in 0..3 {
+            px[c] = (px[c] as f32 * alpha + background[c] as f32 * (1.0 - alpha)).round() as u8;
+        }
+        px[3] = 255;
+    }
+    DynamicImage::ImageRgba8(out)
+}
+
+// Pixels close to the key color become transparent. Tolerance goes from 0 to 100,
+// the last quarter of the tolerance range fades out instead of cutting hard
+pub fn chroma_key(img: &DynamicImage, key: [u8; 3], tolerance: f32) -> DynamicImage {
+    let cutoff = (tolerance / 100.0).clamp(0.0, 1.0) * MAX_COLOR_DISTANCE;
+    let soft = cutoff * 0.25;
+    let mut out = img.to_rgba8();
+    for px in out.pixels_mut() {
+        let distance = (0..3)
+            .map(|c| (px[c] as f32 - key[c] as f32).powi(2))
+            .sum::<f32>()
+            .sqrt();
+        if distance > cutoff {
+            continue;
+        }
+        let keep = if soft > 0.0 { ((distance - (cutoff - soft)) / soft).clamp(0.0, 1.0) } else { 0.0 };
+        px[3] = (px[3] as f32 * keep).round() as u8;
+    }
+    DynamicImage::ImageRgba8(out)
+}
+
+// Save keeping the alpha channel on formats that support it. JPEG has no alpha,
+// so the image is flattened onto white instead of leaking the hidden colors.
+// Quality goes from 1 to 100, WebP is lossless at 100
+pub fn save_with_alpha(img: &DynamicImage, path: &Path, quality: u8) -> ImageResult<()> {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "webp" => {
+            let rgba = img.to_rgba8();
+            let encoder = webp::Encoder::from_rgba(&rgba, rgba.width(), rgba.height());
+            let encoded = if quality >= 100 { encoder.encode_lossless() } else { encoder.encode(quality as f32) };
+            fs::write(path, &*encoded).map_err(ImageError::IoError)
+        },
+        "jpg" | "jpeg" => {
+            let rgb = remove_alpha(img, [255, 255, 255]).to_rgb8();
+            let mut file = BufWriter::new(File::create(path).map_err(ImageError::IoError)?);
+            JpegEncoder::new_with_quality(&mut file, quality.clamp(1, 100)).encode_image(&rgb)
+        },
+        _ => img.save(path),
+    }
+}